@@ -20,15 +20,6 @@
 #![allow(clippy::non_ascii_literal)]
 #![forbid(unsafe_code)]
 
-mod keyparse;
-mod parser;
-
-/// The version of keys supported
-pub const KEY_VERSION: u8 = 1;
-
-/// The magic number at the beginning of a secret key
-pub const KEY_MAGIC: &[u8] = &*b"Ledgeracio Secret Key";
-
 use ledgeracio::{get_network, Error, HardStore};
 use sp_core::crypto::AccountId32 as AccountId;
 use std::{fmt::Debug,
@@ -37,17 +28,24 @@ use std::{fmt::Debug,
 use structopt::StructOpt;
 use substrate_subxt::{sp_core, sp_core::crypto::Ss58AddressFormat};
 
-use ed25519_dalek::Keypair;
-use keyparse::{parse_public, parse_secret};
-use parser::parse as parse_allowlist;
+use ed25519_dalek::{Keypair, PublicKey, SecretKey};
+use ledgeracio::{keyparse::{decode_secret, fingerprint, parse_public, parse_secret, parse_seed,
+                            KEY_MAGIC, KEY_VERSION},
+                 parser::{check_signed, from_hex, merge, parse as parse_allowlist, signature,
+                          DEFAULT_MAX_ENTRIES}};
 use std::{fs::OpenOptions, io::Write, os::unix::fs::OpenOptionsExt, path::PathBuf};
 use substrate_subxt::sp_core::H256;
 
 async fn inner_main() -> Result<(), Error> {
     env_logger::init();
-    let LedgeracioAllowlist { network, cmd } = LedgeracioAllowlist::from_args();
+    let LedgeracioAllowlist {
+        network,
+        trace_apdu,
+        cmd,
+    } = LedgeracioAllowlist::from_args();
+    ledgeracio::trace_apdu(trace_apdu);
 
-    let keystore = || HardStore::new(network);
+    let keystore = || HardStore::new(network, None);
     really_inner_main(cmd, keystore, network).await?;
     Ok(())
 }
@@ -57,7 +55,7 @@ fn main() {
         Ok(()) => (),
         Err(e) => {
             eprintln!("Error: {}", e);
-            std::process::exit(1)
+            std::process::exit(ledgeracio::categorize(&e).exit_code())
         }
     }
 }
@@ -69,17 +67,36 @@ fn main() {
 )]
 struct LedgeracioAllowlist {
     /// Network
-    #[structopt(long, parse(try_from_str = get_network))]
+    #[structopt(long, env = "LEDGERACIO_NETWORK", parse(try_from_str = get_network))]
     network: Ss58AddressFormat,
+    /// Log every APDU exchanged with the Ledger device to stderr, in hex.
+    /// This is for debugging incompatibilities with an app.
+    #[structopt(long)]
+    trace_apdu: bool,
     /// Subcommand
     #[structopt(subcommand)]
     cmd: AllowlistCommand,
 }
 
+/// Print the fingerprint and network of a signing key, so that the key can be
+/// checked out-of-band
+fn print_key(key: &PublicKey, network: Ss58AddressFormat) {
+    println!(
+        "Key fingerprint: {}\nNetwork: {}",
+        fingerprint(key),
+        String::from(network)
+    )
+}
+
 #[derive(StructOpt, Debug)]
 pub(crate) enum AllowlistCommand {
     /// Upload a new approved validator list.  This list must be signed.
-    Upload { path: PathBuf },
+    Upload {
+        path: PathBuf,
+        /// The allowlist is hex-encoded, as written by `sign --hex`
+        #[structopt(long)]
+        hex: bool,
+    },
     /// Set the validator list signing key.  This will fail if a signing key has
     /// already been set.
     SetKey {
@@ -97,6 +114,16 @@ pub(crate) enum AllowlistCommand {
         /// The public key will be written to `file.pub` and the secret key
         /// to `file.sec`.
         file: PathBuf,
+        /// FOR TESTING ONLY.  Derive the key from this 32-byte hex seed instead
+        /// of generating it randomly.  Anyone who knows the seed can sign
+        /// allowlists, so never use this for keys that will be uploaded to a
+        /// real device.
+        #[structopt(long, parse(try_from_str = parse_seed))]
+        seed: Option<SecretKey>,
+        /// Store the secret key in the OS keychain under this id, instead of
+        /// writing `file.sec`.  Requires the `keychain` feature.
+        #[structopt(long, value_name = "ID")]
+        keychain: Option<String>,
     },
     /// Compile the provided textual allowlist into a binary format and sign it.
     ///
@@ -114,11 +141,29 @@ pub(crate) enum AllowlistCommand {
         /// trailing whitespace are ignored.  The process of compiling
         /// an allowlist to binary format and signing it is completely
         /// deterministic.
+        ///
+        /// Comments starting with `#meta:`, such as `#meta: owner alice`, are
+        /// metadata.  They are included in the signed binary allowlist and
+        /// shown by `inspect`, but are not uploaded to the device.
         #[structopt(short = "f", long = "file")]
         file: PathBuf,
         /// The secret key file.
-        #[structopt(short = "s", long = "secret")]
-        secret: PathBuf,
+        #[structopt(
+            short = "s",
+            long = "secret",
+            required_unless_one = &["secret-env", "keychain"],
+            conflicts_with_all = &["secret-env", "keychain"]
+        )]
+        secret: Option<PathBuf>,
+        /// Read the secret key from this environment variable instead of a
+        /// file.  The key must be encoded as hex or base64.
+        #[structopt(long = "secret-env", conflicts_with = "keychain")]
+        secret_env: Option<String>,
+        /// Read the secret key from the OS keychain, where `gen-key
+        /// --keychain` stored it under this id.  Requires the `keychain`
+        /// feature.
+        #[structopt(long, value_name = "ID")]
+        keychain: Option<String>,
         /// The output file
         #[structopt(short = "o", long = "output")]
         output: PathBuf,
@@ -126,6 +171,32 @@ pub(crate) enum AllowlistCommand {
         /// the same key, and is used to prevent replay attacks.
         #[structopt(short = "n", long = "nonce")]
         nonce: u32,
+        /// Refuse to sign an allowlist with more than this many addresses.
+        /// This catches corrupted or accidentally duplicated input files.
+        /// Defaults to the limit of the allowlist parser.
+        #[structopt(long)]
+        max_entries: Option<u32>,
+        /// Also write the 64-byte signature checked by the device to this
+        /// file
+        #[structopt(long)]
+        detached_sig: Option<PathBuf>,
+        /// Write the signed allowlist as a line of hex instead of binary, for
+        /// text-only channels.  `upload --hex` accepts this.
+        #[structopt(long)]
+        hex: bool,
+    },
+    /// Combine several textual allowlists into one, ready for `sign`.
+    ///
+    /// Every address is checked against `--network`, as `sign` does, and
+    /// duplicate addresses are removed.  Metadata comments are kept; other
+    /// comments are not.
+    Merge {
+        /// A textual allowlist file.  Give this once per list to merge.
+        #[structopt(short = "f", long = "file", required = true, number_of_values = 1)]
+        files: Vec<PathBuf>,
+        /// The output file
+        #[structopt(short = "o", long = "output")]
+        output: PathBuf,
     },
     /// Inspect the given allowlist file and verify its signature. The output is
     /// in a format suitable for `ledgeracio sign`.
@@ -139,7 +210,55 @@ pub(crate) enum AllowlistCommand {
         /// The output file.  Defaults to stdout.
         #[structopt(short = "o", long = "output")]
         output: Option<PathBuf>,
+        /// Only print the number of addresses, the nonce, the network, and the
+        /// fingerprint of the signing key.  The addresses are not decoded.
+        #[structopt(long)]
+        count: bool,
     },
+    /// Verify the signature of the given allowlist file, and print its nonce.
+    /// Unlike `inspect`, this does not decode or list the addresses.
+    Verify {
+        /// The binary allowlist file to read
+        #[structopt(short = "f", long = "file")]
+        file: PathBuf,
+        /// The public key file.
+        #[structopt(short = "p", long = "public")]
+        public: PathBuf,
+    },
+}
+
+/// The service under which secret keys are stored in the OS keychain
+#[cfg(feature = "keychain")]
+const KEYCHAIN_SERVICE: &str = "ledgeracio-allowlist";
+
+/// Store the secret key file contents `secret` in the OS keychain, as base64
+#[cfg(feature = "keychain")]
+fn keychain_store(id: &str, secret: &[u8]) -> Result<(), Error> {
+    keyring::Keyring::new(KEYCHAIN_SERVICE, id)
+        .set_password(&base64::encode(secret))
+        .map_err(|e| format!("Cannot store the secret key in the keychain: {}", e).into())
+}
+
+/// Load the secret key file contents stored under `id` in the OS keychain
+#[cfg(feature = "keychain")]
+fn keychain_load(id: &str) -> Result<Vec<u8>, Error> {
+    let encoded = keyring::Keyring::new(KEYCHAIN_SERVICE, id)
+        .get_password()
+        .map_err(|e| format!("Cannot read secret key {} from the keychain: {}", id, e))?;
+    decode_secret(&encoded)
+}
+
+#[cfg(not(feature = "keychain"))]
+fn keychain_store(_: &str, _: &[u8]) -> Result<(), Error> { Err(keychain_unsupported()) }
+
+#[cfg(not(feature = "keychain"))]
+fn keychain_load(_: &str) -> Result<Vec<u8>, Error> { Err(keychain_unsupported()) }
+
+#[cfg(not(feature = "keychain"))]
+fn keychain_unsupported() -> Error {
+    ledgeracio::input_error(
+        "--keychain requires ledgeracio-allowlist to be built with the `keychain` feature",
+    )
 }
 
 fn write(buf: &[&[u8]], path: &std::path::Path) -> std::io::Result<()> {
@@ -155,14 +274,17 @@ fn write(buf: &[&[u8]], path: &std::path::Path) -> std::io::Result<()> {
     Ok(())
 }
 
-async fn really_inner_main<T: FnOnce() -> Result<ledgeracio::HardStore, Error>>(
+async fn really_inner_main<
+    T: FnOnce() -> U,
+    U: std::future::Future<Output = Result<ledgeracio::HardStore, Error>>,
+>(
     acl: AllowlistCommand,
     hardware: T,
     network: Ss58AddressFormat,
 ) -> Result<Option<H256>, Error> {
     match acl {
         AllowlistCommand::GetKey => {
-            let s: [u8; 32] = hardware()?.get_pubkey().await?;
+            let s: [u8; 32] = hardware().await?.get_pubkey().await?;
             println!("Public key is {}", base64::encode(s));
         }
         AllowlistCommand::SetKey { key } => {
@@ -175,13 +297,24 @@ async fn really_inner_main<T: FnOnce() -> Result<ledgeracio::HardStore, Error>>(
                 )
                 .into())
             }
-            hardware()?.set_pubkey(&key.as_bytes()).await?
+            print_key(&key, network);
+            hardware().await?.set_pubkey(&key.as_bytes()).await?
         }
-        AllowlistCommand::Upload { path } => {
+        AllowlistCommand::Upload { path, hex } => {
             let allowlist = fs::read(path)?;
-            hardware()?.allowlist_upload(&allowlist).await?
+            let allowlist = if hex {
+                from_hex(&allowlist)?
+            } else {
+                allowlist
+            };
+            let allowlist = ledgeracio::parser::device_part(&allowlist)?;
+            hardware().await?.allowlist_upload(allowlist).await?
         }
-        AllowlistCommand::GenKey { mut file } => {
+        AllowlistCommand::GenKey {
+            mut file,
+            seed,
+            keychain,
+        } => {
             if file.extension().is_some() {
                 return Err(format!(
                     "please provide a filename with no extension, not {}",
@@ -189,7 +322,16 @@ async fn really_inner_main<T: FnOnce() -> Result<ledgeracio::HardStore, Error>>(
                 )
                 .into())
             }
-            let keypair = Keypair::generate(&mut rand::rngs::OsRng {});
+            let keypair = match seed {
+                Some(secret) => {
+                    eprintln!("Warning: deriving the key from a seed.  This is for testing only!");
+                    Keypair {
+                        public: (&secret).into(),
+                        secret,
+                    }
+                }
+                None => Keypair::generate(&mut rand::rngs::OsRng {}),
+            };
             let secretkey = keypair.secret.to_bytes();
             let publickey = keypair.public.to_bytes();
             let mut thevec = b"Edaaaaaaaa"[..].to_owned();
@@ -204,36 +346,103 @@ async fn really_inner_main<T: FnOnce() -> Result<ledgeracio::HardStore, Error>>(
                 },
                 base64::encode(&thevec[..])
             );
+            let secret: [&[u8]; 5] = [
+                KEY_MAGIC,
+                &u16::from(KEY_VERSION).to_le_bytes(),
+                &[network.into()],
+                &secretkey[..],
+                &publickey[..],
+            ];
+            // Store the secret first, so that no public key is written for a
+            // secret that was lost
+            match keychain {
+                Some(id) => keychain_store(&id, &secret.concat())?,
+                None => {
+                    let mut file = file.clone();
+                    file.set_extension("sec");
+                    write(&secret, &file)?
+                }
+            }
             write(&[public.as_bytes()], &file)?;
-            file.set_extension("sec");
-            write(
-                &[
-                    KEY_MAGIC,
-                    &u16::from(KEY_VERSION).to_le_bytes(),
-                    &[network.into()],
-                    &secretkey[..],
-                    &publickey[..],
-                ],
-                &file,
-            )?;
+            print_key(&keypair.public, network);
         }
         AllowlistCommand::Sign {
             file,
             secret,
+            secret_env,
             output,
             nonce,
+            max_entries,
+            detached_sig,
+            hex,
+            keychain,
         } => {
-            let file = BufReader::new(fs::File::open(file)?);
-            let secret: Vec<u8> = fs::read(secret)?;
+            let text = fs::read(file)?;
+            let secret: Vec<u8> = match (secret, secret_env, keychain) {
+                (Some(secret), ..) => fs::read(secret)?,
+                (None, Some(var), _) => decode_secret(&std::env::var(&var).map_err(|e| {
+                    format!(
+                        "Cannot read secret key from environment variable {}: {}",
+                        var, e
+                    )
+                })?)?,
+                (None, None, Some(id)) => keychain_load(&id)?,
+                (None, None, None) => unreachable!("structopt requires one of these"),
+            };
             let Keypair { public, secret } = parse_secret(&*secret, network)?;
-            let signed =
-                parse_allowlist::<_, AccountId>(file, network, &public, &(&secret).into(), nonce)?;
-            fs::write(output, signed)?;
+            let signed = parse_allowlist::<_, AccountId>(
+                &*text,
+                network,
+                &public,
+                &(&secret).into(),
+                nonce,
+                max_entries.unwrap_or(DEFAULT_MAX_ENTRIES),
+            )?;
+            // Write next to `output` and only rename into place once verified,
+            // so that an unverified allowlist is never left behind
+            let mut partial = output.clone().into_os_string();
+            partial.push(".partial");
+            let partial = PathBuf::from(partial);
+            if hex {
+                fs::write(&partial, hex::encode(&signed) + "\n")?;
+            } else {
+                fs::write(&partial, &signed)?;
+            }
+            // Read back what was written, so that a bad write is caught too
+            let verified = fs::read(&partial)
+                .and_then(|written| if hex { from_hex(&written) } else { Ok(written) })
+                .and_then(|written| check_signed(&*text, &written, network, &public, nonce));
+            if let Err(e) = verified {
+                let _ = fs::remove_file(&partial);
+                return Err(format!(
+                    "{} failed verification after signing: {}",
+                    output.display(),
+                    e
+                )
+                .into())
+            }
+            fs::rename(&partial, &output)?;
+            if let Some(detached_sig) = detached_sig {
+                fs::write(detached_sig, signature(&signed)?)?;
+            }
+        }
+        AllowlistCommand::Merge { files, output } => {
+            let mut lists = vec![];
+            for file in files {
+                let reader = BufReader::new(fs::File::open(&file)?);
+                lists.push((file.display().to_string(), reader))
+            }
+            let (merged, duplicates) = merge(lists, network)?;
+            fs::write(&output, merged)?;
+            if duplicates > 0 {
+                eprintln!("Removed {} duplicate addresses", duplicates)
+            }
         }
         AllowlistCommand::Inspect {
             file,
             public,
             output,
+            count,
         } => {
             let file = BufReader::new(fs::File::open(file)?);
             let (pk, network) = parse_public(&*fs::read(public)?)?;
@@ -250,10 +459,36 @@ async fn really_inner_main<T: FnOnce() -> Result<ledgeracio::HardStore, Error>>(
                 ),
             });
 
-            for i in crate::parser::inspect::<_, AccountId>(file, network, &pk)? {
+            if count {
+                let summary = ledgeracio::parser::verify(file, &pk)?;
+                writeln!(
+                    output,
+                    "Addresses: {}\nNonce: {}\nNetwork: {}\nSigner: {}",
+                    summary.count,
+                    summary.nonce,
+                    String::from(network),
+                    fingerprint(&pk)
+                )?;
+                return Ok(None)
+            }
+            for i in ledgeracio::parser::inspect::<_, AccountId>(file, network, &pk)? {
                 writeln!(output, "{}", i)?;
             }
         }
+        AllowlistCommand::Verify { file, public } => {
+            let file = BufReader::new(fs::File::open(file)?);
+            let (pk, _network) = parse_public(&*fs::read(public)?)?;
+            match ledgeracio::parser::verify(file, &pk) {
+                Ok(summary) => println!(
+                    "Signature valid\nNonce: {}\nAddresses: {}",
+                    summary.nonce, summary.count
+                ),
+                Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                    return Err(format!("Signature invalid: {}", e).into())
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
     }
     Ok(None)
 }
@@ -0,0 +1,176 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of ledgeracio.
+//
+// ledgeracio is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ledgeracio is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ledgeracio.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Finding nominators on the device whose targets overlap
+//!
+//! Two accounts nominating the same validators add little coverage over one
+//! account nominating them with the combined stake.  The overlap of two target
+//! sets is the number of shared targets as a percentage of the smaller set, so
+//! a set contained in a larger one is a complete overlap.
+
+use super::{common::OutputFormat, AccountId, Error};
+use std::collections::BTreeSet;
+use substrate_subxt::{sp_core::crypto::{Ss58AddressFormat, Ss58Codec},
+                      staking::{LedgerStore, NominatorsStore},
+                      Client, KusamaRuntime};
+
+/// Two nominators whose target sets overlap
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Overlap {
+    /// The indices of the two nominators
+    indices: (u32, u32),
+    /// The targets both nominate
+    shared: Vec<AccountId>,
+    /// The shared targets as a percentage of the smaller set
+    percent: usize,
+    /// Whether the two sets are the same
+    identical: bool,
+}
+
+/// Find the pairs of `targets` that overlap by at least `threshold` percent
+fn overlaps(targets: &[(u32, BTreeSet<AccountId>)], threshold: u8) -> Vec<Overlap> {
+    let mut overlaps = vec![];
+    for (position, (first, first_targets)) in targets.iter().enumerate() {
+        for (second, second_targets) in &targets[position + 1..] {
+            let shared: Vec<AccountId> = first_targets
+                .intersection(second_targets)
+                .cloned()
+                .collect();
+            let smaller = first_targets.len().min(second_targets.len());
+            let percent = match (shared.len() * 100).checked_div(smaller) {
+                Some(percent) => percent,
+                None => continue,
+            };
+            if percent >= usize::from(threshold) {
+                overlaps.push(Overlap {
+                    indices: (*first, *second),
+                    shared,
+                    percent,
+                    identical: first_targets == second_targets,
+                })
+            }
+        }
+    }
+    overlaps
+}
+
+/// Report the nominators among `controllers` whose targets overlap by at
+/// least `threshold` percent
+pub(crate) async fn report(
+    client: &Client<KusamaRuntime>,
+    controllers: &[(u32, AccountId)],
+    threshold: u8,
+    network: Ss58AddressFormat,
+    output: OutputFormat,
+) -> Result<(), Error> {
+    let mut targets = vec![];
+    for (index, controller) in controllers {
+        let store = LedgerStore {
+            controller: controller.clone(),
+        };
+        let stash = match client.fetch(&store, None).await? {
+            Some(ledger) => ledger.stash,
+            None => continue,
+        };
+        if let Some(nominations) = client.fetch(&NominatorsStore { stash }, None).await? {
+            targets.push((*index, nominations.targets.into_iter().collect()))
+        }
+    }
+    let overlaps = overlaps(&targets, threshold);
+    for overlap in &overlaps {
+        let (first, second) = overlap.indices;
+        let shared = overlap
+            .shared
+            .iter()
+            .map(|target| target.to_ss58check_with_version(network));
+        if output == OutputFormat::Jsonl {
+            println!(
+                "{}",
+                serde_json::json!({
+                    "indices": [first, second],
+                    "percent": overlap.percent,
+                    "identical": overlap.identical,
+                    "shared": shared.collect::<Vec<_>>(),
+                })
+            );
+            continue
+        }
+        if overlap.identical {
+            println!(
+                "Accounts {} and {} nominate the same {} targets",
+                first,
+                second,
+                overlap.shared.len()
+            )
+        } else {
+            println!(
+                "Accounts {} and {} share {} targets ({}% of the smaller set):",
+                first,
+                second,
+                overlap.shared.len(),
+                overlap.percent
+            );
+            for target in shared {
+                println!("    {}", target)
+            }
+        }
+    }
+    if output != OutputFormat::Jsonl {
+        if overlaps.is_empty() {
+            println!(
+                "No two of the {} nominating accounts overlap by {}% or more",
+                targets.len(),
+                threshold
+            )
+        } else {
+            println!("Consider nominating different validators from these accounts");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(ids: &[u8]) -> BTreeSet<AccountId> { ids.iter().map(|&id| [id; 32].into()).collect() }
+
+    #[test]
+    fn finds_overlaps() {
+        let targets = vec![
+            (1, set(&[1, 2, 3, 4])),
+            (2, set(&[4, 3, 2, 1])),
+            (3, set(&[1, 2, 5, 6])),
+            (4, set(&[7])),
+            (5, set(&[])),
+        ];
+        let found = overlaps(&targets, 50);
+        assert_eq!(
+            found
+                .iter()
+                .map(|overlap| (overlap.indices, overlap.percent, overlap.identical))
+                .collect::<Vec<_>>(),
+            vec![
+                ((1, 2), 100, true),
+                ((1, 3), 50, false),
+                ((2, 3), 50, false)
+            ]
+        );
+        assert_eq!(found[1].shared, vec![[1; 32].into(), [2; 32].into()]);
+        assert_eq!(overlaps(&targets, 75).len(), 1);
+        assert_eq!(overlaps(&targets[..1], 0), vec![]);
+    }
+}
@@ -16,26 +16,70 @@
 
 //! Utilities shared by both validator and nominator code
 
-use super::{AccountId, AccountType, Error, LedgeracioPath};
-use substrate_subxt::{sp_core::crypto::{Ss58AddressFormat, Ss58Codec},
-                      staking::{LedgerStore, StakingLedger, ValidatorsStore},
+use super::{labels::Labels,
+            payee::{Payee, PayeeStore},
+            price::FiatPrice,
+            rewards::ActiveEraStore,
+            AccountId, AccountType, Error, LedgeracioPath};
+use ledgeracio::{device_address, input_error, parse_address, validate_network, CategorizedError,
+                 ErrorCategory};
+use std::{convert::TryFrom, marker::PhantomData};
+use substrate_subxt::{sp_core::{crypto::{Ss58AddressFormat, Ss58Codec},
+                                H256},
+                      staking::{BondedStore, CurrentEraStore, HistoryDepthStore, LedgerStore,
+                                NominatorsStore, StakingLedger, UnlockChunk, ValidatorsStore},
                       system::AccountStoreExt,
-                      Client, KusamaRuntime, Signer, SystemProperties};
+                      Call, Client, KusamaRuntime, Signer, SystemProperties};
+
+/// Check that every one of `addresses` is for `network`.  All mismatches,
+/// including addresses for unknown networks, are reported in a single error.
+pub(crate) fn check_networks<'a>(
+    addresses: impl IntoIterator<Item = &'a (AccountId, u8)>,
+    network: Ss58AddressFormat,
+) -> Result<(), Error> {
+    let mut mismatched = String::new();
+    let mut count = 0_usize;
+    for &(ref address, provided_network) in addresses {
+        if provided_network == u8::from(network) {
+            continue
+        }
+        let provided_network = Ss58AddressFormat::try_from(provided_network)
+            .unwrap_or(Ss58AddressFormat::Custom(provided_network));
+        count += 1;
+        mismatched += &format!(
+            "\n    {} (network {})",
+            address.to_ss58check_with_version(provided_network),
+            String::from(provided_network)
+        );
+    }
+    if count == 0 {
+        return Ok(())
+    }
+    Err(input_error(format!(
+        "{} addresses are not for network {}:{}",
+        count,
+        String::from(network),
+        mismatched
+    )))
+}
 
 pub(crate) async fn fetch_validators(
     client: &Client<KusamaRuntime>,
     source: AddressSource<'_>,
     network: Ss58AddressFormat,
     account_type: AccountType,
-) -> Result<Vec<AccountId>, Error> {
-    let (index, keystore) = match source {
-        AddressSource::Device(index, signer) => (index, signer),
+) -> Result<Vec<(u32, AccountId)>, Error> {
+    let (indices, keystore) = match source {
+        AddressSource::Device(indices, signer) => (indices, signer),
     };
     let mut v = vec![];
-    if let Some(index) = index {
-        let path = LedgeracioPath::new(network, account_type, index)?;
-        let signer = keystore.signer(path).await?;
-        return Ok(vec![signer.account_id().clone()])
+    if !indices.is_empty() {
+        for &index in indices {
+            let path = LedgeracioPath::new(network, account_type, index)?;
+            let signer = keystore.signer(path).await?;
+            v.push((index, signer.account_id().clone()))
+        }
+        return Ok(v)
     }
     let mut index = 0_u32;
     loop {
@@ -47,76 +91,665 @@ pub(crate) async fn fetch_validators(
         if client.account(account_id, None).await?.data.free == 0 {
             return Ok(v)
         }
-        v.push(account_id.clone())
+        v.push((index, account_id.clone()))
+    }
+}
+
+/// How many accounts the interactive picker offers, unless an account with a
+/// higher index has a label
+const PICKER_ACCOUNTS: u32 = 10;
+
+/// Resolve an optional index argument.  If no index was given, and both stdin
+/// and stderr are terminals, let the user pick an account interactively.
+pub(crate) async fn select_index(
+    index: Option<u32>,
+    account_type: AccountType,
+    keystore: &crate::HardStore,
+    network: Ss58AddressFormat,
+) -> Result<u32, Error> {
+    if let Some(index) = index {
+        return Ok(index)
+    }
+    if !atty::is(atty::Stream::Stdin) || !atty::is(atty::Stream::Stderr) {
+        return Err(input_error(
+            "An index is required when not running in a terminal",
+        ))
+    }
+    let labels = Labels::load_or_warn();
+    let count = labels
+        .max_index(network, account_type)
+        .map_or(PICKER_ACCOUNTS, |index| index.max(PICKER_ACCOUNTS));
+    let mut items = vec![];
+    for index in 1..=count {
+        let label = labels.get(network, account_type, index);
+        // With `--confirm-on-device-only`, accounts are offered by index and
+        // label alone
+        if ledgeracio::confirms_on_device_only() {
+            items.push(match label {
+                Some(label) => format!("{:>3}  [{}]", index, label),
+                None => format!("{:>3}", index),
+            });
+            continue
+        }
+        let path = LedgeracioPath::new(network, account_type, index)?;
+        let signer = keystore.signer(path).await?;
+        let address = signer.account_id().to_ss58check_with_version(network);
+        items.push(match label {
+            Some(label) => format!("{:>3}  {}  [{}]", index, address, label),
+            None => format!("{:>3}  {}", index, address),
+        });
+    }
+    match dialoguer::Select::new()
+        .with_prompt("Select an account")
+        .items(&items)
+        .default(0)
+        .interact_opt()?
+    {
+        Some(selected) => Ok(u32::try_from(selected)? + 1),
+        None => Err(input_error("No account selected")),
+    }
+}
+
+/// How many accounts are searched for the controller of a stash
+const CONTROLLER_SEARCH_ACCOUNTS: u32 = 100;
+
+/// An account given as an argument: an index on the device, or an address
+#[derive(Debug, Clone)]
+pub(crate) enum AccountRef {
+    Index(u32),
+    Address((AccountId, u8)),
+}
+
+impl AccountRef {
+    /// The address, with the network it was encoded for, if one was given
+    pub(crate) fn address(&self) -> Option<&(AccountId, u8)> {
+        match self {
+            Self::Index(_) => None,
+            Self::Address(address) => Some(address),
+        }
+    }
+}
+
+/// Parse an account argument: an index if it is a number, or else an SS58
+/// address
+pub(crate) fn parse_account_ref(arg: &str) -> Result<AccountRef, Error> {
+    match arg.parse() {
+        Ok(index) => Ok(AccountRef::Index(index)),
+        Err(_) => Ok(AccountRef::Address(parse_address(arg)?)),
+    }
+}
+
+/// Find the index of `account` among the first [`CONTROLLER_SEARCH_ACCOUNTS`]
+/// accounts of type `account_type` on the device
+async fn find_index(
+    keystore: &crate::HardStore,
+    network: Ss58AddressFormat,
+    account_type: AccountType,
+    account: &AccountId,
+) -> Result<Option<u32>, Error> {
+    for index in 1..=CONTROLLER_SEARCH_ACCOUNTS {
+        let path = LedgeracioPath::new(network, account_type, index)?;
+        if keystore.signer(path).await?.account_id() == account {
+            return Ok(Some(index))
+        }
+    }
+    Ok(None)
+}
+
+/// Find the index of the account on the device that controls the stash
+/// `stash`, given by index or address.  Transactions such as setting the payee
+/// must be signed by the controller, but users often only know the stash.
+pub(crate) async fn controller_index_for_stash(
+    client: &Client<KusamaRuntime>,
+    keystore: &crate::HardStore,
+    network: Ss58AddressFormat,
+    account_type: AccountType,
+    stash_ref: &AccountRef,
+) -> Result<u32, Error> {
+    let stash = match stash_ref {
+        AccountRef::Index(index) => {
+            let path = LedgeracioPath::new(network, account_type, *index)?;
+            keystore.signer(path).await?.account_id().clone()
+        }
+        AccountRef::Address((address, provided_network)) => {
+            validate_network("", *provided_network, network)?;
+            address.clone()
+        }
+    };
+    let controller = match client
+        .fetch(
+            &BondedStore {
+                stash: stash.clone(),
+            },
+            None,
+        )
+        .await?
+    {
+        Some(controller) => controller,
+        None => {
+            return Err(input_error(format!(
+                "Account {} is not a bonded stash",
+                device_address(&stash, network),
+            )))
+        }
+    };
+    if let (AccountRef::Index(index), true) = (stash_ref, controller == stash) {
+        return Ok(*index)
+    }
+    if let Some(index) = find_index(keystore, network, account_type, &controller).await? {
+        return Ok(index)
+    }
+    Err(input_error(format!(
+        "The controller of stash {} is {}, which is not one of the first {} {} accounts on this \
+         device.  It must sign this transaction instead.",
+        device_address(&stash, network),
+        device_address(&controller, network),
+        CONTROLLER_SEARCH_ACCOUNTS,
+        format!("{:?}", account_type).to_lowercase(),
+    )))
+}
+
+/// Resolve the account that signs a staking transaction.  If `stash` is set,
+/// `account` is a stash, and the index of its controller is returned;
+/// otherwise `account` is the signer itself.
+pub(crate) async fn signer_index(
+    client: &Client<KusamaRuntime>,
+    keystore: &crate::HardStore,
+    network: Ss58AddressFormat,
+    account_type: AccountType,
+    account: &AccountRef,
+    stash: bool,
+) -> Result<u32, Error> {
+    if stash {
+        return controller_index_for_stash(client, keystore, network, account_type, account).await
+    }
+    match account {
+        AccountRef::Index(index) => Ok(*index),
+        AccountRef::Address((address, provided_network)) => {
+            validate_network("", *provided_network, network)?;
+            find_index(keystore, network, account_type, address)
+                .await?
+                .ok_or_else(|| {
+                    input_error(format!(
+                        "{} is not one of the first {} {} accounts on this device",
+                        address.to_ss58check_with_version(network),
+                        CONTROLLER_SEARCH_ACCOUNTS,
+                        format!("{:?}", account_type).to_lowercase(),
+                    ))
+                })
+        }
     }
 }
 
 pub enum AddressSource<'a> {
-    Device(Option<u32>, &'a crate::HardStore),
+    /// The accounts at the given indices, or all funded accounts if there are
+    /// none
+    Device(&'a [u32], &'a crate::HardStore),
+}
+
+/// Whether colored output may be used: it has not been disabled by
+/// `--no-color` or by setting the `NO_COLOR` environment variable to a
+/// non-empty value.  Callers must still check that the output is a terminal.
+pub(crate) fn use_color(no_color: bool) -> bool {
+    !no_color && std::env::var_os("NO_COLOR").map_or(true, |value| value.is_empty())
 }
 
-pub(crate) async fn display_validators(
+/// The format of the output of `show` commands
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human-readable text
+    Text,
+    /// One JSON object per account, each on its own line
+    Jsonl,
+    /// A CSV table.  Only supported by `device addresses` and `validator
+    /// list`; other commands print text instead.
+    Csv,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &*s.to_ascii_lowercase() {
+            "text" => Ok(Self::Text),
+            "jsonl" => Ok(Self::Jsonl),
+            "csv" => Ok(Self::Csv),
+            _ => Err("Output format must be `Text`, `JSONL`, or `CSV`"),
+        }
+    }
+}
+
+/// Display the validator with controller `controller`.  `label` is the label
+/// of the account, if it is one of ours and has one.  `on_device` is whether
+/// the controller is an account on the device, whose addresses
+/// `--confirm-on-device-only` hides.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn display_validator(
     client: &Client<KusamaRuntime>,
-    nominations: &[AccountId],
+    controller: AccountId,
+    label: Option<&str>,
+    on_device: bool,
     network: Ss58AddressFormat,
+    output: OutputFormat,
+    token: &TokenOverrides,
+    eras: Option<u32>,
 ) -> Result<(), Error> {
-    for controller in nominations {
+    let address = |account: &AccountId| {
+        if on_device {
+            device_address(account, network)
+        } else {
+            account.to_ss58check_with_version(network)
+        }
+    };
+    let store = LedgerStore {
+        controller: controller.clone(),
+    };
+    let StakingLedger {
+        stash,
+        total,
+        active,
+        unlocking,
+        claimed_rewards: _,
+    } = match client.fetch(&store, None).await? {
+        Some(ledger) => ledger,
+        None if output == OutputFormat::Jsonl => {
+            println!(
+                "{}",
+                serde_json::json!({
+                    "controller": address(&controller),
+                    "label": label,
+                    "found": false,
+                })
+            );
+            return Ok(())
+        }
+        None => {
+            println!(
+                "validator {}{} not found",
+                address(&controller),
+                label
+                    .map(|label| format!(" [{}]", label))
+                    .unwrap_or_default(),
+            );
+            return Ok(())
+        }
+    };
+    let name = super::identity::display_name(client, &stash).await?;
+    let unclaimed = super::payouts::display_payouts(controller.clone(), client, eras).await?;
+    let store = ValidatorsStore {
+        stash: stash.clone(),
+    };
+    let prefs = client.fetch(&store, None).await?;
+    let current_era = current_era(client).await?;
+    if output == OutputFormat::Jsonl {
+        println!(
+            "{}",
+            serde_json::json!({
+                "controller": address(&controller),
+                "label": label,
+                "found": true,
+                "stash": address(&stash),
+                "identity": name,
+                "total": total.to_string(),
+                "active": active.to_string(),
+                "unclaimed_eras": unclaimed,
+                "unlocking": unlocking
+                    .iter()
+                    .map(|chunk| serde_json::json!({
+                        "value": chunk.value.to_string(),
+                        "era": chunk.era,
+                        "withdrawable": chunk.era <= current_era,
+                    }))
+                    .collect::<Vec<_>>(),
+                "commission": prefs.map(|prefs| prefs.commission.deconstruct()),
+            })
+        );
+        return Ok(())
+    }
+    let (token_decimals, token_symbol) = token.resolve(client);
+    println!(
+        "    Validator account: {}{}{}\n    Stash balance: {} {sym}{}\n    Amount at stake: {} \
+         {sym}{}\nEras with unclaimed payouts: {:?}\n    Amount unlocking: {}",
+        address(&stash),
+        name.map(|name| format!(" ({})", name)).unwrap_or_default(),
+        label
+            .map(|label| format!(" [{}]", label))
+            .unwrap_or_default(),
+        pad(token_decimals, total),
+        token.fiat(token_decimals, total),
+        pad(token_decimals, active),
+        token.fiat(token_decimals, active),
+        unclaimed,
+        describe_unlocking(
+            &unlocking,
+            current_era,
+            token_decimals,
+            &token_symbol,
+            "        "
+        ),
+        sym = token_symbol
+    );
+    match prefs {
+        None => println!(
+            "    validator {} has no preferences ― it is probably inactive\n",
+            address(&stash)
+        ),
+        Some(prefs) => println!(
+            "    Commission: {}% ({} bps)\n",
+            pad(7, prefs.commission.deconstruct().into()),
+            pad(5, prefs.commission.deconstruct().into())
+        ),
+    }
+    Ok(())
+}
+
+/// Print the SCALE encoding of `call` in hex, for signing elsewhere.  Nothing
+/// is signed or submitted.
+pub(crate) fn print_call<C: Call<KusamaRuntime>>(
+    client: &Client<KusamaRuntime>,
+    call: C,
+) -> Result<Option<H256>, Error> {
+    println!("0x{}", hex::encode(client.encode(call)?.0));
+    Ok(None)
+}
+
+/// Warn if the node reports an SS58 format other than that of `network`.
+/// Accounts are stored on chain as raw bytes, so every address is encoded for
+/// `network`; if the chain uses another format, the addresses shown differ
+/// from those shown by the chain’s own tools.
+pub(crate) fn check_chain_format(client: &Client<KusamaRuntime>, network: Ss58AddressFormat) {
+    let reported = client.properties().ss58_format;
+    if reported != u8::from(network) {
+        eprintln!(
+            "Warning: the node reports SS58 format {}, but addresses are shown for network {} \
+             (format {})",
+            reported,
+            String::from(network),
+            u8::from(network)
+        )
+    }
+}
+
+/// Storage items of the staking pallet that are read by ledgeracio
+const STAKING_STORAGE: [&str; 8] = [
+    "Bonded",
+    "Ledger",
+    "Payee",
+    "Validators",
+    "Nominators",
+    "CurrentEra",
+    "ErasStakers",
+    "ErasRewardPoints",
+];
+
+/// Check that the staking pallet of the chain has the storage layout that
+/// ledgeracio decodes.  Older or modified pallets would otherwise fail with
+/// obscure decoding errors.  `HistoryDepth` is not required, as newer pallets
+/// make it a constant; it is read with [`history_depth`].
+pub(crate) fn check_staking_pallet(client: &Client<KusamaRuntime>) -> Result<(), Error> {
+    let unsupported = |message: String| -> Error {
+        Box::new(CategorizedError::new(ErrorCategory::Chain, message))
+    };
+    let staking = client
+        .metadata()
+        .module("Staking")
+        .map_err(|_| unsupported("This chain has no staking pallet".to_owned()))?;
+    let missing: Vec<&str> = STAKING_STORAGE
+        .iter()
+        .copied()
+        .filter(|&name| staking.storage(name).is_err())
+        .collect();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(unsupported(format!(
+            "Unsupported staking pallet version: the storage items {} are missing",
+            missing.join(", ")
+        )))
+    }
+}
+
+/// Describe `account` if its raw bytes show that it was not derived from a
+/// key pair, such as an account that belongs to a pallet or a parachain.
+/// Such accounts are unusual as validator controllers and stashes.
+pub(crate) fn unusual_account(account: &AccountId) -> Option<&'static str> {
+    let bytes: &[u8] = account.as_ref();
+    if bytes.iter().all(|&byte| byte == 0) {
+        Some("the all-zero account, which has no key pair")
+    } else if bytes.starts_with(b"modl") {
+        Some("a pallet account, which has no key pair")
+    } else if bytes.starts_with(b"para") || bytes.starts_with(b"sibl") {
+        Some("a parachain sovereign account, which has no key pair")
+    } else {
+        None
+    }
+}
+
+/// Overrides of the token decimals and symbol reported by the node, for nodes
+/// that report them incorrectly, and the fiat price to show balances in
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TokenOverrides {
+    pub(crate) decimals: Option<u8>,
+    pub(crate) symbol: Option<String>,
+    pub(crate) fiat: Option<FiatPrice>,
+}
+
+impl TokenOverrides {
+    /// The number of decimals and the symbol of the chain’s token.  Overrides
+    /// take precedence over the node’s properties.  A symbol from the node
+    /// that is not uppercase ASCII is ignored.
+    pub(crate) fn resolve(&self, client: &Client<KusamaRuntime>) -> (u8, String) {
+        let SystemProperties {
+            token_decimals,
+            token_symbol,
+            ..
+        } = client.properties().clone();
+        let decimals = self.decimals.unwrap_or(token_decimals);
+        let symbol = match &self.symbol {
+            Some(symbol) => symbol.clone(),
+            None if token_symbol.bytes().all(|i| i.is_ascii_uppercase()) => token_symbol,
+            None => "".to_owned(),
+        };
+        (decimals, symbol)
+    }
+
+    /// A note giving the approximate fiat value of `value`, or nothing if no
+    /// price was requested
+    pub(crate) fn fiat(&self, decimals: u8, value: u128) -> String {
+        self.fiat
+            .as_ref()
+            .map(|price| price.describe(decimals, value))
+            .unwrap_or_default()
+    }
+}
+
+/// The current era, used to tell which unlocking chunks can be withdrawn
+pub(crate) async fn current_era(client: &Client<KusamaRuntime>) -> Result<u32, Error> {
+    Ok(client
+        .fetch_or_default(
+            &CurrentEraStore {
+                _runtime: PhantomData,
+            },
+            None,
+        )
+        .await?)
+}
+
+/// The era in progress, whose rewards cannot be paid out yet.  Unlike
+/// [`current_era`], this is never an era that has not started.
+pub(crate) async fn active_era(client: &Client<KusamaRuntime>) -> Result<u32, Error> {
+    let store = ActiveEraStore {
+        _runtime: PhantomData,
+    };
+    Ok(client.fetch(&store, None).await?.map_or(0, |era| era.index))
+}
+
+/// How many eras of history the chain keeps; older eras can no longer be paid
+/// out.  This is a storage item in older staking pallets and a constant in
+/// newer ones, and `None` if the chain has neither.
+pub(crate) async fn history_depth(client: &Client<KusamaRuntime>) -> Result<Option<u32>, Error> {
+    let staking = client.metadata().module("Staking")?;
+    if staking.storage("HistoryDepth").is_err() {
+        return match staking.constant("HistoryDepth") {
+            Ok(constant) => Ok(Some(constant.value()?)),
+            Err(_) => Ok(None),
+        }
+    }
+    let store = HistoryDepthStore {
+        _runtime: PhantomData,
+    };
+    Ok(Some(client.fetch_or_default(&store, None).await?))
+}
+
+/// Whether the payee of the stash controlled by `controller` is already
+/// `target`.  An account that is not bonded has no payee.
+pub(crate) async fn payee_is(
+    client: &Client<KusamaRuntime>,
+    controller: &AccountId,
+    target: &Payee<AccountId>,
+) -> Result<bool, Error> {
+    let store = LedgerStore {
+        controller: controller.clone(),
+    };
+    let stash = match client.fetch(&store, None).await? {
+        Some(StakingLedger { stash, .. }) => stash,
+        None => return Ok(false),
+    };
+    Ok(client.fetch(&PayeeStore { stash }, None).await?.as_ref() == Some(target))
+}
+
+/// Describe the chunks in `unlocking`, one per line, each preceded by `indent`.
+/// A chunk can be rebonded until the era it unlocks in, after which it can be
+/// withdrawn.
+pub(crate) fn describe_unlocking(
+    unlocking: &[UnlockChunk<u128>],
+    current_era: u32,
+    token_decimals: u8,
+    token_symbol: &str,
+    indent: &str,
+) -> String {
+    if unlocking.is_empty() {
+        return "none".to_owned()
+    }
+    let mut description = String::new();
+    for chunk in unlocking {
+        description += &format!(
+            "\n{}{} {} ({} planck): ",
+            indent,
+            pad(token_decimals, chunk.value),
+            token_symbol,
+            chunk.value
+        );
+        description += &if chunk.era > current_era {
+            format!(
+                "rebondable, unlocks in era {} ({} eras from now)",
+                chunk.era,
+                chunk.era - current_era
+            )
+        } else {
+            format!("withdrawable since era {}", chunk.era)
+        };
+    }
+    description
+}
+
+/// Print how many of `controllers` are bonded, and how many of those are
+/// nominating or validating (depending on `account_type`) or chilled.
+pub(crate) async fn display_counts(
+    client: &Client<KusamaRuntime>,
+    controllers: &[(u32, AccountId)],
+    account_type: AccountType,
+) -> Result<(), Error> {
+    let (mut bonded, mut active, mut chilled, mut unbonded) = (0_usize, 0_usize, 0_usize, 0_usize);
+    for (_, controller) in controllers {
         let store = LedgerStore {
             controller: controller.clone(),
         };
-        match client.fetch(&store, None).await? {
-            None => println!(
-                "validator {} not found",
-                controller.to_ss58check_with_version(network)
-            ),
-            Some(StakingLedger {
-                stash,
-                total,
-                active,
-                unlocking,
-                claimed_rewards: _,
-            }) => {
-                let SystemProperties {
-                    token_decimals,
-                    mut token_symbol,
-                    ..
-                } = client.properties().clone();
-                let mut good_symbol = true;
-                for i in token_symbol.bytes() {
-                    good_symbol &= i.is_ascii_uppercase()
-                }
-                if !good_symbol {
-                    token_symbol = "".to_owned()
-                }
-                println!(
-                    "    Validator account: {}\n    Stash balance: {} {sym}\n    Amount at stake: \
-                     {} {sym}\nEras with unclaimed payouts: {:?}\n    Amount unlocking: {:?}",
-                    stash.to_ss58check_with_version(network),
-                    pad(token_decimals, total),
-                    pad(token_decimals, active),
-                    super::payouts::display_payouts(controller.clone(), client).await?,
-                    unlocking,
-                    sym = token_symbol
-                );
-                let store = ValidatorsStore {
-                    stash: stash.clone(),
-                };
-                match client.fetch(&store, None).await? {
-                    None => println!(
-                        "    validator {} has no preferences ― it is probably inactive\n",
-                        stash.to_ss58check_with_version(network)
-                    ),
-                    Some(prefs) => println!(
-                        "    Commission: {}%\n",
-                        pad(9, u128::from(prefs.commission.deconstruct()) * 100)
-                    ),
-                }
+        let stash = match client.fetch(&store, None).await? {
+            Some(StakingLedger { stash, .. }) => stash,
+            None => {
+                unbonded += 1;
+                continue
             }
+        };
+        bonded += 1;
+        let is_active = match account_type {
+            AccountType::Nominator => client
+                .fetch(&NominatorsStore { stash }, None)
+                .await?
+                .is_some(),
+            AccountType::Validator => client
+                .fetch(&ValidatorsStore { stash }, None)
+                .await?
+                .is_some(),
+        };
+        if is_active {
+            active += 1
+        } else {
+            chilled += 1
         }
     }
+    println!(
+        "Accounts: {}\nBonded: {}\n{}: {}\nChilled: {}\nUnbonded: {}",
+        controllers.len(),
+        bonded,
+        match account_type {
+            AccountType::Nominator => "Nominating",
+            AccountType::Validator => "Validating",
+        },
+        active,
+        chilled,
+        unbonded,
+    );
     Ok(())
 }
 
+/// Format `value` for a table cell, followed by the token symbol if there is
+/// one.  `token` is the number of decimals and the symbol of the token.
+pub(crate) fn format_balance((decimals, symbol): (u8, &str), value: u128) -> String {
+    if symbol.is_empty() {
+        pad(decimals, value)
+    } else {
+        format!("{} {}", pad(decimals, value), symbol)
+    }
+}
+
+/// Format `rows` as a table, with columns separated by two spaces.  The first
+/// row is the header.  The columns in `right_aligned` are aligned to the
+/// right, and all others to the left.
+pub(crate) fn format_table(rows: &[Vec<String>], right_aligned: &[usize]) -> String {
+    let mut widths: Vec<usize> = vec![];
+    for row in rows {
+        for (column, cell) in row.iter().enumerate() {
+            let width = cell.chars().count();
+            match widths.get_mut(column) {
+                Some(max) => *max = (*max).max(width),
+                None => widths.push(width),
+            }
+        }
+    }
+    let mut table = String::new();
+    for row in rows {
+        let mut line = String::new();
+        for (column, cell) in row.iter().enumerate() {
+            if column > 0 {
+                line.push_str("  ")
+            }
+            let padding = " ".repeat(widths[column] - cell.chars().count());
+            if right_aligned.contains(&column) {
+                line.push_str(&padding);
+                line.push_str(cell);
+            } else {
+                line.push_str(cell);
+                line.push_str(&padding);
+            }
+        }
+        table.push_str(line.trim_end());
+        table.push('\n');
+    }
+    table
+}
+
 pub fn pad(mut zeros: u8, value: u128) -> String {
     if value == 0 {
         return "0".to_owned()
@@ -143,7 +776,119 @@ pub fn pad(mut zeros: u8, value: u128) -> String {
     value
 }
 
+/// The value of `--ss58-prefix` in the command-line arguments `args`, if it is
+/// given and is a number.  Address arguments are parsed together with the
+/// other arguments, so this is needed before they are parsed.
+pub(crate) fn ss58_prefix_arg<I: IntoIterator<Item = String>>(args: I) -> Option<u8> {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break
+        } else if arg == "--ss58-prefix" {
+            return args.next()?.parse().ok()
+        } else if let Some(prefix) = arg.strip_prefix("--ss58-prefix=") {
+            return prefix.parse().ok()
+        }
+    }
+    None
+}
+
+/// Parse a duration such as `7d` or `12h` into seconds.  The supported units
+/// are `s` (seconds), `m` (minutes), `h` (hours), `d` (days), and `w` (weeks).
+pub fn parse_duration(data: &str) -> Result<u64, Error> {
+    let unit = match data.bytes().last() {
+        Some(b's') => 1,
+        Some(b'm') => 60,
+        Some(b'h') => 60 * 60,
+        Some(b'd') => 24 * 60 * 60,
+        Some(b'w') => 7 * 24 * 60 * 60,
+        _ => {
+            return Err("Duration must end with a unit: s, m, h, d, or w"
+                .to_owned()
+                .into())
+        }
+    };
+    let count: u64 = data[..data.len() - 1]
+        .parse()
+        .map_err(|_| format!("Invalid duration {}", data))?;
+    count
+        .checked_mul(unit)
+        .ok_or_else(|| format!("Duration {} too long", data).into())
+}
+
+/// Parse a non-negative decimal number with at most `scale` digits after the
+/// decimal point, as a number of its smallest units
+fn parse_scaled(data: &str, scale: usize) -> Result<u64, Error> {
+    let bad = || format!("Invalid commission {:?}", data);
+    let (integer, fraction) = match data.find('.') {
+        Some(dot) => (&data[..dot], &data[dot + 1..]),
+        None => (data, ""),
+    };
+    if integer.is_empty()
+        || integer.len() > 10
+        || fraction.len() > scale
+        || !integer
+            .bytes()
+            .chain(fraction.bytes())
+            .all(|i| i.is_ascii_digit())
+        || (data.contains('.') && fraction.is_empty())
+    {
+        return Err(bad().into())
+    }
+    let mut res: u64 = integer.parse().map_err(|_| bad())?;
+    for i in 0..scale {
+        let digit = fraction.as_bytes().get(i).map_or(0, |&i| i - b'0');
+        res = res * 10 + u64::from(digit);
+    }
+    Ok(res)
+}
+
+/// Parse an amount of tokens, such as `1.5`, into planck.  `decimals` is the
+/// number of decimals of the token.
+pub fn parse_tokens(data: &str, decimals: u8) -> Result<u128, Error> {
+    let bad = || format!("Invalid amount {:?}", data);
+    let (integer, fraction) = match data.find('.') {
+        Some(dot) => (&data[..dot], &data[dot + 1..]),
+        None => (data, ""),
+    };
+    if integer.is_empty()
+        || fraction.len() > usize::from(decimals)
+        || (data.contains('.') && fraction.is_empty())
+        || !integer
+            .bytes()
+            .chain(fraction.bytes())
+            .all(|i| i.is_ascii_digit())
+    {
+        return Err(bad().into())
+    }
+    let mut res: u128 = integer.parse().map_err(|_| bad())?;
+    for i in 0..usize::from(decimals) {
+        let digit = fraction.as_bytes().get(i).map_or(0, |&i| i - b'0');
+        res = res
+            .checked_mul(10)
+            .and_then(|res| res.checked_add(u128::from(digit)))
+            .ok_or_else(|| format!("Amount {} too large", data))?;
+    }
+    Ok(res)
+}
+
+/// Parse a commission, as a decimal between 0 and 1 inclusive, a percentage
+/// such as `2.5%`, or a number of basis points such as `250bps`.  The result is
+/// in parts per billion.
 pub fn parse_ppb(data: &str) -> Result<u32, Error> {
+    let scaled = if let Some(percent) = data.strip_suffix('%') {
+        Some(parse_scaled(percent.trim_end(), 7)?)
+    } else if let Some(bps) = data.strip_suffix("bps") {
+        Some(parse_scaled(bps.trim_end(), 5)?)
+    } else {
+        None
+    };
+    if let Some(scaled) = scaled {
+        return match u32::try_from(scaled) {
+            Ok(ppb) if ppb <= 1_000_000_000 => Ok(ppb),
+            _ => Err("Commission cannot exceed 100%".to_owned().into()),
+        }
+    }
     if data.len() > 11 {
         Err("Commission too long.  Check for excess trailing zeroes."
             .to_owned()
@@ -180,6 +925,66 @@ pub fn parse_ppb(data: &str) -> Result<u32, Error> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn describes_unlocking() {
+        assert_eq!(describe_unlocking(&[], 10, 12, "KSM", "  "), "none");
+        let unlocking = [
+            UnlockChunk {
+                value: 1_500_000_000_000,
+                era: 9,
+            },
+            UnlockChunk {
+                value: 2_000_000_000_000,
+                era: 13,
+            },
+        ];
+        assert_eq!(
+            describe_unlocking(&unlocking, 10, 12, "KSM", "  "),
+            "\n  1.5 KSM (1500000000000 planck): withdrawable since era 9\n  2 KSM (2000000000000 \
+             planck): rebondable, unlocks in era 13 (3 eras from now)"
+        );
+    }
+
+    #[test]
+    fn detects_unusual_accounts() {
+        let mut bytes = [0_u8; 32];
+        assert!(unusual_account(&bytes.into()).is_some());
+        bytes[..4].copy_from_slice(b"modl");
+        assert!(unusual_account(&bytes.into()).is_some());
+        bytes[..4].copy_from_slice(b"para");
+        assert!(unusual_account(&bytes.into()).is_some());
+        assert_eq!(unusual_account(&[1; 32].into()), None);
+    }
+
+    #[test]
+    fn reports_every_network_mismatch() {
+        let addresses = [
+            (AccountId::from([1; 32]), 2),
+            (AccountId::from([2; 32]), 0),
+            (AccountId::from([3; 32]), 200),
+            (AccountId::from([4; 32]), 2),
+        ];
+        assert!(check_networks(&addresses[..1], Ss58AddressFormat::KusamaAccount).is_ok());
+        let error = check_networks(&addresses, Ss58AddressFormat::KusamaAccount)
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("2 addresses are not for network"));
+        assert_eq!(error.lines().count(), 3);
+    }
+
+    #[test]
+    fn formats_tables() {
+        let rows = vec![
+            vec!["INDEX".to_owned(), "STATUS".to_owned(), "LABEL".to_owned()],
+            vec!["1".to_owned(), "nominating".to_owned(), "café".to_owned()],
+            vec!["12".to_owned(), "chilled".to_owned(), "".to_owned()],
+        ];
+        assert_eq!(
+            format_table(&rows, &[0]),
+            "INDEX  STATUS      LABEL\n    1  nominating  café\n   12  chilled\n"
+        );
+    }
+
     #[test]
     fn padding_works() {
         assert_eq!(pad(0, 100), "100".to_owned());
@@ -189,6 +994,77 @@ mod tests {
         assert_eq!(pad(3, 10010), "10.01".to_owned());
     }
 
+    #[test]
+    fn parse_output_format_works() {
+        assert_eq!("Text".parse(), Ok(OutputFormat::Text));
+        assert_eq!("JSONL".parse(), Ok(OutputFormat::Jsonl));
+        assert_eq!("jsonl".parse(), Ok(OutputFormat::Jsonl));
+        assert_eq!("CSV".parse(), Ok(OutputFormat::Csv));
+        assert!("json".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn finds_ss58_prefix() {
+        let args =
+            |args: &[&str]| -> Vec<String> { args.iter().map(|&arg| arg.to_owned()).collect() };
+        assert_eq!(
+            ss58_prefix_arg(args(&[
+                "--network",
+                "custom",
+                "--ss58-prefix",
+                "36",
+                "metadata"
+            ])),
+            Some(36)
+        );
+        assert_eq!(ss58_prefix_arg(args(&["--ss58-prefix=36"])), Some(36));
+        assert_eq!(ss58_prefix_arg(args(&["--ss58-prefix"])), None);
+        assert_eq!(ss58_prefix_arg(args(&["--ss58-prefix", "x"])), None);
+        assert_eq!(ss58_prefix_arg(args(&["--", "--ss58-prefix", "36"])), None);
+        assert_eq!(ss58_prefix_arg(args(&["--network", "polkadot"])), None);
+    }
+
+    #[test]
+    fn parse_duration_works() {
+        assert_eq!(parse_duration("90s").unwrap(), 90);
+        assert_eq!(parse_duration("30m").unwrap(), 30 * 60);
+        assert_eq!(parse_duration("12h").unwrap(), 12 * 60 * 60);
+        assert_eq!(parse_duration("7d").unwrap(), 7 * 24 * 60 * 60);
+        assert_eq!(parse_duration("2w").unwrap(), 14 * 24 * 60 * 60);
+        assert!(parse_duration("7").is_err());
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("-1d").is_err());
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("99999999999999999999w").is_err());
+    }
+
+    #[test]
+    fn parse_tokens_works() {
+        assert_eq!(parse_tokens("1.5", 12).unwrap(), 1_500_000_000_000);
+        assert_eq!(parse_tokens("10", 10).unwrap(), 100_000_000_000);
+        assert_eq!(parse_tokens("0.0000000001", 10).unwrap(), 1);
+        assert_eq!(parse_tokens("7", 0).unwrap(), 7);
+        assert!(parse_tokens("0.00000000001", 10).is_err());
+        assert!(parse_tokens("1.", 10).is_err());
+        assert!(parse_tokens(".5", 10).is_err());
+        assert!(parse_tokens("-1", 10).is_err());
+        assert!(parse_tokens("1e3", 10).is_err());
+        assert!(parse_tokens("999999999999999999999999999999", 12).is_err());
+    }
+
+    #[test]
+    fn parse_account_ref_works() {
+        assert!(matches!(
+            parse_account_ref("7").unwrap(),
+            AccountRef::Index(7)
+        ));
+        match parse_account_ref("5DArCreQ9Yk2HaGvxcRHS35qky3eXBD5BprPZQvbiJBfFY6Y").unwrap() {
+            AccountRef::Address((_, network)) => assert_eq!(network, 42),
+            AccountRef::Index(_) => panic!("address parsed as an index"),
+        }
+        assert!(parse_account_ref("-1").is_err());
+    }
+
     #[test]
     fn parse_ppb_works() {
         assert_eq!(parse_ppb("1.000000000").unwrap(), 1_000_000_000);
@@ -202,4 +1078,22 @@ mod tests {
         assert_eq!(parse_ppb("0.000000000").unwrap(), 0);
         assert_eq!(parse_ppb("0.999999999").unwrap(), 999_999_999);
     }
+
+    #[test]
+    fn parse_ppb_percent_and_bps() {
+        assert_eq!(parse_ppb("2.5%").unwrap(), 25_000_000);
+        assert_eq!(parse_ppb("250bps").unwrap(), 25_000_000);
+        assert_eq!(parse_ppb("250 bps").unwrap(), 25_000_000);
+        assert_eq!(parse_ppb("100%").unwrap(), 1_000_000_000);
+        assert_eq!(parse_ppb("10000bps").unwrap(), 1_000_000_000);
+        assert_eq!(parse_ppb("0.5bps").unwrap(), 50_000);
+        assert_eq!(parse_ppb("0.0000001%").unwrap(), 1);
+        assert!(parse_ppb("0.00000001%").is_err());
+        assert!(parse_ppb("100.1%").is_err());
+        assert!(parse_ppb("10001bps").is_err());
+        assert!(parse_ppb("%").is_err());
+        assert!(parse_ppb("2.%").is_err());
+        assert!(parse_ppb("-1%").is_err());
+        assert!(parse_ppb("99999999999%").is_err());
+    }
 }
@@ -0,0 +1,190 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of ledgeracio.
+//
+// ledgeracio is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ledgeracio is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ledgeracio.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Connecting to the RPC node
+//!
+//! Commands get a [`Connector`] rather than a client, so that long-running
+//! commands can connect again if the node drops the connection.
+
+use super::{insecure_tls, Error};
+use core::{future::Future, time::Duration};
+use jsonrpsee::common::Params;
+use ledgeracio::{categorize, input_error, ErrorCategory};
+use std::convert::TryFrom;
+use substrate_subxt::{sp_core::H256, Client, ClientBuilder, KusamaRuntime};
+
+/// The delay before the first attempt to reconnect, in milliseconds
+const RECONNECT_INITIAL_DELAY_MS: u64 = 1000;
+
+/// The longest delay between attempts to reconnect, in milliseconds
+const RECONNECT_MAX_DELAY_MS: u64 = 60_000;
+
+/// The spec version of the runtime the node is running now
+pub(crate) async fn spec_version(client: &Client<KusamaRuntime>) -> Result<u32, Error> {
+    let version: serde_json::Value = client
+        .rpc_client()
+        .request("state_getRuntimeVersion", Params::None)
+        .await?;
+    version["specVersion"]
+        .as_u64()
+        .and_then(|version| u32::try_from(version).ok())
+        .ok_or_else(|| "Node returned an invalid runtime version".to_owned().into())
+}
+
+/// How to connect to the RPC node
+#[derive(Clone, Debug)]
+pub(crate) struct Connector {
+    host: String,
+    insecure_accept_invalid_certs: bool,
+    reconnect: bool,
+    /// The genesis hash the node must report, if any
+    genesis_hash: Option<H256>,
+}
+
+impl Connector {
+    pub(crate) fn new(
+        host: String,
+        insecure_accept_invalid_certs: bool,
+        reconnect: bool,
+        genesis_hash: Option<H256>,
+    ) -> Self {
+        Self {
+            host,
+            insecure_accept_invalid_certs,
+            reconnect,
+            genesis_hash,
+        }
+    }
+
+    /// The URL of the node
+    pub(crate) fn host(&self) -> &str { &self.host }
+
+    /// Whether the node is reached over a WebSocket, which is needed to watch
+    /// transactions until they are included in a block
+    pub(crate) fn subscribes(&self) -> bool {
+        self.host.starts_with("ws://") || self.host.starts_with("wss://")
+    }
+
+    /// A connector to `host` with the same settings
+    pub(crate) fn with_host(&self, host: String) -> Self {
+        Self {
+            host,
+            ..self.clone()
+        }
+    }
+
+    /// Connect to the node, and check its genesis hash if one was given
+    pub(crate) async fn connect(&self) -> Result<Client<KusamaRuntime>, Error> {
+        let host = &*self.host;
+        let builder = ClientBuilder::<KusamaRuntime>::new();
+        let builder = if self.insecure_accept_invalid_certs {
+            builder.set_client(insecure_tls::connect(host).await?)
+        } else if host.starts_with("http://") || host.starts_with("https://") {
+            builder.set_client(jsonrpsee::http_client(host))
+        } else if host.starts_with("ws://") || host.starts_with("wss://") {
+            builder.set_url(host)
+        } else {
+            return Err(input_error(
+                "RPC host must start with ws://, wss://, http://, or https://",
+            ))
+        };
+        let client = builder.build().await?;
+        match self.genesis_hash {
+            Some(expected) if *client.genesis() != expected => Err(input_error(format!(
+                "The node at {} has genesis hash {:?}, but --genesis-hash is {:?}",
+                host,
+                client.genesis(),
+                expected
+            ))),
+            _ => Ok(client),
+        }
+    }
+
+    /// Connect to the node, and check that its staking pallet is supported
+    /// with [`check_staking_pallet`](super::common::check_staking_pallet)
+    pub(crate) async fn connect_staking(&self) -> Result<Client<KusamaRuntime>, Error> {
+        let client = self.connect().await?;
+        super::common::check_staking_pallet(&client)?;
+        Ok(client)
+    }
+
+    /// Connect to the node again if its runtime has been upgraded since
+    /// `spec_version`, which is then updated.  A client keeps the metadata of
+    /// the runtime it was built for, so after an upgrade its calls and storage
+    /// queries can fail or be encoded wrongly; building a new client fetches
+    /// the new metadata.  Otherwise, `client` is returned.
+    pub(crate) async fn refresh(
+        &self,
+        client: Client<KusamaRuntime>,
+        spec_version: &mut u32,
+    ) -> Result<Client<KusamaRuntime>, Error> {
+        let current = self::spec_version(&client).await?;
+        if current == *spec_version {
+            return Ok(client)
+        }
+        eprintln!(
+            "Runtime upgraded from spec version {} to {}; reloading metadata",
+            spec_version, current
+        );
+        drop(client);
+        let client = self.connect().await?;
+        *spec_version = self::spec_version(&client).await?;
+        Ok(client)
+    }
+
+    /// Connect to the node again after `error`, if `--reconnect` was given and
+    /// `error` is a network error.  Attempts are retried with exponential
+    /// backoff until one succeeds.  Otherwise, `error` is returned.
+    pub(crate) async fn reconnect(&self, error: Error) -> Result<Client<KusamaRuntime>, Error> {
+        if !self.reconnect || categorize(&error) != ErrorCategory::Network {
+            return Err(error)
+        }
+        eprintln!("Lost connection to {}: {}", self.host, error);
+        let mut delay = RECONNECT_INITIAL_DELAY_MS;
+        loop {
+            eprintln!("Reconnecting in {} seconds", delay / 1000);
+            async_std::task::sleep(Duration::from_millis(delay)).await;
+            match self.connect().await {
+                Ok(client) => {
+                    eprintln!("Reconnected to {}", self.host);
+                    return Ok(client)
+                }
+                Err(e) if categorize(&e) == ErrorCategory::Network => {
+                    eprintln!("Failed to reconnect: {}", e);
+                    delay = (delay * 2).min(RECONNECT_MAX_DELAY_MS)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Run `operation` with a connection to the node, and if it fails, connect
+    /// again with [`reconnect`](Self::reconnect) and run it again.  `client`
+    /// is replaced by the new connection.  This is for per-item work in
+    /// batches, so that one dropped connection does not fail the rest.
+    pub(crate) async fn retry<R, F: Future<Output = Result<R, Error>>>(
+        &self,
+        client: &mut Client<KusamaRuntime>,
+        mut operation: impl FnMut(Client<KusamaRuntime>) -> F,
+    ) -> Result<R, Error> {
+        loop {
+            match operation(client.clone()).await {
+                Ok(result) => return Ok(result),
+                Err(e) => *client = self.reconnect(e).await?,
+            }
+        }
+    }
+}
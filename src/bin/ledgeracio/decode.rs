@@ -0,0 +1,465 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of ledgeracio.
+//
+// ledgeracio is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ledgeracio is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ledgeracio.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Decoding raw extrinsics, so that they can be checked before they are
+//! broadcast
+//!
+//! The call is looked up in the chain’s metadata.  The metadata only gives the
+//! *names* of argument types, so only the types used by the calls Ledgeracio
+//! deals with (and common primitives) are decoded.  Decoding stops at the first
+//! argument of an unknown type, and the rest of the call is shown as hex.
+//!
+//! The metadata is normally fetched from the node, but can also be read from a
+//! file with `--metadata-file`, so that extrinsics can be decoded offline.
+
+use super::{common::OutputFormat, AccountId, Error};
+use codec::{Compact, Decode};
+use frame_support::metadata::{DecodeDifferent, RuntimeMetadata, RuntimeMetadataPrefixed};
+use jsonrpsee::common::Params;
+use ledgeracio::input_error;
+use serde_json::{json, Value};
+use std::{convert::TryFrom, path::Path};
+use substrate_subxt::{sp_core::{crypto::{Ss58AddressFormat, Ss58Codec},
+                                Bytes},
+                      Client, KusamaRuntime};
+
+/// A dispatchable function, as described by the metadata
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Function {
+    name: String,
+    /// The name and type of each argument
+    arguments: Vec<(String, String)>,
+}
+
+/// A module with calls, as described by the metadata.  Modules are in the
+/// order of their indices.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Module {
+    name: String,
+    calls: Vec<Function>,
+}
+
+fn decoded<B, O>(value: &DecodeDifferent<B, O>) -> Result<&O, Error>
+where
+    B: 'static,
+    O: 'static,
+{
+    match value {
+        DecodeDifferent::Decoded(value) => Ok(value),
+        DecodeDifferent::Encode(_) => Err("Metadata was not decoded".to_owned().into()),
+    }
+}
+
+/// Fetch the SCALE-encoded metadata of the connected chain
+pub(crate) async fn fetch_metadata(client: &Client<KusamaRuntime>) -> Result<Vec<u8>, Error> {
+    let bytes: Bytes = client
+        .rpc_client()
+        .request("state_getMetadata", Params::None)
+        .await?;
+    Ok(bytes.0)
+}
+
+/// Read SCALE-encoded metadata from `path`.  The file may hold the raw bytes,
+/// or hex with a `0x` prefix, as returned by `state_getMetadata`.
+pub(crate) fn read_metadata(path: &Path) -> Result<Vec<u8>, Error> {
+    let bytes = std::fs::read(path)
+        .map_err(|e| input_error(format!("Cannot read {}: {}", path.display(), e)))?;
+    if !bytes.starts_with(b"0x") {
+        return Ok(bytes)
+    }
+    let hex = std::str::from_utf8(&bytes[2..])
+        .map_err(|_| input_error(format!("{} is not valid hex", path.display())))?;
+    hex::decode(hex.trim())
+        .map_err(|e| input_error(format!("{} is not valid hex: {}", path.display(), e)))
+}
+
+/// Parse SCALE-encoded metadata, as returned by [`fetch_metadata`] or
+/// [`read_metadata`]
+pub(crate) fn parse_metadata(bytes: &[u8]) -> Result<RuntimeMetadataPrefixed, Error> {
+    RuntimeMetadataPrefixed::decode(&mut &*bytes)
+        .map_err(|e| input_error(format!("Invalid metadata: {}", e)))
+}
+
+/// Find the modules with calls in SCALE-encoded metadata
+fn parse_modules(bytes: &[u8]) -> Result<Vec<Module>, Error> {
+    let metadata = match parse_metadata(bytes)?.1 {
+        RuntimeMetadata::V11(metadata) => metadata,
+        _ => return Err("Unsupported metadata version".to_owned().into()),
+    };
+    let mut modules = vec![];
+    for module in decoded(&metadata.modules)? {
+        let calls = match &module.calls {
+            Some(calls) => decoded(calls)?,
+            None => continue,
+        };
+        let mut functions = vec![];
+        for call in calls {
+            let mut arguments = vec![];
+            for argument in decoded(&call.arguments)? {
+                arguments.push((
+                    decoded(&argument.name)?.clone(),
+                    decoded(&argument.ty)?.clone(),
+                ))
+            }
+            functions.push(Function {
+                name: decoded(&call.name)?.clone(),
+                arguments,
+            })
+        }
+        modules.push(Module {
+            name: decoded(&module.name)?.clone(),
+            calls: functions,
+        })
+    }
+    Ok(modules)
+}
+
+/// The outermost generic type in `ty`, such as `Vec` in `Vec<T::AccountId>`,
+/// and its parameter
+fn split_generic(ty: &str) -> Option<(&str, &str)> {
+    let open = ty.find('<')?;
+    if !ty.ends_with('>') || open == 0 {
+        return None
+    }
+    Some((&ty[..open], &ty[open + 1..ty.len() - 1]))
+}
+
+/// Decode a value of the type named `ty` from `input`
+fn decode_value(
+    modules: &[Module],
+    ty: &str,
+    input: &mut &[u8],
+    network: Ss58AddressFormat,
+) -> Result<Value, Error> {
+    let ty = ty.trim();
+    if ty.ends_with("::Call>") || ty.ends_with("::Call") {
+        return decode_call(modules, input, network)
+    }
+    if let Some((outer, inner)) = split_generic(ty) {
+        match outer {
+            "Vec" if inner == "u8" => {
+                return Ok(format!("0x{}", hex::encode(Vec::<u8>::decode(input)?)).into())
+            }
+            "Vec" => {
+                let len = Compact::<u32>::decode(input)?.0;
+                let mut items = vec![];
+                for _ in 0..len {
+                    items.push(decode_value(modules, inner, input, network)?)
+                }
+                return Ok(items.into())
+            }
+            "Option" => {
+                return match u8::decode(input)? {
+                    0 => Ok(Value::Null),
+                    1 => decode_value(modules, inner, input, network),
+                    _ => Err("Invalid Option".to_owned().into()),
+                }
+            }
+            "Box" => return decode_value(modules, inner, input, network),
+            "Compact" => return Ok(Compact::<u128>::decode(input)?.0.to_string().into()),
+            "BalanceOf" => return Ok(u128::decode(input)?.to_string().into()),
+            "RewardDestination" => return decode_reward_destination(input, network),
+            _ => {}
+        }
+    }
+    Ok(match ty {
+        "bool" => bool::decode(input)?.into(),
+        "u8" => u8::decode(input)?.into(),
+        "u16" => u16::decode(input)?.into(),
+        "u32" | "T::BlockNumber" | "EraIndex" | "SessionIndex" | "Perbill" => {
+            u32::decode(input)?.into()
+        }
+        "u64" => u64::decode(input)?.to_string().into(),
+        "u128" | "T::Balance" | "Balance" => u128::decode(input)?.to_string().into(),
+        "T::AccountId" | "AccountId" | "<T::Lookup as StaticLookup>::Source" => {
+            AccountId::decode(input)?
+                .to_ss58check_with_version(network)
+                .into()
+        }
+        "Bytes" => format!("0x{}", hex::encode(Vec::<u8>::decode(input)?)).into(),
+        "ValidatorPrefs" => json!({ "commission": Compact::<u32>::decode(input)?.0 }),
+        _ => return Err(format!("Cannot decode arguments of type {}", ty).into()),
+    })
+}
+
+fn decode_reward_destination(
+    input: &mut &[u8],
+    network: Ss58AddressFormat,
+) -> Result<Value, Error> {
+    Ok(match u8::decode(input)? {
+        0 => "Staked".into(),
+        1 => "Stash".into(),
+        2 => "Controller".into(),
+        3 => json!({
+            "Account": AccountId::decode(input)?.to_ss58check_with_version(network)
+        }),
+        4 => "None".into(),
+        _ => return Err("Invalid RewardDestination".to_owned().into()),
+    })
+}
+
+/// Decode a call from `input`.  If an argument cannot be decoded, the rest of
+/// `input` is consumed and included as hex.
+fn decode_call(
+    modules: &[Module],
+    input: &mut &[u8],
+    network: Ss58AddressFormat,
+) -> Result<Value, Error> {
+    let (module_index, call_index) = (u8::decode(input)?, u8::decode(input)?);
+    let module = modules
+        .get(usize::from(module_index))
+        .ok_or_else(|| format!("No module with calls at index {}", module_index))?;
+    let function = module
+        .calls
+        .get(usize::from(call_index))
+        .ok_or_else(|| format!("Module {} has no call at index {}", module.name, call_index))?;
+    let mut arguments = vec![];
+    let mut undecoded = None;
+    for (name, ty) in &function.arguments {
+        match decode_value(modules, ty, input, network) {
+            Ok(value) => arguments.push(json!({ "name": name, "value": value })),
+            Err(e) => {
+                log::debug!("Stopped decoding at argument {}: {}", name, e);
+                undecoded = Some(format!("0x{}", hex::encode(*input)));
+                *input = &[];
+                break
+            }
+        }
+    }
+    Ok(json!({
+        "module": module.name,
+        "function": function.name,
+        "arguments": arguments,
+        "undecoded": undecoded,
+    }))
+}
+
+/// Decode a signed or unsigned extrinsic, with or without its length prefix
+fn decode_extrinsic(
+    modules: &[Module],
+    mut input: &[u8],
+    network: Ss58AddressFormat,
+) -> Result<Value, Error> {
+    let input = &mut input;
+    {
+        let mut prefixed = *input;
+        if let Ok(len) = Compact::<u32>::decode(&mut prefixed) {
+            if usize::try_from(len.0).ok() == Some(prefixed.len()) {
+                *input = prefixed
+            }
+        }
+    }
+    let version = u8::decode(input)?;
+    if version & 0x7F != 4 {
+        return Err(input_error(format!(
+            "Unsupported extrinsic version {}",
+            version & 0x7F
+        )))
+    }
+    let signature = if version & 0x80 == 0 {
+        Value::Null
+    } else {
+        let signer = AccountId::decode(input)?.to_ss58check_with_version(network);
+        let signature_len = match u8::decode(input)? {
+            0 | 1 => 64,
+            2 => 65,
+            _ => return Err(input_error("Invalid signature type")),
+        };
+        if input.len() < signature_len {
+            return Err(input_error("Extrinsic too short"))
+        }
+        *input = &input[signature_len..];
+        // An immortal era is one zero byte; a mortal era is two bytes
+        let mortal = u8::decode(input)? != 0;
+        if mortal {
+            u8::decode(input)?;
+        }
+        let nonce = Compact::<u32>::decode(input)?.0;
+        let tip = Compact::<u128>::decode(input)?.0;
+        json!({
+            "signer": signer,
+            "nonce": nonce,
+            "tip": tip.to_string(),
+            "mortal": mortal,
+        })
+    };
+    let call = decode_call(modules, input, network)?;
+    if !input.is_empty() {
+        return Err(input_error("Trailing data after the call"))
+    }
+    Ok(json!({ "signature": signature, "call": call }))
+}
+
+/// Render a decoded call as text
+fn render_call(call: &Value, indent: usize) -> String {
+    let mut output = format!(
+        "{}.{}\n",
+        call["module"].as_str().unwrap_or("?"),
+        call["function"].as_str().unwrap_or("?")
+    );
+    for argument in call["arguments"].as_array().into_iter().flatten() {
+        let value = &argument["value"];
+        let rendered = if value.get("module").is_some() {
+            render_call(value, indent + 4)
+        } else if let Some(calls) = value
+            .as_array()
+            .filter(|calls| calls.iter().all(|i| i.get("module").is_some()) && !calls.is_empty())
+        {
+            let mut rendered = "\n".to_owned();
+            for call in calls {
+                rendered += &format!(
+                    "{:indent$}- {}",
+                    "",
+                    render_call(call, indent + 8),
+                    indent = indent + 4
+                );
+            }
+            rendered
+        } else {
+            format!(
+                "{}\n",
+                value
+                    .as_str()
+                    .map_or_else(|| value.to_string(), str::to_owned)
+            )
+        };
+        output += &format!(
+            "{:indent$}{}: {}",
+            "",
+            argument["name"].as_str().unwrap_or("?"),
+            rendered,
+            indent = indent + 4
+        );
+    }
+    if let Some(undecoded) = call["undecoded"].as_str() {
+        output += &format!(
+            "{:indent$}(not decoded): {}\n",
+            "",
+            undecoded,
+            indent = indent + 4
+        );
+    }
+    output
+}
+
+/// Decode and print `extrinsic`, looking up its call in `metadata`
+pub(crate) fn main(
+    metadata: &[u8],
+    extrinsic: &str,
+    network: Ss58AddressFormat,
+    output: OutputFormat,
+) -> Result<(), Error> {
+    let extrinsic = hex::decode(extrinsic.strip_prefix("0x").unwrap_or(extrinsic))
+        .map_err(|e| input_error(format!("Invalid hex: {}", e)))?;
+    let modules = parse_modules(metadata)?;
+    let decoded = decode_extrinsic(&modules, &extrinsic, network)?;
+    if output == OutputFormat::Jsonl {
+        println!("{}", decoded);
+        return Ok(())
+    }
+    match decoded["signature"].as_object() {
+        None => println!("Unsigned"),
+        Some(signature) => println!(
+            "Signed by: {}\nNonce: {}\nTip: {}\nMortal: {}",
+            signature["signer"].as_str().unwrap_or("?"),
+            signature["nonce"],
+            signature["tip"].as_str().unwrap_or("?"),
+            signature["mortal"],
+        ),
+    }
+    print!("Call: {}", render_call(&decoded["call"], 0));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codec::Encode;
+
+    fn modules() -> Vec<Module> {
+        let function = |name: &str, arguments: &[(&str, &str)]| Function {
+            name: name.to_owned(),
+            arguments: arguments
+                .iter()
+                .map(|&(name, ty)| (name.to_owned(), ty.to_owned()))
+                .collect(),
+        };
+        vec![
+            Module {
+                name: "Utility".to_owned(),
+                calls: vec![function("batch", &[("calls", "Vec<<T as Trait>::Call>")])],
+            },
+            Module {
+                name: "Staking".to_owned(),
+                calls: vec![
+                    function("chill", &[]),
+                    function("nominate", &[(
+                        "targets",
+                        "Vec<<T::Lookup as StaticLookup>::Source>",
+                    )]),
+                    function("set_keys", &[("keys", "T::Keys"), ("proof", "Vec<u8>")]),
+                ],
+            },
+        ]
+    }
+
+    #[test]
+    fn decodes_unsigned_batch() {
+        let target = AccountId::from([1; 32]);
+        let mut call = vec![1_u8, 1];
+        call.extend(vec![target.clone()].encode());
+        let mut extrinsic = vec![4_u8, 0, 0];
+        extrinsic.extend(Compact(2_u32).encode());
+        extrinsic.extend(&call);
+        extrinsic.extend(&[1, 0]);
+        let extrinsic = extrinsic.encode();
+        let decoded =
+            decode_extrinsic(&modules(), &extrinsic, Ss58AddressFormat::KusamaAccount).unwrap();
+        assert_eq!(decoded["signature"], Value::Null);
+        assert_eq!(decoded["call"]["function"], "batch");
+        let calls = &decoded["call"]["arguments"][0]["value"];
+        assert_eq!(calls[0]["module"], "Staking");
+        assert_eq!(calls[0]["function"], "nominate");
+        assert_eq!(
+            calls[0]["arguments"][0]["value"][0],
+            target.to_ss58check_with_version(Ss58AddressFormat::KusamaAccount)
+        );
+        assert_eq!(calls[1]["function"], "chill");
+    }
+
+    #[test]
+    fn stops_at_unknown_types() {
+        let decoded = decode_extrinsic(
+            &modules(),
+            &[4, 1, 2, 0xAB, 0xCD],
+            Ss58AddressFormat::KusamaAccount,
+        )
+        .unwrap();
+        assert_eq!(decoded["call"]["function"], "set_keys");
+        assert_eq!(decoded["call"]["arguments"], json!([]));
+        assert_eq!(decoded["call"]["undecoded"], "0xabcd");
+    }
+
+    #[test]
+    fn rejects_unknown_calls() {
+        assert!(
+            decode_extrinsic(&modules(), &[4, 1, 9], Ss58AddressFormat::KusamaAccount).is_err()
+        );
+        assert!(
+            decode_extrinsic(&modules(), &[4, 5, 0], Ss58AddressFormat::KusamaAccount).is_err()
+        );
+    }
+}
@@ -0,0 +1,232 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of ledgeracio.
+//
+// ledgeracio is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ledgeracio is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ledgeracio.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Commands that use the Ledger device.  Except for `reconcile`, these do not
+//! require a network connection.
+
+use super::{common::OutputFormat,
+            connection::Connector,
+            labels::{account_type_name, Labels},
+            nominator::load_allowlist,
+            parse_reward_destination,
+            payee::Payee,
+            reconcile::{self, Expectations},
+            AccountId, AccountType, Error, LedgeracioPath, StructOpt};
+use core::future::Future;
+use ledgeracio::{input_error, parse_address, validate_network};
+use std::path::PathBuf;
+use substrate_subxt::{sp_core::crypto::{Ss58AddressFormat, Ss58Codec},
+                      Signer};
+
+#[derive(StructOpt, Debug)]
+pub(crate) enum DeviceCommand {
+    /// List the derivation path and address of every nominator and validator
+    /// account from index 1 up to `--max-index`.  With `--output CSV`, the
+    /// list is a CSV table.
+    Addresses {
+        /// The highest index to list
+        #[structopt(long)]
+        max_index: u32,
+    },
+    /// Check whether the device derives the given address, by deriving every
+    /// nominator and validator account from index 1 up to `--max-index`.
+    /// Fails if none of them is the address.
+    Owns {
+        #[structopt(parse(try_from_str = parse_address))]
+        address: (AccountId, u8),
+        /// The highest index to check
+        #[structopt(long, default_value = "100")]
+        max_index: u32,
+    },
+    /// Open the app for `--network` (or `--app-name`) if the device is on its
+    /// dashboard.  Does nothing if the app is already open.
+    OpenApp,
+    /// Close the app open on the device, returning it to the dashboard
+    QuitApp,
+    /// Check the staking state of every nominator and validator account on
+    /// the device, and report each way in which it differs from what is
+    /// expected.  Fails if any account has drifted.
+    Reconcile {
+        /// The highest index to check.  By default, accounts are checked up
+        /// to the first with no free balance.
+        #[structopt(long)]
+        max_index: Option<u32>,
+        /// The reward destination every account should have
+        #[structopt(long, parse(try_from_str = parse_reward_destination))]
+        payee: Option<Payee<AccountId>>,
+        /// Flag nominated targets that are not on this signed allowlist,
+        /// verified with the given public key
+        #[structopt(
+            long,
+            number_of_values = 2,
+            value_names = &["ALLOWLIST", "PUBLIC"],
+            parse(from_os_str)
+        )]
+        allowlist: Vec<PathBuf>,
+    },
+}
+
+impl DeviceCommand {
+    /// The addresses given as arguments, with the networks they were encoded
+    /// for
+    pub(crate) fn addresses(&self) -> Vec<&(AccountId, u8)> {
+        match self {
+            Self::Owns { address, .. } => vec![address],
+            Self::Addresses { .. } | Self::OpenApp | Self::QuitApp | Self::Reconcile { .. } => {
+                vec![]
+            }
+        }
+    }
+}
+
+pub(crate) async fn main<T: FnOnce() -> U, U: Future<Output = Result<super::HardStore, Error>>>(
+    cmd: DeviceCommand,
+    connector: &Connector,
+    network: Ss58AddressFormat,
+    keystore: T,
+    output: OutputFormat,
+    app_name: Option<&str>,
+) -> Result<(), Error> {
+    match cmd {
+        DeviceCommand::OpenApp => {
+            super::HardStore::open_app(network, app_name).await?;
+            println!("{} is open", super::HardStore::app_info().await?.name);
+            Ok(())
+        }
+        DeviceCommand::QuitApp => {
+            match super::HardStore::quit_app().await? {
+                Some(name) => println!("Closed {}", name),
+                None => println!("No app is open"),
+            }
+            Ok(())
+        }
+        DeviceCommand::Reconcile {
+            max_index,
+            payee,
+            allowlist,
+        } => {
+            if max_index == Some(0) {
+                return Err(input_error("--max-index must not be zero"))
+            }
+            let allowlist = match &*allowlist {
+                [] => None,
+                [allowlist, public] => Some(load_allowlist(allowlist, public, network)?),
+                _ => return Err(input_error("--allowlist may only be passed once")),
+            };
+            let indices: Vec<u32> = max_index.map_or_else(Vec::new, |max| (1..=max).collect());
+            let keystore = keystore().await?;
+            let client = connector.connect_staking().await?;
+            let expected = Expectations { payee, allowlist };
+            reconcile::report(&client, &keystore, &indices, &expected, network, output).await
+        }
+        DeviceCommand::Addresses { max_index } => {
+            if max_index == 0 {
+                return Err(input_error("--max-index must not be zero"))
+            }
+            if ledgeracio::confirms_on_device_only() {
+                return Err(input_error(
+                    "device addresses cannot be used with --confirm-on-device-only",
+                ))
+            }
+            let keystore = keystore().await?;
+            let labels = Labels::load_or_warn();
+            if output == OutputFormat::Csv {
+                println!("derivation_path,account_type,index,address");
+            }
+            for &account_type in &[AccountType::Nominator, AccountType::Validator] {
+                for index in 1..=max_index {
+                    let path = LedgeracioPath::new(network, account_type, index)?;
+                    let address = keystore
+                        .signer(path.clone())
+                        .await?
+                        .account_id()
+                        .to_ss58check_with_version(network);
+                    let account_type_name = account_type_name(account_type);
+                    let label = labels.get(network, account_type, index);
+                    match output {
+                        // Neither paths nor addresses contain commas or quotes,
+                        // so no field needs quoting.
+                        OutputFormat::Csv => {
+                            println!("{},{},{},{}", path, account_type_name, index, address)
+                        }
+                        OutputFormat::Jsonl => println!(
+                            "{}",
+                            serde_json::json!({
+                                "derivation_path": path.to_string(),
+                                "account_type": account_type_name,
+                                "index": index,
+                                "address": address,
+                                "label": label,
+                            })
+                        ),
+                        OutputFormat::Text => println!(
+                            "{}  {:<9}  {:>5}  {}{}",
+                            path,
+                            account_type_name,
+                            index,
+                            address,
+                            label
+                                .map(|label| format!(" [{}]", label))
+                                .unwrap_or_default()
+                        ),
+                    }
+                }
+            }
+            Ok(())
+        }
+        DeviceCommand::Owns {
+            address: (address, provided_network),
+            max_index,
+        } => {
+            validate_network("", provided_network, network)?;
+            let keystore = keystore().await?;
+            for &account_type in &[AccountType::Nominator, AccountType::Validator] {
+                for index in 1..=max_index {
+                    let path = LedgeracioPath::new(network, account_type, index)?;
+                    if *keystore.signer(path.clone()).await?.account_id() != address {
+                        continue
+                    }
+                    let account_type_name = account_type_name(account_type);
+                    if output == OutputFormat::Jsonl {
+                        println!(
+                            "{}",
+                            serde_json::json!({
+                                "derivation_path": path.to_string(),
+                                "account_type": account_type_name,
+                                "index": index,
+                            })
+                        )
+                    } else {
+                        println!(
+                            "{} is {} account {}, derived at {}",
+                            address.to_ss58check_with_version(network),
+                            account_type_name,
+                            index,
+                            path
+                        )
+                    }
+                    return Ok(())
+                }
+            }
+            Err(format!(
+                "{} is not derived by this device at any nominator or validator index up to {}",
+                address.to_ss58check_with_version(network),
+                max_index
+            )
+            .into())
+        }
+    }
+}
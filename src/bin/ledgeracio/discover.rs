@@ -0,0 +1,228 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of ledgeracio.
+//
+// ledgeracio is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ledgeracio is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ledgeracio.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Listing the validators of the whole network, such as to pick candidates
+//! for an allowlist
+
+use super::{common::{active_era, format_balance, format_table, pad, parse_tokens, OutputFormat,
+                     TokenOverrides},
+            shares::ErasStakersStore,
+            AccountId, Error};
+use futures::stream::{self, StreamExt as _};
+use std::convert::TryFrom;
+use substrate_subxt::{sp_core::crypto::{Ss58AddressFormat, Ss58Codec},
+                      staking::ValidatorsStore,
+                      Client, KusamaRuntime};
+
+/// How many exposures are fetched at once
+const CONCURRENT_FETCHES: usize = 16;
+
+/// A validator and its stake in the active era
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Candidate {
+    stash: AccountId,
+    /// The commission, in parts per billion
+    commission: u32,
+    /// The total stake, in planck
+    total: u128,
+    /// The validator’s own stake, in planck
+    own: u128,
+    /// How many nominators back the validator
+    nominators: usize,
+}
+
+impl Candidate {
+    /// Whether the validator is in the active set of the era
+    fn active(&self) -> bool { self.total > 0 }
+}
+
+/// Which validators to list
+#[derive(Clone, Debug, Default)]
+struct Filter {
+    /// The smallest total stake, in planck
+    min_stake: Option<u128>,
+    /// The largest commission, in parts per billion
+    max_commission: Option<u32>,
+    /// Only list validators in the active set
+    active: bool,
+}
+
+impl Filter {
+    fn accepts(&self, candidate: &Candidate) -> bool {
+        self.min_stake.map_or(true, |min| candidate.total >= min)
+            && self
+                .max_commission
+                .map_or(true, |max| candidate.commission <= max)
+            && (!self.active || candidate.active())
+    }
+}
+
+/// Fetch every validator that has announced its intention to validate, with
+/// its stake in `era`
+async fn fetch_candidates(
+    client: &Client<KusamaRuntime>,
+    era: u32,
+) -> Result<Vec<Candidate>, Error> {
+    let mut validators = vec![];
+    let mut iter = client.iter::<ValidatorsStore<KusamaRuntime>>(None).await?;
+    while let Some((key, prefs)) = iter.next().await? {
+        // The map is hashed with `Twox64Concat`, so the key ends with the stash
+        let stash = key
+            .0
+            .len()
+            .checked_sub(32)
+            .and_then(|start| <[u8; 32]>::try_from(&key.0[start..]).ok())
+            .ok_or_else(|| "Malformed key in Staking.Validators".to_owned())?;
+        validators.push((AccountId::from(stash), prefs.commission.deconstruct()))
+    }
+    stream::iter(validators)
+        .map(|(stash, commission)| async move {
+            let store = ErasStakersStore {
+                era,
+                validator: stash.clone(),
+            };
+            let exposure = client.fetch(&store, None).await?;
+            Ok::<_, Error>(Candidate {
+                stash,
+                commission,
+                total: exposure.as_ref().map_or(0, |exposure| exposure.total.0),
+                own: exposure.as_ref().map_or(0, |exposure| exposure.own.0),
+                nominators: exposure.map_or(0, |exposure| exposure.others.len()),
+            })
+        })
+        .buffered(CONCURRENT_FETCHES)
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect()
+}
+
+/// List the validators of the network that pass the filters, by decreasing
+/// total stake.  `min_stake` is in tokens.
+pub(crate) async fn main(
+    client: &Client<KusamaRuntime>,
+    min_stake: Option<&str>,
+    max_commission: Option<u32>,
+    active: bool,
+    network: Ss58AddressFormat,
+    output: OutputFormat,
+    token: &TokenOverrides,
+) -> Result<(), Error> {
+    let (token_decimals, token_symbol) = token.resolve(client);
+    let filter = Filter {
+        min_stake: min_stake
+            .map(|min_stake| parse_tokens(min_stake, token_decimals))
+            .transpose()?,
+        max_commission,
+        active,
+    };
+    let era = active_era(client).await?;
+    let mut candidates = fetch_candidates(client, era).await?;
+    let total = candidates.len();
+    candidates.retain(|candidate| filter.accepts(candidate));
+    candidates.sort_by(|a, b| b.total.cmp(&a.total));
+    let mut rows = vec![[
+        "STASH",
+        "COMMISSION",
+        "TOTAL",
+        "OWN",
+        "NOMINATORS",
+        "ACTIVE",
+    ]
+    .iter()
+    .map(|&header| header.to_owned())
+    .collect::<Vec<_>>()];
+    if output == OutputFormat::Csv {
+        println!("stash,commission_percent,total_stake,own_stake,nominators,active");
+    }
+    for candidate in &candidates {
+        let stash = candidate.stash.to_ss58check_with_version(network);
+        let commission = pad(7, candidate.commission.into());
+        match output {
+            OutputFormat::Csv => println!(
+                "{},{},{},{},{},{}",
+                stash,
+                commission,
+                candidate.total,
+                candidate.own,
+                candidate.nominators,
+                candidate.active()
+            ),
+            OutputFormat::Jsonl => println!(
+                "{}",
+                serde_json::json!({
+                    "stash": stash,
+                    "commission": candidate.commission,
+                    "total_stake": candidate.total.to_string(),
+                    "own_stake": candidate.own.to_string(),
+                    "nominators": candidate.nominators,
+                    "active": candidate.active(),
+                })
+            ),
+            OutputFormat::Text => rows.push(vec![
+                stash,
+                format!("{}%", commission),
+                format_balance((token_decimals, &token_symbol), candidate.total),
+                format_balance((token_decimals, &token_symbol), candidate.own),
+                candidate.nominators.to_string(),
+                if candidate.active() { "yes" } else { "no" }.to_owned(),
+            ]),
+        }
+    }
+    if output == OutputFormat::Text {
+        print!("{}", format_table(&rows, &[1, 2, 3, 4]));
+        println!(
+            "{} of {} validators match, based on era {}",
+            candidates.len(),
+            total,
+            era
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(commission: u32, total: u128) -> Candidate {
+        Candidate {
+            stash: [1; 32].into(),
+            commission,
+            total,
+            own: 0,
+            nominators: 0,
+        }
+    }
+
+    #[test]
+    fn filters_candidates() {
+        let filter = Filter {
+            min_stake: Some(100),
+            max_commission: Some(50_000_000),
+            active: false,
+        };
+        assert!(filter.accepts(&candidate(50_000_000, 100)));
+        assert!(!filter.accepts(&candidate(50_000_001, 100)));
+        assert!(!filter.accepts(&candidate(0, 99)));
+        let filter = Filter {
+            active: true,
+            ..Filter::default()
+        };
+        assert!(filter.accepts(&candidate(1_000_000_000, 1)));
+        assert!(!filter.accepts(&candidate(0, 0)));
+    }
+}
@@ -0,0 +1,211 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of ledgeracio.
+//
+// ledgeracio is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ledgeracio is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ledgeracio.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Diagnostics for the Ledger device and the RPC node
+
+use super::{Error, HardStore};
+use codec::Encode;
+use core::{future::Future, pin::Pin};
+use std::{marker::PhantomData,
+          time::{SystemTime, UNIX_EPOCH}};
+use substrate_subxt::{module, sp_core::crypto::Ss58AddressFormat, system::System, Client,
+                      KusamaRuntime, Store};
+
+/// How far the latest block’s timestamp may lag behind the local clock before
+/// the node is considered out of sync, in milliseconds
+const MAX_LAG_MS: u64 = 5 * 60 * 1000;
+
+/// The oldest app version that Ledgeracio supports, as major, minor, and
+/// patch numbers
+const MIN_APP_VERSION: (u16, u16, u16) = (1, 0, 0);
+
+/// The timestamp pallet
+#[module]
+pub trait Timestamp: System {}
+
+impl Timestamp for KusamaRuntime {}
+
+/// The timestamp of the current block, in milliseconds since the Unix epoch
+#[derive(Clone, Debug, Eq, PartialEq, Store, Encode)]
+pub struct NowStore<T: Timestamp> {
+    #[store(returns = u64)]
+    pub _runtime: PhantomData<T>,
+}
+
+/// Print the result of a check, and return whether it passed
+fn report(check: &str, result: Result<String, Error>) -> bool {
+    match result {
+        Ok(detail) => {
+            println!("[ OK ] {}: {}", check, detail);
+            true
+        }
+        Err(e) => {
+            println!("[FAIL] {}: {}", check, e);
+            false
+        }
+    }
+}
+
+fn skip(check: &str) { println!("[SKIP] {}", check) }
+
+/// Check that the app version `version`, as returned by
+/// [`HardStore::version`], is at least [`MIN_APP_VERSION`]
+fn check_app_version(version: String) -> Result<String, Error> {
+    let mut parts = version.split('.').map(str::parse::<u16>);
+    let parsed = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(Ok(major)), Some(Ok(minor)), Some(Ok(patch)), None) => (major, minor, patch),
+        _ => return Err(format!("Cannot parse app version {}", version).into()),
+    };
+    if parsed < MIN_APP_VERSION {
+        let (major, minor, patch) = MIN_APP_VERSION;
+        Err(format!(
+            "App version {} is too old — update it to {}.{}.{} or later with Ledger Live.",
+            version, major, minor, patch
+        )
+        .into())
+    } else {
+        Ok(version)
+    }
+}
+
+async fn check_device(network: Ss58AddressFormat, app_name: Option<&str>) -> bool {
+    let info = match HardStore::app_info().await {
+        Ok(info) => info,
+        Err(e) => {
+            report("Ledger device connected", Err(e));
+            skip("Ledger device unlocked");
+            skip("Correct app open");
+            skip("App version");
+            skip("Allowlist signing key set");
+            return false
+        }
+    };
+    let mut ok = report("Ledger device connected", Ok("yes".to_owned()));
+    ok &= report(
+        "Ledger device unlocked",
+        if info.pin_validated {
+            Ok("yes".to_owned())
+        } else {
+            Err("Your Ledger is locked ― enter your PIN and try again."
+                .to_owned()
+                .into())
+        },
+    );
+    ok &= report(
+        "Correct app open",
+        info.check(network, app_name)
+            .map(|()| format!("{} {}", info.name, info.version)),
+    );
+    let keystore = match HardStore::new(network, app_name).await {
+        Ok(keystore) => keystore,
+        Err(_) => {
+            skip("App version");
+            skip("Allowlist signing key set");
+            return false
+        }
+    };
+    ok &= report(
+        "App version",
+        keystore.version().await.and_then(check_app_version),
+    );
+    // The stock apps do not support allowlists, so this is not a failure.
+    match keystore.get_pubkey().await {
+        Ok(key) => {
+            report("Allowlist signing key set", Ok(base64::encode(key)));
+        }
+        Err(e) => println!("[WARN] Allowlist signing key set: {}", e),
+    }
+    ok
+}
+
+async fn check_synced(client: &Client<KusamaRuntime>) -> Result<String, Error> {
+    let now = client
+        .fetch_or_default(
+            &NowStore {
+                _runtime: PhantomData,
+            },
+            None,
+        )
+        .await?;
+    let local = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    let lag = local.saturating_sub(now.into());
+    if lag > MAX_LAG_MS.into() {
+        Err(format!("latest block is {} seconds old", lag / 1000).into())
+    } else {
+        Ok(format!("latest block is {} seconds old", lag / 1000))
+    }
+}
+
+async fn check_node(
+    client: Pin<Box<dyn Future<Output = Result<Client<KusamaRuntime>, Error>>>>,
+) -> bool {
+    let client = match client.await {
+        Ok(client) => client,
+        Err(e) => {
+            report("RPC endpoint reachable", Err(e));
+            skip("Chain metadata downloaded");
+            skip("Node synced");
+            return false
+        }
+    };
+    let mut ok = report("RPC endpoint reachable", Ok("yes".to_owned()));
+    ok &= report(
+        "Chain metadata downloaded",
+        client
+            .metadata()
+            .module("Staking")
+            .map(|_| "staking pallet present".to_owned())
+            .map_err(From::from),
+    );
+    ok &= report("Node synced", check_synced(&client).await);
+    ok
+}
+
+/// Check that the Ledger device and the RPC node are ready for use, printing a
+/// checklist of the results.
+pub(crate) async fn main(
+    client: Pin<Box<dyn Future<Output = Result<Client<KusamaRuntime>, Error>>>>,
+    network: Ss58AddressFormat,
+    app_name: Option<&str>,
+) -> Result<(), Error> {
+    let device_ok = check_device(network, app_name).await;
+    let node_ok = check_node(client).await;
+    if device_ok && node_ok {
+        Ok(())
+    } else {
+        Err("Some checks failed".to_owned().into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_app_version() {
+        let (major, minor, patch) = MIN_APP_VERSION;
+        let min = format!("{}.{}.{}", major, minor, patch);
+        assert_eq!(check_app_version(min.clone()).unwrap(), min);
+        let newer = format!("{}.{}.0", major, minor + 1);
+        assert_eq!(check_app_version(newer.clone()).unwrap(), newer);
+        let newer = format!("{}.0.0", major + 1);
+        assert!(check_app_version(newer).is_ok());
+        assert!(check_app_version("0.99.99".to_owned()).is_err());
+        assert!(check_app_version("1.0".to_owned()).is_err());
+        assert!(check_app_version("1.0.0.0".to_owned()).is_err());
+        assert!(check_app_version("1.x.0".to_owned()).is_err());
+    }
+}
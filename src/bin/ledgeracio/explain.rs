@@ -0,0 +1,563 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of ledgeracio.
+//
+// ledgeracio is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ledgeracio is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ledgeracio.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Plain-English descriptions of commands, for `--explain`
+//!
+//! Nothing here touches the device or the network: the description is built
+//! from the arguments alone.
+
+use super::{common::AccountRef,
+            device::DeviceCommand,
+            labels::{account_type_name, LabelCommand},
+            nominator::Nominator,
+            pools::PoolCommand,
+            proxy::ProxyCommand,
+            validator::Validator,
+            AccountId, AccountType, Command, Error, LedgeracioPath};
+use substrate_subxt::sp_core::crypto::{Ss58AddressFormat, Ss58Codec};
+
+/// What a command does, and what it touches
+struct Explanation {
+    /// What the command does
+    operation: String,
+    /// The accounts involved, with their derivation paths where known
+    accounts: Vec<String>,
+    /// The chain state the command reads
+    reads: &'static str,
+    /// The chain state changed by the transaction the command submits, if any
+    changes: Option<&'static str>,
+}
+
+impl Explanation {
+    /// The explanation, as printed.  With `emit_call`, the transaction is
+    /// only printed, not signed.
+    fn render(&self, emit_call: bool) -> String {
+        let mut text = format!("What it does: {}\n", self.operation);
+        if self.accounts.is_empty() {
+            text += "Accounts: none\n";
+        } else {
+            text += "Accounts:\n";
+            for account in &self.accounts {
+                text += &format!("    {}\n", account);
+            }
+        }
+        text += &format!("Reads: {}\n", self.reads);
+        text += &match self.changes {
+            Some(changes) if emit_call => format!(
+                "Changes: nothing yet.  With --emit-call, the call is printed for signing \
+                 elsewhere.  Once submitted, it changes {}",
+                changes
+            ),
+            Some(changes) => format!(
+                "Changes: {}\nThe transaction is shown on the Ledger device, and is only signed \
+                 and submitted if you approve it there.",
+                changes
+            ),
+            None => "Changes: nothing.  No transaction is signed.".to_owned(),
+        };
+        text
+    }
+}
+
+/// A device account, with its derivation path
+fn device_account(
+    network: Ss58AddressFormat,
+    account_type: AccountType,
+    index: u32,
+) -> Result<String, Error> {
+    Ok(format!(
+        "{} account {}, derived at {}",
+        account_type_name(account_type),
+        index,
+        LedgeracioPath::new(network, account_type, index)?.slip44_notation()
+    ))
+}
+
+/// The accounts at `indices`, or a description of the scan if there are none
+fn device_accounts(
+    network: Ss58AddressFormat,
+    account_type: AccountType,
+    indices: &[u32],
+) -> Result<Vec<String>, Error> {
+    if indices.is_empty() {
+        return Ok(vec![format!(
+            "every {} account on the device, from index 1 up to the first that is not bonded",
+            account_type_name(account_type)
+        )])
+    }
+    indices
+        .iter()
+        .map(|&index| device_account(network, account_type, index))
+        .collect()
+}
+
+/// The account that signs a transaction for `account`, given as in
+/// `nominate`, `chill`, and `set-payee`
+fn signing_account(
+    network: Ss58AddressFormat,
+    account_type: AccountType,
+    account: Option<&AccountRef>,
+    stash: bool,
+) -> Result<String, Error> {
+    let name = account_type_name(account_type);
+    Ok(match (account, stash) {
+        (None, _) => format!("a {} account, picked interactively", name),
+        (Some(AccountRef::Index(index)), false) => device_account(network, account_type, *index)?,
+        (Some(AccountRef::Index(index)), true) => format!(
+            "the controller of the stash {}, which must also be on the device",
+            device_account(network, account_type, *index)?
+        ),
+        (Some(AccountRef::Address((address, _))), false) => format!(
+            "the {} account on the device with address {}",
+            name,
+            address.to_ss58check_with_version(network)
+        ),
+        (Some(AccountRef::Address((address, _))), true) => format!(
+            "the controller of the stash {}, which must be a {} account on the device",
+            address.to_ss58check_with_version(network),
+            name
+        ),
+    })
+}
+
+fn address(network: Ss58AddressFormat, address: &AccountId) -> String {
+    address.to_ss58check_with_version(network)
+}
+
+fn nominator(cmd: &Nominator, network: Ss58AddressFormat) -> Result<Explanation, Error> {
+    const SHOW_READS: &str = "the staking ledger, payee, nominations, and unclaimed payouts of \
+                              each account, and the identities of its targets";
+    let nominator = AccountType::Nominator;
+    Ok(match cmd {
+        Nominator::ShowAddress {
+            address: (account, _),
+            ..
+        } => Explanation {
+            operation: "show the staking status of a nominator".to_owned(),
+            accounts: vec![address(network, account)],
+            reads: SHOW_READS,
+            changes: None,
+        },
+        Nominator::Show { indices, .. } => Explanation {
+            operation: "show the staking status of nominator accounts on the device".to_owned(),
+            accounts: device_accounts(network, nominator, indices)?,
+            reads: SHOW_READS,
+            changes: None,
+        },
+        Nominator::Audit { threshold } => Explanation {
+            operation: format!(
+                "report nominators whose targets overlap by at least {}%",
+                threshold
+            ),
+            accounts: device_accounts(network, nominator, &[])?,
+            reads: "the nominations of each account",
+            changes: None,
+        },
+        Nominator::Nominate {
+            index, set, stash, ..
+        } => Explanation {
+            operation: format!(
+                "nominate {} validators, replacing any current nominations",
+                set.len()
+            ),
+            accounts: std::iter::once(signing_account(network, nominator, Some(index), *stash)?)
+                .chain(
+                    set.iter()
+                        .map(|(target, _)| format!("target {}", address(network, target))),
+                )
+                .collect(),
+            reads: "the staking ledger of the signer, and whether each target is a registered \
+                    validator",
+            changes: Some(
+                "the nominations of the signer’s stash (Staking.Nominators), which take effect at \
+                 the next election",
+            ),
+        },
+        Nominator::Rewards { index, eras } => Explanation {
+            operation: format!(
+                "chart the rewards earned in the last {} completed eras",
+                eras
+            ),
+            accounts: vec![match index {
+                Some(index) => device_account(network, nominator, *index)?,
+                None => "a nominator account, picked interactively".to_owned(),
+            }],
+            reads: "the nominations of the account, and the exposure, reward points, and \
+                    commission of each target in each era",
+            changes: None,
+        },
+        Nominator::Chill { index, all, stash } => Explanation {
+            operation: "stop nominating, while keeping the funds bonded".to_owned(),
+            accounts: vec![if *all {
+                "every nominator account on the device that is nominating, each signing its own \
+                 transaction"
+                    .to_owned()
+            } else {
+                signing_account(network, nominator, index.as_ref(), *stash)?
+            }],
+            reads: "the staking ledger and nominations of each account, and whether it can pay \
+                    the fee",
+            changes: Some("the nominations of the stash, which are removed (Staking.Nominators)"),
+        },
+        Nominator::SetPayee {
+            index,
+            target,
+            stash,
+            ..
+        } => Explanation {
+            operation: format!("set the reward destination to {:?}", target),
+            accounts: vec![signing_account(network, nominator, Some(index), *stash)?],
+            reads: "the staking ledger of the signer, and the current payee of its stash",
+            changes: Some(
+                "where future rewards of the stash are paid (Staking.Payee).  Nothing is \
+                 submitted if the payee is already the target, unless --force is given.",
+            ),
+        },
+        Nominator::Address { index, .. } => Explanation {
+            operation: "display the address of an account on the device".to_owned(),
+            accounts: vec![match index {
+                Some(index) => device_account(network, nominator, *index)?,
+                None => "a nominator account, picked interactively".to_owned(),
+            }],
+            reads: "nothing from the chain",
+            changes: None,
+        },
+    })
+}
+
+fn validator(cmd: &Validator, network: Ss58AddressFormat) -> Result<Explanation, Error> {
+    const SHOW_READS: &str =
+        "the staking ledger, payee, preferences, exposure, and unclaimed payouts of each validator";
+    let validator = AccountType::Validator;
+    Ok(match cmd {
+        Validator::ShowAddress {
+            address: (account, _),
+            ..
+        } => Explanation {
+            operation: "show the status of a validator".to_owned(),
+            accounts: vec![address(network, account)],
+            reads: SHOW_READS,
+            changes: None,
+        },
+        Validator::Show { indices, .. } => Explanation {
+            operation: "show the status of validator accounts on the device".to_owned(),
+            accounts: device_accounts(network, validator, indices)?,
+            reads: SHOW_READS,
+            changes: None,
+        },
+        Validator::List { .. } => Explanation {
+            operation: "list every validator on the network by total stake".to_owned(),
+            accounts: vec![],
+            reads: "the registered validators, their preferences, and their exposure in the \
+                    current era",
+            changes: None,
+        },
+        Validator::Announce { index, commission } => Explanation {
+            operation: format!(
+                "announce the intention to validate, with a commission of {}%",
+                commission.map_or(100.0, |ppb| f64::from(ppb) / 10_000_000.0)
+            ),
+            accounts: vec![device_account(network, validator, *index)?],
+            reads: "the staking ledger of the account",
+            changes: Some(
+                "the validator preferences of the stash (Staking.Validators), making it a \
+                 candidate from the next election",
+            ),
+        },
+        Validator::Chill { index, stash } => Explanation {
+            operation: "stop validating, while keeping the funds bonded".to_owned(),
+            accounts: vec![signing_account(network, validator, index.as_ref(), *stash)?],
+            reads: "the staking ledger of the account",
+            changes: Some("the validator preferences of the stash, which are removed"),
+        },
+        Validator::Payout { index, era } => Explanation {
+            operation: match era {
+                Some(era) => format!(
+                    "pay out the rewards of a validator and its nominators for era {}",
+                    era
+                ),
+                None => "pay out the rewards of a validator and its nominators for every era with \
+                         unclaimed rewards, one transaction each"
+                    .to_owned(),
+            },
+            accounts: vec![device_account(network, validator, *index)?],
+            reads: "the staking ledger and reward points of the validator",
+            changes: Some(
+                "the balances of the validator and its nominators, which receive their rewards, \
+                 and the claimed rewards in the staking ledger",
+            ),
+        },
+        Validator::ReplaceKey { index, .. } => Explanation {
+            operation: "replace the session keys of a validator".to_owned(),
+            accounts: vec![device_account(network, validator, *index)?],
+            reads: "nothing beyond what is needed to sign",
+            changes: Some(
+                "the session keys of the controller (Session.NextKeys), which take effect from \
+                 the next session",
+            ),
+        },
+        Validator::SetPayee {
+            index,
+            target,
+            stash,
+            ..
+        } => Explanation {
+            operation: format!("set the reward destination to {:?}", target),
+            accounts: vec![signing_account(network, validator, Some(index), *stash)?],
+            reads: "the staking ledger of the signer, and the current payee of its stash",
+            changes: Some(
+                "where future rewards of the stash are paid (Staking.Payee).  Nothing is \
+                 submitted if the payee is already the target, unless --force is given.",
+            ),
+        },
+        Validator::Address { index, .. } => Explanation {
+            operation: "display the address of an account on the device".to_owned(),
+            accounts: vec![match index {
+                Some(index) => device_account(network, validator, *index)?,
+                None => "a validator account, picked interactively".to_owned(),
+            }],
+            reads: "nothing from the chain",
+            changes: None,
+        },
+    })
+}
+
+fn proxy(cmd: &ProxyCommand, network: Ss58AddressFormat) -> Result<Explanation, Error> {
+    let (operation, account_type, index, delegate, changes) = match cmd {
+        ProxyCommand::Add {
+            account_type,
+            index,
+            delegate: (delegate, _),
+            proxy_type,
+            ..
+        } => (
+            format!(
+                "allow another account to act for this one as a {:?} proxy",
+                proxy_type
+            ),
+            account_type,
+            index,
+            delegate,
+            "the proxies of the account (Proxy.Proxies), reserving a deposit",
+        ),
+        ProxyCommand::Remove {
+            account_type,
+            index,
+            delegate: (delegate, _),
+            proxy_type,
+            ..
+        } => (
+            format!(
+                "stop another account from acting as a {:?} proxy",
+                proxy_type
+            ),
+            account_type,
+            index,
+            delegate,
+            "the proxies of the account (Proxy.Proxies), returning part of the deposit",
+        ),
+    };
+    Ok(Explanation {
+        operation,
+        accounts: vec![
+            device_account(network, *account_type, *index)?,
+            format!("proxy {}", address(network, delegate)),
+        ],
+        reads: "nothing beyond what is needed to sign",
+        changes: Some(changes),
+    })
+}
+
+/// Describe what `cmd` would do, without doing it
+pub(crate) fn explain(
+    cmd: &Command,
+    network: Ss58AddressFormat,
+    emit_call: bool,
+) -> Result<String, Error> {
+    let explanation = match cmd {
+        Command::Nominator(n) => nominator(n, network)?,
+        Command::Validator(v) => validator(v, network)?,
+        Command::Proxy(p) => proxy(p, network)?,
+        Command::Pool(PoolCommand::Show { index, member }) => Explanation {
+            operation: "show the nomination pool membership of an account".to_owned(),
+            accounts: vec![match (index, member) {
+                (_, Some((member, _))) => address(network, member),
+                (Some(index), None) => device_account(network, AccountType::Nominator, *index)?,
+                (None, None) => "a nominator account, picked interactively".to_owned(),
+            }],
+            reads: "the pool membership of the account, and the state of its pool",
+            changes: None,
+        },
+        Command::Label(l) => {
+            let (operation, account_type, index) = match l {
+                LabelCommand::Set {
+                    account_type,
+                    index,
+                    ..
+                } => ("set the local label of an account", account_type, index),
+                LabelCommand::Get {
+                    account_type,
+                    index,
+                } => ("print the local label of an account", account_type, index),
+                LabelCommand::Remove {
+                    account_type,
+                    index,
+                } => ("remove the local label of an account", account_type, index),
+            };
+            Explanation {
+                operation: format!("{}, in the labels file on this computer", operation),
+                accounts: vec![device_account(network, *account_type, *index)?],
+                reads: "nothing from the chain",
+                changes: None,
+            }
+        }
+        Command::Device(DeviceCommand::Addresses { max_index }) => Explanation {
+            operation: "list the addresses of accounts on the device".to_owned(),
+            accounts: vec![format!(
+                "every nominator and validator account from index 1 to {}",
+                max_index
+            )],
+            reads: "nothing from the chain",
+            changes: None,
+        },
+        Command::Device(DeviceCommand::Owns {
+            address: (account, _),
+            max_index,
+        }) => Explanation {
+            operation: "check whether the device derives an address".to_owned(),
+            accounts: vec![
+                address(network, account),
+                format!(
+                    "every nominator and validator account from index 1 to {}",
+                    max_index
+                ),
+            ],
+            reads: "nothing from the chain",
+            changes: None,
+        },
+        Command::Device(DeviceCommand::OpenApp) => Explanation {
+            operation: "open the app for the network on the device, if it is on its dashboard"
+                .to_owned(),
+            accounts: vec![],
+            reads: "nothing from the chain",
+            changes: None,
+        },
+        Command::Device(DeviceCommand::QuitApp) => Explanation {
+            operation: "close the app open on the device, returning it to its dashboard".to_owned(),
+            accounts: vec![],
+            reads: "nothing from the chain",
+            changes: None,
+        },
+        Command::Device(DeviceCommand::Reconcile { max_index, .. }) => Explanation {
+            operation: "check the staking state of the accounts on the device, and report any \
+                        drift"
+                .to_owned(),
+            accounts: match max_index {
+                Some(max_index) => vec![format!(
+                    "every nominator and validator account from index 1 to {}",
+                    max_index
+                )],
+                None => {
+                    let mut accounts = device_accounts(network, AccountType::Nominator, &[])?;
+                    accounts.extend(device_accounts(network, AccountType::Validator, &[])?);
+                    accounts
+                }
+            },
+            reads: "the staking ledger, nominations, validator preferences, and reward \
+                    destination of each account",
+            changes: None,
+        },
+        Command::Metadata => Explanation {
+            operation: "print the chain metadata".to_owned(),
+            accounts: vec![],
+            reads: "the metadata",
+            changes: None,
+        },
+        Command::Properties => Explanation {
+            operation: "print the chain properties".to_owned(),
+            accounts: vec![],
+            reads: "the chain properties, such as the token symbol",
+            changes: None,
+        },
+        Command::Doctor => Explanation {
+            operation: "check that the device and node are set up correctly".to_owned(),
+            accounts: vec![],
+            reads: "the node’s health, version, and metadata",
+            changes: None,
+        },
+        Command::PendingTx { hash, blocks } => Explanation {
+            operation: format!(
+                "look for transaction {:?} in the pool and the last {} blocks",
+                hash, blocks
+            ),
+            accounts: vec![],
+            reads: "the transaction pool, and the extrinsics of recent blocks",
+            changes: None,
+        },
+        Command::Decode { .. } => Explanation {
+            operation: "decode an extrinsic and display its call".to_owned(),
+            accounts: vec![],
+            reads: "the metadata",
+            changes: None,
+        },
+        Command::Ping { .. } => Explanation {
+            operation: "measure the latency of RPC hosts".to_owned(),
+            accounts: vec![],
+            reads: "the latest block header, repeatedly",
+            changes: None,
+        },
+        Command::History {
+            address: (account, _),
+            since_block,
+        } => Explanation {
+            operation: format!(
+                "list the staking rewards and slashes of an account since block {}",
+                since_block
+            ),
+            accounts: vec![address(network, account)],
+            reads: "the events of every block in the range",
+            changes: None,
+        },
+    };
+    Ok(explanation.render(emit_call))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::payee::Payee;
+
+    #[test]
+    fn explains_set_payee() {
+        let cmd = Command::Nominator(Nominator::SetPayee {
+            index: AccountRef::Index(3),
+            target: Payee::Controller,
+            force: false,
+            stash: false,
+        });
+        let text = explain(&cmd, Ss58AddressFormat::PolkadotAccount, false).unwrap();
+        assert!(text.starts_with("What it does: set the reward destination to Controller\n"));
+        assert!(text.contains("nominator account 3, derived at m/44'/354'/0'/0'/3'"));
+        assert!(text.contains("Changes: where future rewards of the stash are paid"));
+        let text = explain(&cmd, Ss58AddressFormat::PolkadotAccount, true).unwrap();
+        assert!(text.contains("Changes: nothing yet."));
+        assert!(
+            explain(&Command::Metadata, Ss58AddressFormat::KusamaAccount, false)
+                .unwrap()
+                .ends_with("Changes: nothing.  No transaction is signed.")
+        );
+    }
+}
@@ -0,0 +1,146 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of ledgeracio.
+//
+// ledgeracio is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ledgeracio is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ledgeracio.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Checking that accounts can pay the fees of their transactions before a
+//! batch of them is signed
+//!
+//! The fee is estimated by the node with `payment_queryInfo`, which does not
+//! check signatures, so the extrinsic is signed with an all-zero signature
+//! instead of asking the device.
+
+use super::{common::pad, connection::Connector, AccountId, Error};
+use codec::Encode;
+use jsonrpsee::{client::RequestError, common::Params};
+use ledgeracio::{CategorizedError, ErrorCategory, Signed};
+use substrate_subxt::{sp_core::{crypto::Ss58AddressFormat, sr25519, Bytes},
+                      sp_runtime::{generic::{SignedPayload, UncheckedExtrinsic},
+                                   MultiSignature},
+                      system::System,
+                      Call, Client, Encoded, KusamaRuntime, Runtime, SignedExtra, Signer};
+
+type T = KusamaRuntime;
+
+/// A signer that does not sign, for fee estimation only
+struct FeeSigner {
+    payer: AccountId,
+    /// The tip, so that the extrinsic has the length it will have when signed
+    tip: u128,
+}
+
+impl Signer<T> for FeeSigner {
+    fn account_id(&self) -> &AccountId { &self.payer }
+
+    fn nonce(&self) -> Option<<T as System>::Index> { None }
+
+    fn sign(
+        &self,
+        extrinsic: SignedPayload<Encoded, <<T as Runtime>::Extra as SignedExtra<T>>::Extra>,
+    ) -> Signed<T> {
+        let (call, mut extra, _) = extrinsic.deconstruct();
+        // As in `HardSigner`, the tip is carried by the last signed extension
+        (extra.6).0 = self.tip;
+        let signature = MultiSignature::Sr25519(sr25519::Signature::from_raw([0; 64]));
+        let extrinsic = UncheckedExtrinsic::new_signed(call, self.payer.clone(), signature, extra);
+        Box::pin(async move { Ok(extrinsic) })
+    }
+}
+
+/// The fee `payer` would pay for `call`, with a tip of `tip`.  The tip itself
+/// is not included.  If the node answers with an error, such as because it
+/// cannot decode the call, the error is categorized as
+/// [`ErrorCategory::Chain`]; if it cannot be reached, as
+/// [`ErrorCategory::Network`].
+pub(crate) async fn estimate_fee<C: Call<T> + Send + Sync>(
+    client: &Client<T>,
+    call: C,
+    payer: &AccountId,
+    tip: u128,
+) -> Result<u128, Error> {
+    let signer = FeeSigner {
+        payer: payer.clone(),
+        tip,
+    };
+    let extrinsic = client.create_signed(call, &signer).await?;
+    let info: serde_json::Value = client
+        .rpc_client()
+        .request(
+            "payment_queryInfo",
+            Params::Array(vec![serde_json::to_value(Bytes(extrinsic.encode()))?]),
+        )
+        .await
+        .map_err(|e| -> Error {
+            let category = match e {
+                RequestError::Request(_) => ErrorCategory::Chain,
+                _ => ErrorCategory::Network,
+            };
+            Box::new(CategorizedError::new(category, e.to_string()))
+        })?;
+    // Nodes encode the fee as a number or, if it is too large, as a string
+    match &info["partialFee"] {
+        serde_json::Value::Number(fee) => fee.as_u64().map(u128::from),
+        serde_json::Value::String(fee) => fee.parse().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| "Node returned an invalid fee estimate".to_owned().into())
+}
+
+/// Check that each of `payers` can pay the fee for the call `call` makes for
+/// it, plus a tip of `tip`, and warn about those that cannot.  Fees must leave
+/// the existential deposit, and cannot be paid from funds that are locked for
+/// fees, such as bonded funds.  Returns the indices of the payers that cannot
+/// pay.  Each payer is retried with [`Connector::retry`].
+pub(crate) async fn check_fees<C: Call<T> + Send + Sync>(
+    connector: &Connector,
+    client: &mut Client<T>,
+    payers: &[(u32, AccountId)],
+    call: impl Fn(&AccountId) -> C,
+    tip: u128,
+    network: Ss58AddressFormat,
+    (decimals, symbol): (u8, &str),
+) -> Result<Vec<u32>, Error> {
+    let existential_deposit: u128 = client
+        .metadata()
+        .module("Balances")?
+        .constant("ExistentialDeposit")?
+        .value()?;
+    let mut unfunded = vec![];
+    let call = &call;
+    for (index, payer) in payers {
+        let (fee, data) = connector
+            .retry(client, |client| async move {
+                let fee = estimate_fee(&client, call(payer), payer, tip).await?;
+                Ok((fee, client.account(payer, None).await?.data))
+            })
+            .await?;
+        let available = data
+            .free
+            .saturating_sub(data.fee_frozen)
+            .saturating_sub(existential_deposit);
+        let required = fee.saturating_add(tip);
+        if available < required {
+            eprintln!(
+                "Warning: account {} ({}) has {} {sym} available for fees, but needs {} {sym}",
+                index,
+                ledgeracio::device_address(payer, network),
+                pad(decimals, available),
+                pad(decimals, required),
+                sym = symbol
+            );
+            unfunded.push(*index)
+        }
+    }
+    Ok(unfunded)
+}
@@ -0,0 +1,208 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of ledgeracio.
+//
+// ledgeracio is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ledgeracio is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ledgeracio.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Reward and slash history of an account, read from the events emitted by
+//! the staking pallet
+//!
+//! Unlike the estimate in `rewards`, this is what was actually paid or
+//! slashed, but it requires reading the events of every block in the range.
+
+use super::{common::{pad, OutputFormat, TokenOverrides},
+            AccountId, Error};
+use codec::Decode;
+use futures::stream::{self, StreamExt as _};
+use jsonrpsee::common::Params;
+use ledgeracio::input_error;
+use substrate_subxt::{sp_core::{crypto::{Ss58AddressFormat, Ss58Codec},
+                                storage::StorageKey,
+                                twox_128, Bytes, H256},
+                      Client, EventsDecoder, KusamaRuntime, Raw, Runtime};
+
+/// How many blocks are fetched at once
+const CONCURRENT_BLOCKS: usize = 16;
+
+/// A staking event affecting an account
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Kind {
+    Reward,
+    Slash,
+}
+
+/// Classify a staking event by its name.  Newer runtimes use the past tense.
+fn kind(variant: &str) -> Option<Kind> {
+    match variant {
+        "Reward" | "Rewarded" => Some(Kind::Reward),
+        "Slash" | "Slashed" => Some(Kind::Slash),
+        _ => None,
+    }
+}
+
+/// The raw events of the block with number `number`
+async fn block_events(
+    client: &Client<KusamaRuntime>,
+    number: u32,
+) -> Result<Option<Vec<u8>>, Error> {
+    let hash: Option<H256> = client
+        .rpc_client()
+        .request(
+            "chain_getBlockHash",
+            Params::Array(vec![serde_json::json!(number)]),
+        )
+        .await?;
+    let hash = match hash {
+        Some(hash) => hash,
+        None => return Ok(None),
+    };
+    let key = StorageKey([twox_128(b"System"), twox_128(b"Events")].concat());
+    let events: Option<Bytes> = client
+        .rpc_client()
+        .request(
+            "state_getStorage",
+            Params::Array(vec![
+                serde_json::to_value(key)?,
+                serde_json::to_value(hash)?,
+            ]),
+        )
+        .await?;
+    Ok(events.map(|events| events.0))
+}
+
+/// Print the rewards and slashes of `account` in the blocks from `since` to
+/// the last finalized block, as recorded by staking events
+pub(crate) async fn main(
+    client: &Client<KusamaRuntime>,
+    account: &AccountId,
+    since: u32,
+    network: Ss58AddressFormat,
+    output: OutputFormat,
+    token: &TokenOverrides,
+) -> Result<(), Error> {
+    let finalized = client.finalized_head().await?;
+    let last = match client.header(Some(finalized)).await? {
+        Some(header) => header.number,
+        None => return Err("Node did not return its finalized block".to_owned().into()),
+    };
+    if since > last {
+        return Err(input_error(format!(
+            "--since-block {} is after the last finalized block, {}",
+            since, last
+        )))
+    }
+    let mut decoder = EventsDecoder::<KusamaRuntime>::new(client.metadata().clone());
+    KusamaRuntime::register_type_sizes(&mut decoder);
+    let (token_decimals, token_symbol) = token.resolve(client);
+    if output != OutputFormat::Jsonl {
+        println!(
+            "Staking events of {} in blocks {} to {}:",
+            account.to_ss58check_with_version(network),
+            since,
+            last
+        );
+    }
+    let (mut rewarded, mut slashed, mut undecoded) = (0_u128, 0_u128, 0_usize);
+    let mut blocks = stream::iter(since..=last)
+        .map(|number| async move { (number, block_events(client, number).await) })
+        .buffered(CONCURRENT_BLOCKS);
+    while let Some((number, events)) = blocks.next().await {
+        let events = match events? {
+            Some(events) => events,
+            None => continue,
+        };
+        let events = match decoder.decode_events(&mut &*events) {
+            Ok(events) => events,
+            Err(e) => {
+                eprintln!(
+                    "Warning: cannot decode the events of block {}: {}",
+                    number, e
+                );
+                undecoded += 1;
+                continue
+            }
+        };
+        for (_, event) in events {
+            let event = match event {
+                Raw::Event(event) if event.module == "Staking" => event,
+                _ => continue,
+            };
+            let kind = match kind(&event.variant) {
+                Some(kind) => kind,
+                None => continue,
+            };
+            let (who, amount) = <(AccountId, u128)>::decode(&mut &*event.data)?;
+            if who != *account {
+                continue
+            }
+            let name = match kind {
+                Kind::Reward => {
+                    rewarded = rewarded.saturating_add(amount);
+                    "reward"
+                }
+                Kind::Slash => {
+                    slashed = slashed.saturating_add(amount);
+                    "slash"
+                }
+            };
+            if output == OutputFormat::Jsonl {
+                println!(
+                    "{}",
+                    serde_json::json!({
+                        "block": number,
+                        "event": name,
+                        "amount": amount.to_string(),
+                    })
+                )
+            } else {
+                println!(
+                    "    #{}: {} of {} {}",
+                    number,
+                    name,
+                    pad(token_decimals, amount),
+                    token_symbol
+                )
+            }
+        }
+    }
+    if output != OutputFormat::Jsonl {
+        println!(
+            "Total rewarded: {} {sym}\nTotal slashed: {} {sym}",
+            pad(token_decimals, rewarded),
+            pad(token_decimals, slashed),
+            sym = token_symbol
+        );
+    }
+    if undecoded > 0 {
+        return Err(format!(
+            "The events of {} blocks could not be decoded, so the history is incomplete",
+            undecoded
+        )
+        .into())
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_events() {
+        assert_eq!(kind("Reward"), Some(Kind::Reward));
+        assert_eq!(kind("Rewarded"), Some(Kind::Reward));
+        assert_eq!(kind("Slash"), Some(Kind::Slash));
+        assert_eq!(kind("Slashed"), Some(Kind::Slash));
+        assert_eq!(kind("Bonded"), None);
+    }
+}
@@ -0,0 +1,208 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of ledgeracio.
+//
+// ledgeracio is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ledgeracio is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ledgeracio.  If not, see <http://www.gnu.org/licenses/>.
+
+//! On-chain identity lookup
+//!
+//! `substrate-subxt` does not ship bindings for the identity pallet, so the
+//! few types we need are declared here.  Only decoding is supported.
+
+use super::{AccountId, Error};
+use codec::{Decode, Encode, Input};
+use ledgeracio::{categorize, ErrorCategory};
+use substrate_subxt::{balances::Balances, module, Client, KusamaRuntime, Store};
+
+/// The identity pallet
+#[module]
+pub trait Identity: Balances {}
+
+impl Identity for KusamaRuntime {}
+
+/// Identity information, as stored on chain.  Raw data is at most 32 bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Data {
+    /// No data
+    None,
+    /// Raw bytes
+    Raw(Vec<u8>),
+    /// A BLAKE2b-256 hash
+    BlakeTwo256([u8; 32]),
+    /// A SHA-256 hash
+    Sha256([u8; 32]),
+    /// A Keccak-256 hash
+    Keccak256([u8; 32]),
+    /// A SHA3-256 hash
+    ShaThree256([u8; 32]),
+}
+
+impl Decode for Data {
+    fn decode<I: Input>(input: &mut I) -> Result<Self, codec::Error> {
+        Ok(match input.read_byte()? {
+            0 => Self::None,
+            len @ 1..=33 => {
+                let mut buf = vec![0; usize::from(len) - 1];
+                input.read(&mut buf[..])?;
+                Self::Raw(buf)
+            }
+            34 => Self::BlakeTwo256(Decode::decode(input)?),
+            35 => Self::Sha256(Decode::decode(input)?),
+            36 => Self::Keccak256(Decode::decode(input)?),
+            37 => Self::ShaThree256(Decode::decode(input)?),
+            _ => return Err("invalid leading byte in identity data".into()),
+        })
+    }
+}
+
+impl Data {
+    /// Convert raw data to a string, if possible
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::Raw(bytes) => std::str::from_utf8(bytes).ok(),
+            _ => None,
+        }
+    }
+}
+
+/// A registrar’s judgement of an identity
+#[derive(Clone, Debug, Decode, PartialEq, Eq)]
+pub enum Judgement<Balance> {
+    /// No judgement yet
+    Unknown,
+    /// A fee has been paid and a judgement is pending
+    FeePaid(Balance),
+    /// The data looks reasonable, but was not checked in depth
+    Reasonable,
+    /// The registrar has verified the data
+    KnownGood,
+    /// The data was once good but is now out of date
+    OutOfDate,
+    /// The data is imprecise
+    LowQuality,
+    /// The data is wrong
+    Erroneous,
+}
+
+/// The information associated with an identity
+#[derive(Clone, Debug, Decode, PartialEq, Eq)]
+pub struct IdentityInfo {
+    pub additional: Vec<(Data, Data)>,
+    pub display: Data,
+    pub legal: Data,
+    pub web: Data,
+    pub riot: Data,
+    pub email: Data,
+    pub pgp_fingerprint: Option<[u8; 20]>,
+    pub image: Data,
+    pub twitter: Data,
+}
+
+/// An identity, with the judgements it has received
+#[derive(Clone, Debug, Decode, PartialEq, Eq)]
+pub struct Registration<Balance> {
+    pub judgements: Vec<(u32, Judgement<Balance>)>,
+    pub deposit: Balance,
+    pub info: IdentityInfo,
+}
+
+/// The identity of an account
+#[derive(Clone, Debug, Eq, PartialEq, Store, Encode)]
+pub struct IdentityOfStore<T: Identity> {
+    #[store(returns = Registration<T::Balance>)]
+    pub who: T::AccountId,
+}
+
+/// The parent identity of a sub-account, and the sub-account’s name
+#[derive(Clone, Debug, Eq, PartialEq, Store, Encode)]
+pub struct SuperOfStore<T: Identity> {
+    #[store(returns = (T::AccountId, Data))]
+    pub who: T::AccountId,
+}
+
+fn format_name<Balance>(registration: &Registration<Balance>, sub: Option<&str>) -> Option<String> {
+    let name = registration.info.display.as_str()?;
+    let verified = registration
+        .judgements
+        .iter()
+        .any(|(_, judgement)| matches!(judgement, Judgement::KnownGood | Judgement::Reasonable));
+    Some(format!(
+        "{}{}{} ({})",
+        name,
+        if sub.is_some() { "/" } else { "" },
+        sub.unwrap_or(""),
+        if verified { "verified" } else { "unverified" }
+    ))
+}
+
+/// Fetch the display name of `account`, if it has one.  Sub-identities are
+/// shown as `parent/child`.  Returns `Ok(None)` if the chain has no identity
+/// pallet.  Identities are only informational, so if the chain’s identity
+/// pallet stores them in a shape that cannot be decoded, a warning is logged
+/// and `Ok(None)` is returned too.
+pub(crate) async fn display_name(
+    client: &Client<KusamaRuntime>,
+    account: &AccountId,
+) -> Result<Option<String>, Error> {
+    if client.metadata().module("Identity").is_err() {
+        return Ok(None)
+    }
+    match fetch_name(client, account).await {
+        Err(e) if categorize(&e) != ErrorCategory::Network => {
+            log::warn!("Cannot decode the identity of {}: {}", account, e);
+            Ok(None)
+        }
+        result => result,
+    }
+}
+
+async fn fetch_name(
+    client: &Client<KusamaRuntime>,
+    account: &AccountId,
+) -> Result<Option<String>, Error> {
+    let who = account.clone();
+    if let Some(registration) = client.fetch(&IdentityOfStore { who }, None).await? {
+        return Ok(format_name(&registration, None))
+    }
+    let who = account.clone();
+    let (parent, sub) = match client.fetch(&SuperOfStore { who }, None).await? {
+        Some(e) => e,
+        None => return Ok(None),
+    };
+    Ok(
+        match client.fetch(&IdentityOfStore { who: parent }, None).await? {
+            Some(registration) => format_name(&registration, Some(sub.as_str().unwrap_or("?"))),
+            None => None,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_data() {
+        assert_eq!(Data::decode(&mut &[0][..]).unwrap(), Data::None);
+        assert_eq!(
+            Data::decode(&mut &b"\x04abc"[..]).unwrap(),
+            Data::Raw(b"abc".to_vec())
+        );
+        assert_eq!(
+            Data::decode(&mut &b"\x04abc"[..]).unwrap().as_str(),
+            Some("abc")
+        );
+        assert!(Data::decode(&mut &b"\x04ab"[..]).is_err());
+        assert!(Data::decode(&mut &[38][..]).is_err());
+    }
+}
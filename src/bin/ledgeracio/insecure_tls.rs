@@ -0,0 +1,134 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of ledgeracio.
+//
+// ledgeracio is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ledgeracio is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ledgeracio.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A WebSocket transport that accepts any TLS certificate, for
+//! `--insecure-accept-invalid-certs`.
+//!
+//! `jsonrpsee`’s WebSocket transport always verifies certificates against the
+//! system roots, so nodes with self-signed certificates need their own
+//! transport.  This is only safe when the network path to the node is trusted:
+//! anyone who can intercept the connection can impersonate the node.
+
+use super::Error;
+use async_std::net::TcpStream;
+use async_tls::{client::TlsStream, TlsConnector};
+use core::{future::Future, pin::Pin};
+use jsonrpsee::{common::{Request, Response},
+                raw::RawClient,
+                transport::TransportClient};
+use ledgeracio::input_error;
+use rustls::{Certificate, ClientConfig, RootCertStore, ServerCertVerified, ServerCertVerifier,
+             TLSError};
+use soketto::{connection::{Receiver, Sender},
+              handshake::{Client as Handshake, ServerResponse}};
+use std::sync::Arc;
+use webpki::DNSNameRef;
+
+type Socket = TlsStream<TcpStream>;
+
+/// A certificate verifier that accepts every certificate
+struct AcceptAnyCert;
+
+impl ServerCertVerifier for AcceptAnyCert {
+    fn verify_server_cert(
+        &self,
+        _roots: &RootCertStore,
+        _presented_certs: &[Certificate],
+        _dns_name: DNSNameRef<'_>,
+        _ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// Errors that can occur once connected
+#[derive(::thiserror::Error, Debug)]
+pub(crate) enum TransportError {
+    /// The WebSocket connection failed
+    #[error("WebSocket error: {0}")]
+    WebSocket(#[from] soketto::connection::Error),
+    /// The node sent or was sent invalid JSON
+    #[error("Invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// A `wss://` transport that does not verify the server’s certificate
+pub(crate) struct InsecureWsTransport {
+    sender: Sender<Socket>,
+    receiver: Receiver<Socket>,
+}
+
+impl TransportClient for InsecureWsTransport {
+    type Error = TransportError;
+
+    fn send_request<'s>(
+        &'s mut self,
+        request: Request,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Self::Error>> + Send + 's>> {
+        Box::pin(async move {
+            let request = serde_json::to_string(&request)?;
+            self.sender.send_text(request).await?;
+            self.sender.flush().await?;
+            Ok(())
+        })
+    }
+
+    fn next_response<'s>(
+        &'s mut self,
+    ) -> Pin<Box<dyn Future<Output = Result<Response, Self::Error>> + Send + 's>> {
+        Box::pin(async move {
+            let mut message = vec![];
+            self.receiver.receive_data(&mut message).await?;
+            Ok(serde_json::from_slice(&message)?)
+        })
+    }
+}
+
+/// Connect to the `wss://` URL `url` without verifying the server’s
+/// certificate.
+pub(crate) async fn connect(url: &str) -> Result<jsonrpsee::Client, Error> {
+    let url = url::Url::parse(url).map_err(|e| input_error(format!("Invalid URL: {}", e)))?;
+    if url.scheme() != "wss" {
+        return Err(input_error(
+            "--insecure-accept-invalid-certs requires a wss:// host",
+        ))
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| input_error("URL has no host"))?;
+    let port = url.port_or_known_default().unwrap_or(443);
+    let mut config = ClientConfig::new();
+    config
+        .dangerous()
+        .set_certificate_verifier(Arc::new(AcceptAnyCert));
+    let socket = TcpStream::connect((host, port)).await?;
+    let socket = TlsConnector::from(Arc::new(config))
+        .connect(host, socket)
+        .await?;
+    let path = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_owned(),
+    };
+    let mut handshake = Handshake::new(socket, host, &path);
+    match handshake.handshake().await? {
+        ServerResponse::Accepted { .. } => {}
+        ServerResponse::Redirect { status_code, .. } | ServerResponse::Rejected { status_code } => {
+            return Err(format!("WebSocket handshake failed with status {}", status_code).into())
+        }
+    }
+    let (sender, receiver) = handshake.into_builder().finish();
+    Ok(RawClient::new(InsecureWsTransport { sender, receiver }).into())
+}
@@ -0,0 +1,252 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of ledgeracio.
+//
+// ledgeracio is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ledgeracio is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ledgeracio.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Human-readable labels for accounts, stored in a local state file.  Does not
+//! require a network connection or a Ledger device.
+//!
+//! The state file has one label per line, in the form
+//! `<network> <account type> <index> <label>`, where `<network>` is the numeric
+//! SS58 address format.
+
+use super::{AccountType, Error, LedgeracioPath, StructOpt};
+use ledgeracio::input_error;
+use std::{collections::BTreeMap, io::ErrorKind, path::PathBuf};
+use substrate_subxt::sp_core::crypto::Ss58AddressFormat;
+
+#[derive(StructOpt, Debug)]
+pub(crate) enum LabelCommand {
+    /// Set the label of the account at the given index
+    Set {
+        /// The type of account: `nominator` or `validator`
+        account_type: AccountType,
+        index: u32,
+        label: String,
+    },
+    /// Print the label of the account at the given index, if any
+    Get {
+        /// The type of account: `nominator` or `validator`
+        account_type: AccountType,
+        index: u32,
+    },
+    /// Remove the label of the account at the given index
+    Remove {
+        /// The type of account: `nominator` or `validator`
+        account_type: AccountType,
+        index: u32,
+    },
+}
+
+type Key = (u8, AccountType, u32);
+
+/// The labels in the state file
+#[derive(Debug, Default)]
+pub(crate) struct Labels(BTreeMap<Key, String>);
+
+pub(crate) fn account_type_name(account_type: AccountType) -> &'static str {
+    match account_type {
+        AccountType::Nominator => "nominator",
+        AccountType::Validator => "validator",
+    }
+}
+
+/// The path of the state file: `$XDG_CONFIG_HOME/ledgeracio/labels`, or
+/// `$HOME/.config/ledgeracio/labels` if `XDG_CONFIG_HOME` is not set.
+fn path() -> Result<PathBuf, Error> {
+    let mut path = match (
+        std::env::var_os("XDG_CONFIG_HOME"),
+        std::env::var_os("HOME"),
+    ) {
+        (Some(config), _) if !config.is_empty() => PathBuf::from(config),
+        (_, Some(home)) => PathBuf::from(home).join(".config"),
+        _ => return Err("Cannot find the config directory: HOME is not set".into()),
+    };
+    path.push("ledgeracio");
+    path.push("labels");
+    Ok(path)
+}
+
+impl Labels {
+    fn parse(data: &str) -> Result<Self, Error> {
+        let mut labels = BTreeMap::new();
+        for (line_number, line) in data.lines().enumerate() {
+            let bad_line = || format!("Malformed label file line {}: {:?}", line_number + 1, line);
+            let mut fields = line.splitn(4, ' ');
+            let (network, account_type, index, label) =
+                match (fields.next(), fields.next(), fields.next(), fields.next()) {
+                    (Some(network), Some(account_type), Some(index), Some(label)) => {
+                        (network, account_type, index, label)
+                    }
+                    _ => return Err(bad_line().into()),
+                };
+            let network = network.parse().map_err(|_| bad_line())?;
+            let account_type = account_type.parse().map_err(|_| bad_line())?;
+            let index = index.parse().map_err(|_| bad_line())?;
+            labels.insert((network, account_type, index), label.to_owned());
+        }
+        Ok(Self(labels))
+    }
+
+    fn serialize(&self) -> String {
+        let mut data = String::new();
+        for ((network, account_type, index), label) in &self.0 {
+            data += &format!(
+                "{} {} {} {}\n",
+                network,
+                account_type_name(*account_type),
+                index,
+                label
+            );
+        }
+        data
+    }
+
+    /// Load the labels from the state file.  A missing file has no labels.
+    pub(crate) fn load() -> Result<Self, Error> {
+        match std::fs::read_to_string(path()?) {
+            Ok(data) => Self::parse(&data),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Load the labels for display.  Labels are never needed to complete a
+    /// command, so if they cannot be loaded, a warning is printed and there
+    /// are none.
+    pub(crate) fn load_or_warn() -> Self {
+        Self::load().unwrap_or_else(|e| {
+            eprintln!("Warning: cannot load labels, so none are shown: {}", e);
+            Self::default()
+        })
+    }
+
+    /// Save the labels to the state file, replacing it atomically
+    fn save(&self) -> Result<(), Error> {
+        let path = path()?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, self.serialize())?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    /// The highest index of an account of type `account_type` with a label
+    pub(crate) fn max_index(
+        &self,
+        network: Ss58AddressFormat,
+        account_type: AccountType,
+    ) -> Option<u32> {
+        let network: u8 = network.into();
+        self.0
+            .keys()
+            .filter(|&&(n, t, _)| n == network && t == account_type)
+            .map(|&(_, _, index)| index)
+            .max()
+    }
+
+    /// The label of the given account, if it has one
+    pub(crate) fn get(
+        &self,
+        network: Ss58AddressFormat,
+        account_type: AccountType,
+        index: u32,
+    ) -> Option<&str> {
+        self.0
+            .get(&(network.into(), account_type, index))
+            .map(|s| &**s)
+    }
+}
+
+pub(crate) fn main(cmd: LabelCommand, network: Ss58AddressFormat) -> Result<(), Error> {
+    let mut labels = Labels::load()?;
+    match cmd {
+        LabelCommand::Set {
+            account_type,
+            index,
+            label,
+        } => {
+            // Only accounts that the device can derive can be labelled
+            LedgeracioPath::new(network, account_type, index)?;
+            if label.is_empty() || label.contains(|c| c == '\n' || c == '\r') {
+                return Err(input_error("Labels must be non-empty and fit on one line"))
+            }
+            labels
+                .0
+                .insert((network.into(), account_type, index), label);
+            labels.save()
+        }
+        LabelCommand::Get {
+            account_type,
+            index,
+        } => {
+            match labels.get(network, account_type, index) {
+                Some(label) => println!("{}", label),
+                None => println!("No label set"),
+            }
+            Ok(())
+        }
+        LabelCommand::Remove {
+            account_type,
+            index,
+        } => {
+            if labels
+                .0
+                .remove(&(network.into(), account_type, index))
+                .is_some()
+            {
+                labels.save()
+            } else {
+                Err(input_error("No label set"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let data = "0 nominator 1 hot nominator\n2 validator 10 cold stash\n";
+        let labels = Labels::parse(data).unwrap();
+        assert_eq!(
+            labels.get(Ss58AddressFormat::KusamaAccount, AccountType::Validator, 10),
+            Some("cold stash")
+        );
+        assert_eq!(
+            labels.get(
+                Ss58AddressFormat::PolkadotAccount,
+                AccountType::Nominator,
+                1
+            ),
+            Some("hot nominator")
+        );
+        assert_eq!(
+            labels.get(Ss58AddressFormat::KusamaAccount, AccountType::Nominator, 1),
+            None
+        );
+        assert_eq!(labels.serialize(), data);
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(Labels::parse("2 validator 10").is_err());
+        assert!(Labels::parse("2 stash 10 label").is_err());
+        assert!(Labels::parse("2 validator ten label").is_err());
+    }
+}
@@ -20,25 +20,51 @@
 #![allow(clippy::non_ascii_literal)]
 #![forbid(unsafe_code)]
 
+mod audit;
 mod common;
+mod connection;
+mod decode;
+mod device;
+mod discover;
+mod doctor;
+mod explain;
+mod fees;
+mod history;
+mod identity;
+mod insecure_tls;
+mod labels;
 mod nominator;
+mod payee;
 mod payouts;
+mod pending;
+mod ping;
+mod points;
+mod pools;
+mod price;
+mod prometheus;
+mod proxy;
+mod reconcile;
+mod rewards;
+mod shares;
 mod validator;
 
-use futures::future::TryFutureExt;
-use ledgeracio::{get_network, AccountType, Error, HardSigner, HardStore, LedgeracioPath};
+use futures::future::{Either, TryFutureExt};
+use ledgeracio::{categorize, get_network, input_error, parse_address, validate_network,
+                 AccountType, CategorizedError, Error, ErrorCategory, HardSigner, HardStore,
+                 LedgeracioPath};
 
 #[cfg(not(unix))]
 compile_error!("Only *nix-like platforms are supported");
 
-use common::AddressSource;
+use common::{AddressSource, OutputFormat, TokenOverrides};
+use payee::Payee;
 use sp_core::crypto::AccountId32 as AccountId;
-use std::{fmt::Debug, future::Future, pin::Pin};
+use std::{convert::TryFrom, fmt::Debug, future::Future, pin::Pin, time::Duration};
 use structopt::StructOpt;
 use substrate_subxt::{sp_core,
-                      sp_core::crypto::{Ss58AddressFormat, Ss58Codec},
-                      staking::RewardDestination,
-                      Client, ClientBuilder, Signer};
+                      sp_core::{crypto::{Ss58AddressFormat, Ss58Codec},
+                                H256},
+                      Client, Signer};
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "Ledgeracio", about = "Ledger CLI for staking")]
@@ -46,30 +72,224 @@ struct Ledgeracio {
     /// Dry run.  Do not execute the operation.
     #[structopt(short = "n", long)]
     dry_run: bool,
-    /// RPC host
-    #[structopt(short, long)]
+    /// Describe in plain English what the command would do, the accounts and
+    /// derivation paths involved, and the chain state it would read and
+    /// change, then exit.  Neither the device nor the network is used.
+    #[structopt(long)]
+    explain: bool,
+    /// Instead of signing and submitting a transaction, print the hex SCALE
+    /// encoding of its call, for signing elsewhere.  The Ledger device is not
+    /// used.
+    #[structopt(long)]
+    emit_call: bool,
+    /// RPC host: a `ws://`, `wss://`, `http://`, or `https://` URL
+    #[structopt(short, long, env = "LEDGERACIO_HOST")]
     host: Option<String>,
-    /// Network
-    #[structopt(long, parse(try_from_str = get_network))]
-    network: Ss58AddressFormat,
+    /// Network.  `custom` is a network without a registered name, described
+    /// by `--ss58-prefix`.
+    #[structopt(long, env = "LEDGERACIO_NETWORK", parse(try_from_str = parse_network))]
+    network: NetworkArg,
+    /// The SS58 address prefix of a `custom` network.  Custom networks are
+    /// read-only: commands that use the Ledger device are not available.
+    #[structopt(long)]
+    ss58_prefix: Option<u8>,
+    /// Fail unless the node reports this genesis hash.  This makes sure that
+    /// the node is for the intended chain.
+    #[structopt(long, parse(try_from_str = parse_hash))]
+    genesis_hash: Option<H256>,
+    /// Do not verify the TLS certificate of a `wss://` host, or of an
+    /// `https://` price source.  This allows self-signed certificates, but
+    /// anyone who can intercept the connection can impersonate the node.  Only
+    /// use this with trusted internal nodes.
+    #[structopt(long)]
+    insecure_accept_invalid_certs: bool,
+    /// If the connection to the node is lost during a long-running command,
+    /// such as `nominator show --watch`, or a batch command, such as
+    /// `validator payout`, connect again with exponential backoff instead of
+    /// failing
+    #[structopt(long)]
+    reconnect: bool,
+    /// Name of the Ledger app to expect.  Defaults to the stock app for the
+    /// network.
+    #[structopt(long)]
+    app_name: Option<String>,
+    /// If the Ledger device is on its dashboard, open the app for `--network`
+    /// (or `--app-name`) instead of failing.  Not all devices and firmware
+    /// versions support this.
+    #[structopt(long)]
+    auto_open_app: bool,
+    /// Never use colored output.  Color is also disabled if the `NO_COLOR`
+    /// environment variable is set, or the output is not a terminal.
+    #[structopt(long)]
+    no_color: bool,
+    /// Output format of `show` commands: `Text` (the default) or `JSONL`, which
+    /// prints one JSON object per account per line as soon as it is fetched.
+    /// `device addresses` and `validator list` also support `CSV`.
+    #[structopt(long, default_value = "Text")]
+    output: OutputFormat,
+    /// Before doing anything else, check every address argument against
+    /// `--network`, and fail with a list of all the addresses for other
+    /// networks
+    #[structopt(long)]
+    strict_network: bool,
+    /// Abort the command if it has not finished within this duration, such as
+    /// `90s` or `5m`.  The command is aborted as if by Ctrl-C.
+    #[structopt(long, parse(try_from_str = common::parse_duration))]
+    timeout: Option<u64>,
+    /// Append every extrinsic signed by the Ledger device to this file, as
+    /// hex, one per line, before it is broadcast
+    #[structopt(long, value_name = "PATH")]
+    dump_extrinsic: Option<std::path::PathBuf>,
+    /// Pay this many tokens, such as `0.01`, as a tip with every transaction
+    /// signed by the Ledger device, to raise its priority in the transaction
+    /// pool
+    #[structopt(long, value_name = "AMOUNT")]
+    tip: Option<String>,
+    /// Never print addresses derived by the Ledger device, or of the stashes
+    /// they control.  `address` commands display the address on the device
+    /// for confirmation instead, commands that only list addresses fail, and
+    /// other commands print `<hidden>` in place of the address.
+    #[structopt(long)]
+    confirm_on_device_only: bool,
+    /// Log every APDU exchanged with the Ledger device to stderr, in hex.
+    /// This is for debugging incompatibilities with an app.
+    #[structopt(long)]
+    trace_apdu: bool,
+    /// Use this number of decimals for the token instead of the one reported
+    /// by the node
+    #[structopt(long)]
+    token_decimals: Option<u8>,
+    /// Use this token symbol instead of the one reported by the node
+    #[structopt(long)]
+    token_symbol: Option<String>,
+    /// Also show the approximate value of balances in this currency, such as
+    /// `USD`, using the price from `--price-source`
+    #[structopt(long, requires = "price-source", value_name = "CURRENCY")]
+    fiat: Option<String>,
+    /// An HTTP or HTTPS URL returning the price of the token in the `--fiat`
+    /// currency, as JSON
+    #[structopt(long, requires = "fiat", value_name = "URL")]
+    price_source: Option<String>,
+    /// Read the chain metadata from this file instead of fetching it from the
+    /// node, so that `metadata` and `decode` work without a network
+    /// connection.  The file holds SCALE-encoded metadata, either as raw bytes
+    /// or as `0x`-prefixed hex.
+    #[structopt(long, value_name = "PATH")]
+    metadata_file: Option<std::path::PathBuf>,
+    /// Allow commands that sign transactions to run on Polkadot.  Without
+    /// this, they fail, unless the `LEDGERACIO_NO_MAINNET_GUARD` environment
+    /// variable is set to a non-empty value.
+    #[structopt(long)]
+    i_understand_this_is_mainnet: bool,
     /// Subcommand
     #[structopt(subcommand)]
     cmd: Command,
 }
 
+/// The `--network` argument
+#[derive(Debug, Copy, Clone)]
+enum NetworkArg {
+    /// A network with a registered name
+    Known(Ss58AddressFormat),
+    /// A network described by `--ss58-prefix`
+    Custom,
+}
+
+fn parse_network(arg: &str) -> Result<NetworkArg, Error> {
+    if arg.eq_ignore_ascii_case("custom") {
+        Ok(NetworkArg::Custom)
+    } else {
+        get_network(arg).map(NetworkArg::Known)
+    }
+}
+
+/// The address format of a custom network with SS58 prefix `prefix`
+fn custom_network(prefix: u8) -> Ss58AddressFormat {
+    Ss58AddressFormat::try_from(prefix).unwrap_or(Ss58AddressFormat::Custom(prefix))
+}
+
+/// The address format of `network`
+fn resolve_network(
+    network: NetworkArg,
+    ss58_prefix: Option<u8>,
+) -> Result<Ss58AddressFormat, Error> {
+    match (network, ss58_prefix) {
+        (NetworkArg::Known(network), None) => Ok(network),
+        (NetworkArg::Known(_), Some(_)) => {
+            Err(input_error("--ss58-prefix requires --network custom"))
+        }
+        (NetworkArg::Custom, None) => Err(input_error("--network custom requires --ss58-prefix")),
+        (NetworkArg::Custom, Some(prefix)) if prefix >= 64 => Err(input_error(format!(
+            "SS58 prefix {} is not supported (the limit is 63)",
+            prefix
+        ))),
+        (NetworkArg::Custom, Some(prefix)) => Ok(custom_network(prefix)),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn display_path(
     account_type: AccountType,
     keystore: &HardStore,
     network: Ss58AddressFormat,
     index: u32,
+    display_network: Option<Ss58AddressFormat>,
+    qr: bool,
+    show_path: bool,
+    raw: bool,
 ) -> Result<(), Error> {
-    if index == 0 {
-        return Err("Index must not be zero".to_owned().into())
-    }
     let path = LedgeracioPath::new(network, account_type, index)?;
+    if show_path {
+        println!("Derivation path: {}", path.slip44_notation())
+    }
+    let labels = labels::Labels::load_or_warn();
+    if ledgeracio::confirms_on_device_only() {
+        if qr || raw || display_network.is_some() {
+            return Err(input_error(
+                "--qr, --raw, and --display-network cannot be used with --confirm-on-device-only",
+            ))
+        }
+        println!(
+            "Check the address of {} account {}{} on the device, and confirm it there",
+            labels::account_type_name(account_type),
+            index,
+            labels
+                .get(network, account_type, index)
+                .map(|label| format!(" [{}]", label))
+                .unwrap_or_default()
+        );
+        keystore.show_address(&path).await?;
+        println!("Address confirmed on the device");
+        return Ok(())
+    }
     let signer: HardSigner = keystore.signer(path).await?;
     let account_id: &AccountId = signer.account_id();
-    println!("{}", account_id.to_ss58check_with_version(network));
+    let display_network = display_network.unwrap_or(network);
+    let address = account_id.to_ss58check_with_version(display_network);
+    if qr {
+        use qrcode::render::unicode::Dense1x2;
+        let code = qrcode::QrCode::new(address.as_bytes())?;
+        // Most terminals are light-on-dark, so invert the colors
+        let image = code
+            .render::<Dense1x2>()
+            .dark_color(Dense1x2::Light)
+            .light_color(Dense1x2::Dark)
+            .build();
+        println!("{}", image);
+    }
+    println!(
+        "{}{}",
+        address,
+        labels
+            .get(network, account_type, index)
+            .map(|label| format!(" [{}]", label))
+            .unwrap_or_default()
+    );
+    if raw {
+        // The SS58 address is a checksummed encoding of these two values
+        println!("SS58 version: {}", u8::from(display_network));
+        println!("Account ID: 0x{}", hex::encode(account_id));
+    }
     Ok(())
 }
 
@@ -79,54 +299,309 @@ enum Command {
     Nominator(nominator::Nominator),
     /// Validator operations
     Validator(validator::Validator),
-    /// Pretty-print the chain metadata
+    /// Proxy operations
+    Proxy(proxy::ProxyCommand),
+    /// Nomination pool operations
+    Pool(pools::PoolCommand),
+    /// Manage local labels for accounts
+    Label(labels::LabelCommand),
+    /// Operations that only use the Ledger device
+    Device(device::DeviceCommand),
+    /// Pretty-print the chain metadata, or that of `--metadata-file`
     Metadata,
     /// Display the chain properties
     Properties,
+    /// Check that the Ledger device and RPC node are set up correctly
+    Doctor,
+    /// Show whether a submitted transaction is pending or has been included in
+    /// a recent block
+    PendingTx {
+        /// The transaction hash, as printed when it was submitted
+        #[structopt(parse(try_from_str = parse_hash))]
+        hash: H256,
+        /// How many of the most recent blocks to search
+        #[structopt(long, default_value = "100")]
+        blocks: u32,
+    },
+    /// Decode a SCALE-encoded extrinsic and display the call it makes.  The
+    /// call is looked up in the metadata of the connected chain, or of
+    /// `--metadata-file`.
+    Decode {
+        /// The extrinsic, as hex
+        hex: String,
+    },
+    /// Measure the round-trip latency of `chain_getHeader` on each of the
+    /// given RPC hosts, and report the fastest reachable one
+    Ping {
+        /// The hosts to test.  Defaults to `--host`.
+        hosts: Vec<String>,
+        /// How many requests to time on each host
+        #[structopt(long, default_value = "3")]
+        count: u32,
+    },
+    /// List the staking rewards and slashes of an account, as recorded in the
+    /// events of every block from `--since-block` to the last finalized block
+    History {
+        /// The account, usually a stash
+        #[structopt(parse(try_from_str = parse_address))]
+        address: (AccountId, u8),
+        /// The first block to scan
+        #[structopt(long)]
+        since_block: u32,
+    },
+}
+
+impl Command {
+    /// The addresses given as arguments, with the networks they were encoded
+    /// for
+    fn addresses(&self) -> Vec<&(AccountId, u8)> {
+        match self {
+            Self::Nominator(s) => s.addresses(),
+            Self::Validator(v) => v.addresses(),
+            Self::Proxy(p) => p.addresses(),
+            Self::Pool(p) => p.addresses(),
+            Self::History { address, .. } => vec![address],
+            Self::Device(d) => d.addresses(),
+            Self::Label(_)
+            | Self::Metadata
+            | Self::Properties
+            | Self::Doctor
+            | Self::PendingTx { .. }
+            | Self::Decode { .. }
+            | Self::Ping { .. } => vec![],
+        }
+    }
+
+    /// Whether the command signs and submits a transaction
+    fn signs(&self) -> bool {
+        match self {
+            Self::Nominator(s) => s.signs(),
+            Self::Validator(v) => v.signs(),
+            Self::Proxy(p) => p.signs(),
+            Self::Pool(_)
+            | Self::Label(_)
+            | Self::Device(_)
+            | Self::Metadata
+            | Self::Properties
+            | Self::Doctor
+            | Self::PendingTx { .. }
+            | Self::Decode { .. }
+            | Self::Ping { .. }
+            | Self::History { .. } => false,
+        }
+    }
+}
+
+/// Refuse to sign transactions on Polkadot unless the user has acknowledged
+/// that it is mainnet, or has disabled the check with the
+/// `LEDGERACIO_NO_MAINNET_GUARD` environment variable
+fn check_mainnet(
+    cmd: &Command,
+    network: Ss58AddressFormat,
+    acknowledged: bool,
+    emit_call: bool,
+) -> Result<(), Error> {
+    let disabled =
+        std::env::var_os("LEDGERACIO_NO_MAINNET_GUARD").map_or(false, |value| !value.is_empty());
+    if network != Ss58AddressFormat::PolkadotAccount
+        || !cmd.signs()
+        || emit_call
+        || acknowledged
+        || disabled
+    {
+        return Ok(())
+    }
+    Err(input_error(
+        "This command signs a transaction on Polkadot mainnet.  Pass \
+         --i-understand-this-is-mainnet to proceed.",
+    ))
 }
 
 type Runtime = substrate_subxt::KusamaRuntime;
 
-fn parse_reward_destination(arg: &str) -> Result<RewardDestination<AccountId>, Error> {
+/// Check that every address argument of `cmd` is for `network`.  All
+/// mismatches are reported in a single error.
+fn check_networks(cmd: &Command, network: Ss58AddressFormat) -> Result<(), Error> {
+    common::check_networks(cmd.addresses(), network)
+}
+
+fn parse_reward_destination(arg: &str) -> Result<Payee<AccountId>, Error> {
     Ok(match &*arg.to_ascii_lowercase() {
-        "staked" => RewardDestination::Staked,
-        "stash" => RewardDestination::Stash,
-        "controller" => RewardDestination::Controller,
-        _ => {
-            return Err("Arbitrary reward destinations not supported"
-                .to_owned()
-                .into())
-        }
+        "staked" => Payee::Staked,
+        "stash" => Payee::Stash,
+        "controller" => Payee::Controller,
+        "none" => Payee::None,
+        _ => return Err(input_error("Arbitrary reward destinations not supported")),
     })
 }
 
-async fn inner_main() -> Result<(), Error> {
-    env_logger::init();
+fn parse_hash(arg: &str) -> Result<H256, Error> {
+    let arg = arg.strip_prefix("0x").unwrap_or(arg);
+    let bytes = hex::decode(arg)?;
+    if bytes.len() != 32 {
+        return Err(input_error("Hashes must be 32 bytes"))
+    }
+    Ok(H256::from_slice(&bytes))
+}
+
+/// Run `cmd` with the metadata in `path`, without connecting to a node
+fn offline_main(
+    cmd: Command,
+    path: &std::path::Path,
+    network: Ss58AddressFormat,
+    output: OutputFormat,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let metadata = decode::read_metadata(path)?;
+    let parsed = decode::parse_metadata(&metadata)?;
+    if dry_run {
+        return Ok(())
+    }
+    match cmd {
+        Command::Metadata => {
+            let metadata = substrate_subxt::Metadata::try_from(parsed)
+                .map_err(|e| input_error(format!("Invalid metadata: {:?}", e)))?;
+            println!("{:#?}", metadata);
+            Ok(())
+        }
+        Command::Decode { hex } => decode::main(&metadata, &hex, network, output),
+        _ => Err(input_error(
+            "--metadata-file can only be used with the metadata and decode commands",
+        )),
+    }
+}
+
+async fn inner_main(args: Ledgeracio) -> Result<(), Error> {
     let Ledgeracio {
         dry_run,
+        explain,
+        emit_call,
         host,
         network,
+        ss58_prefix,
+        genesis_hash,
+        insecure_accept_invalid_certs,
+        reconnect,
+        app_name,
+        auto_open_app,
+        no_color,
+        output,
+        token_decimals,
+        token_symbol,
+        fiat,
+        price_source,
+        metadata_file,
+        i_understand_this_is_mainnet,
+        strict_network,
+        trace_apdu,
+        dump_extrinsic,
+        tip,
+        confirm_on_device_only,
+        timeout: _,
         cmd,
-    } = Ledgeracio::from_args();
+    } = args;
+    ledgeracio::trace_apdu(trace_apdu);
+    ledgeracio::confirm_on_device_only(confirm_on_device_only);
+    let network = resolve_network(network, ss58_prefix)?;
+    if explain {
+        println!("{}", explain::explain(&cmd, network, emit_call)?);
+        return Ok(())
+    }
+    if strict_network {
+        check_networks(&cmd, network)?
+    }
+    check_mainnet(&cmd, network, i_understand_this_is_mainnet, emit_call)?;
+    let mut token = TokenOverrides {
+        decimals: token_decimals,
+        symbol: token_symbol,
+        fiat: None,
+    };
+    env_logger::Builder::from_default_env()
+        .write_style(if common::use_color(no_color) {
+            env_logger::WriteStyle::Auto
+        } else {
+            env_logger::WriteStyle::Never
+        })
+        .init();
+    if let Some(path) = metadata_file {
+        return offline_main(cmd, &path, network, output, dry_run)
+    }
+    let host = host.or_else(|| match &cmd {
+        Command::Ping { hosts, .. } => hosts.first().cloned(),
+        _ => None,
+    });
     let host = match (host, network) {
         (Some(host), _) => host,
         (None, Ss58AddressFormat::KusamaAccount) => "wss://kusama-rpc.polkadot.io".into(),
         (None, Ss58AddressFormat::PolkadotAccount) => "wss://rpc.polkadot.io".into(),
-        _ => return Err("Please supply an RPC endpoint".into()),
+        _ => return Err(input_error("Please supply an RPC endpoint")),
     };
 
-    let client = ClientBuilder::<Runtime>::new()
-        .set_url(host)
-        .build()
-        .map_err(From::from);
+    let connector =
+        connection::Connector::new(host, insecure_accept_invalid_certs, reconnect, genesis_hash);
+    let client = {
+        let connector = connector.clone();
+        async move { connector.connect().await }
+    };
     let client: Pin<Box<dyn Future<Output = Result<Client<Runtime>, _>>>> = Box::pin(client);
-    let keystore = || HardStore::new(network);
+    let expected_app = app_name.as_deref();
+    let tip_connector = connector.clone();
+    let keystore = move || async move {
+        if !matches!(
+            network,
+            Ss58AddressFormat::PolkadotAccount | Ss58AddressFormat::KusamaAccount
+        ) {
+            return Err(input_error(
+                "Commands that use the Ledger device are not available on custom networks, as the \
+                 Ledger apps only derive keys for Polkadot and Kusama",
+            ))
+        }
+        // The amount is in tokens, so the node may be needed for the decimals
+        let tip = match tip {
+            Some(tip) => {
+                let decimals = match token_decimals {
+                    Some(decimals) => decimals,
+                    None => tip_connector.connect().await?.properties().token_decimals,
+                };
+                common::parse_tokens(&tip, decimals).map_err(|e| input_error(e.to_string()))?
+            }
+            None => 0,
+        };
+        if auto_open_app {
+            HardStore::open_app(network, expected_app).await?
+        }
+        let keystore = HardStore::new(network, expected_app).await?.tip(tip);
+        Ok::<_, Error>(match dump_extrinsic {
+            Some(path) => keystore.dump_extrinsics(path),
+            None => keystore,
+        })
+    };
     if dry_run {
         return Ok(())
     }
+    if let (Some(currency), Some(source)) = (fiat, price_source) {
+        token.fiat = Some(price::fetch(&source, &currency, insecure_accept_invalid_certs).await?)
+    }
     if let Some(hash) = match cmd {
-        Command::Nominator(s) => nominator::main(s, client, network, keystore).await?,
-        Command::Validator(v) => validator::main(v, client, network, keystore).await?,
+        Command::Nominator(s) => {
+            nominator::main(s, &connector, network, keystore, output, &token, emit_call).await?
+        }
+        Command::Validator(v) => {
+            validator::main(v, &connector, network, keystore, output, &token, emit_call).await?
+        }
+        Command::Proxy(p) => proxy::main(p, &connector, network, keystore, emit_call).await?,
+        Command::Pool(p) => {
+            pools::main(p, &connector, network, keystore, &token).await?;
+            None
+        }
+        Command::Label(l) => {
+            labels::main(l, network)?;
+            None
+        }
+        Command::Device(d) => {
+            device::main(d, &connector, network, keystore, output, expected_app).await?;
+            None
+        }
         Command::Metadata => {
             println!("{:#?}", client.await?.metadata());
             None
@@ -135,18 +610,181 @@ async fn inner_main() -> Result<(), Error> {
             println!("{:#?}", client.await?.properties());
             None
         }
+        Command::Doctor => {
+            doctor::main(client, network, app_name.as_deref()).await?;
+            None
+        }
+        Command::PendingTx { hash, blocks } => {
+            pending::main(&client.await?, hash, blocks).await?;
+            None
+        }
+        Command::Decode { hex } => {
+            let metadata = decode::fetch_metadata(&client.await?).await?;
+            decode::main(&metadata, &hex, network, output)?;
+            None
+        }
+        Command::Ping { hosts, count } => {
+            ping::main(&connector, hosts, count, output).await?;
+            None
+        }
+        Command::History {
+            address: (address, provided_network),
+            since_block,
+        } => {
+            validate_network("", provided_network, network)?;
+            history::main(
+                &client.await?,
+                &address,
+                since_block,
+                network,
+                output,
+                &token,
+            )
+            .await?;
+            None
+        }
     } {
         println!("Transaction hash: {:?}", hash);
     }
     Ok(())
 }
 
+/// How long the in-flight command has to be abandoned after Ctrl-C or a
+/// timeout, before the process exits anyway
+const ABANDON_GRACE: Duration = Duration::from_secs(1);
+
+/// Exit with the exit code of `category` unless the process has exited within
+/// [`ABANDON_GRACE`].  Exchanges with the device are blocking reads, so while
+/// one waits for the device, the executor cannot poll anything else, and the
+/// in-flight future cannot be dropped until the device answers.  The operating
+/// system closes the device when the process exits.
+fn exit_after_grace(category: ErrorCategory, message: String) {
+    std::thread::spawn(move || {
+        std::thread::sleep(ABANDON_GRACE);
+        eprintln!("Error: {}", message);
+        std::process::exit(category.exit_code())
+    });
+}
+
+/// The sender with which the Ctrl-C handler interrupts `interruptible_main`
+type Interrupter = std::sync::Mutex<Option<futures::channel::oneshot::Sender<()>>>;
+
+/// Handle Ctrl-C.  The first interrupts `interruptible_main`; a second exits
+/// immediately.
+fn interrupt(interrupter: &Interrupter) {
+    match interrupter.lock().expect("signal handler panicked").take() {
+        Some(sender) => {
+            // The receiver is only gone if `inner_main` has already finished
+            sender.send(()).unwrap_or_default();
+            exit_after_grace(ErrorCategory::Interrupted, "Interrupted".to_owned())
+        }
+        None => std::process::exit(ErrorCategory::Interrupted.exit_code()),
+    }
+}
+
+/// Run `inner_main` until it completes, the user presses Ctrl-C, or the
+/// `--timeout` expires.
+///
+/// On Ctrl-C or timeout, the in-flight future is dropped.  This drops any
+/// `HardStore`, so the pending device operation is abandoned and the transport
+/// is closed before the process exits.  If a blocking exchange with the device
+/// keeps the future from being dropped, the process exits anyway after
+/// [`ABANDON_GRACE`].  A second Ctrl-C exits immediately.
+async fn interruptible_main() -> Result<(), Error> {
+    let (sender, receiver) = futures::channel::oneshot::channel();
+    let interrupter = Interrupter::new(Some(sender));
+    ctrlc::set_handler(move || interrupt(&interrupter))?;
+    // An address with an unregistered prefix only parses if the prefix is the
+    // default, so the default must be set before the arguments are parsed.
+    let args = std::env::args_os()
+        .skip(1)
+        .filter_map(|arg| arg.into_string().ok());
+    if let Some(prefix) = common::ss58_prefix_arg(args).filter(|&prefix| prefix < 64) {
+        sp_core::crypto::set_default_ss58_version(custom_network(prefix))
+    }
+    let args = Ledgeracio::from_args();
+    let timeout = args.timeout;
+    // The deadline is kept by a thread, as the executor may be blocked
+    let deadline: Pin<Box<dyn Future<Output = ()>>> = match timeout {
+        Some(seconds) => {
+            let (sender, receiver) = futures::channel::oneshot::channel();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_secs(seconds));
+                sender.send(()).unwrap_or_default();
+                exit_after_grace(
+                    ErrorCategory::TimedOut,
+                    format!("Timed out after {} seconds", seconds),
+                )
+            });
+            Box::pin(async {
+                // The sender is never dropped without sending
+                let _ = receiver.await;
+            })
+        }
+        None => Box::pin(futures::future::pending()),
+    };
+    let main = Box::pin(inner_main(args));
+    match futures::future::select(main, futures::future::select(receiver, deadline)).await {
+        Either::Left((result, _)) => result,
+        Either::Right((Either::Left(_), main)) => {
+            drop(main);
+            Err(Box::new(CategorizedError::new(
+                ErrorCategory::Interrupted,
+                "Interrupted",
+            )))
+        }
+        Either::Right((Either::Right(_), main)) => {
+            drop(main);
+            Err(Box::new(CategorizedError::new(
+                ErrorCategory::TimedOut,
+                format!("Timed out after {} seconds", timeout.unwrap_or_default()),
+            )))
+        }
+    }
+}
+
 fn main() {
-    match async_std::task::block_on(inner_main()) {
+    match async_std::task::block_on(interruptible_main()) {
         Ok(()) => (),
         Err(e) => {
             eprintln!("Error: {}", e);
-            std::process::exit(1)
+            std::process::exit(categorize(&e).exit_code())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{process, time::Instant};
+
+    /// Set in the child process of `interrupt_exits_during_blocking_exchange`
+    const BLOCKED_CHILD: &str = "LEDGERACIO_TEST_BLOCKED_CHILD";
+
+    #[test]
+    fn interrupt_exits_during_blocking_exchange() {
+        if std::env::var_os(BLOCKED_CHILD).is_some() {
+            let (sender, receiver) = futures::channel::oneshot::channel();
+            let interrupter = std::sync::Arc::new(Interrupter::new(Some(sender)));
+            let handler = interrupter.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(100));
+                interrupt(&handler)
+            });
+            // Like a blocking read waiting for the user to confirm on the device
+            let exchange = async { std::thread::sleep(Duration::from_secs(60)) };
+            async_std::task::block_on(futures::future::select(Box::pin(exchange), receiver));
+            panic!("the process should have exited during the exchange")
         }
+        let started = Instant::now();
+        let status = process::Command::new(std::env::current_exe().unwrap())
+            .args(&["--exact", "tests::interrupt_exits_during_blocking_exchange"])
+            .env(BLOCKED_CHILD, "1")
+            .stdout(process::Stdio::null())
+            .stderr(process::Stdio::null())
+            .status()
+            .unwrap();
+        assert_eq!(status.code(), Some(ErrorCategory::Interrupted.exit_code()));
+        assert!(started.elapsed() < Duration::from_secs(30));
     }
 }
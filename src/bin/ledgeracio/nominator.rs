@@ -17,14 +17,28 @@
 //! Nominator commands
 
 use super::{parse_reward_destination, AccountType, Error, LedgeracioPath, StructOpt};
-use crate::common::pad;
-use core::{future::Future, pin::Pin};
-use ledgeracio::{parse_address, validate_network};
+use crate::{common::{check_chain_format, format_balance, format_table, pad, parse_account_ref,
+                     parse_duration, parse_ppb, payee_is, print_call, select_index, signer_index,
+                     unusual_account, AccountRef, OutputFormat, TokenOverrides},
+            connection::{spec_version, Connector},
+            labels::Labels,
+            payee::{check_supported, Payee, PayeeStore, SetPayeeCall},
+            payouts::eras_in};
+use core::future::Future;
+use ledgeracio::{get_network, input_error, keyparse::parse_public, parse_address, parser::inspect,
+                 validate_network, CategorizedError, ErrorCategory};
+use std::{collections::BTreeSet,
+          fs::File,
+          io::BufReader,
+          marker::PhantomData,
+          path::{Path, PathBuf},
+          time::Duration};
 use substrate_subxt::{sp_core::{crypto::{AccountId32 as AccountId, Ss58AddressFormat, Ss58Codec},
                                 H256},
-                      staking::{BondedStore, ChillCallExt, LedgerStore, NominateCallExt,
-                                PayeeStore, RewardDestination, SetPayeeCallExt},
-                      Client, KusamaRuntime};
+                      staking::{BondedStore, ChillCall, ChillCallExt, LedgerStore, NominateCall,
+                                NominateCallExt, NominatorsStore, ValidatorsStore},
+                      system::AccountStoreExt,
+                      Client, KusamaRuntime, Signer};
 
 #[derive(StructOpt, Debug)]
 pub(crate) enum Nominator {
@@ -32,35 +46,202 @@ pub(crate) enum Nominator {
     ShowAddress {
         #[structopt(parse(try_from_str = parse_address))]
         address: (AccountId, u8),
+        /// Only report unclaimed payouts from eras in this period, such as
+        /// `7d` or `12h`.
+        #[structopt(long, parse(try_from_str = parse_duration))]
+        since: Option<u64>,
+    },
+    /// Show the specified stash controllers, or all if none are specified.
+    Show {
+        /// The indices to show, separated by commas or given as separate
+        /// arguments
+        #[structopt(use_delimiter = true)]
+        indices: Vec<u32>,
+        /// Only print how many accounts are bonded, nominating, chilled, or
+        /// unbonded.  Only used when no index is given.
+        #[structopt(long)]
+        count_only: bool,
+        /// Only report unclaimed payouts from eras in this period, such as
+        /// `7d` or `12h`.
+        #[structopt(long, parse(try_from_str = parse_duration))]
+        since: Option<u64>,
+        /// Check each nominator’s targets against a signed allowlist, verified
+        /// with the given public key, and flag targets that are not on it.
+        #[structopt(
+            long,
+            number_of_values = 2,
+            value_names = &["ALLOWLIST", "PUBLIC"],
+            parse(from_os_str)
+        )]
+        only_allowlisted: Vec<PathBuf>,
+        /// Fetch and display the accounts again every this many seconds, until
+        /// interrupted with Ctrl-C.  The screen is cleared in between if stdout
+        /// is a terminal.
+        #[structopt(long, value_name = "SECONDS")]
+        watch: Option<u64>,
+    },
+    /// Report nominators on the device whose targets are the same or overlap
+    /// heavily, so that their nominations can be diversified
+    Audit {
+        /// Report pairs of accounts sharing at least this percentage of the
+        /// smaller of their target sets
+        #[structopt(long, default_value = "75", parse(try_from_str = parse_percent))]
+        threshold: u8,
     },
-    /// Show the specified stash controller, or all if none is specified.
-    Show { index: Option<u32> },
     /// Nominate a new validator set
     #[structopt(name = "nominate")]
     Nominate {
-        index: u32,
+        /// The account: an index, or an address on this device
+        #[structopt(parse(try_from_str = parse_account_ref))]
+        index: AccountRef,
         #[structopt(parse(try_from_str = parse_address))]
         set: Vec<(AccountId, u8)>,
+        /// Refuse to nominate targets that are not registered validators,
+        /// instead of only warning about them
+        #[structopt(long)]
+        strict: bool,
+        /// Do not wait for the nomination to be included in a block to check
+        /// that the targets stored on chain are exactly the ones submitted.
+        /// The check requires a WebSocket connection, so it is always skipped
+        /// over HTTP.
+        #[structopt(long)]
+        no_verify: bool,
+        /// If the set is empty, chill instead of failing
+        #[structopt(long)]
+        allow_empty_set: bool,
+        /// Drop the targets whose current commission is above this, in any
+        /// format accepted by `validator announce`, such as `10%`.  The
+        /// dropped targets are listed.
+        #[structopt(long, parse(try_from_str = parse_ppb))]
+        max_commission: Option<u32>,
+        /// Also nominate the addresses on the system clipboard, separated by
+        /// commas or whitespace.  Requires the `clipboard` feature.
+        #[structopt(long)]
+        from_clipboard: bool,
+        /// The account is a stash.  The transaction is signed by its
+        /// controller, which must also be an account on this device.
+        #[structopt(long)]
+        stash: bool,
+        /// Refuse to sign unless every target is on this signed allowlist,
+        /// verified with the given public key.  The key must be the one set on
+        /// the device, so this is the check the device will make.
+        #[structopt(
+            long,
+            number_of_values = 2,
+            value_names = &["ALLOWLIST", "PUBLIC"],
+            parse(from_os_str)
+        )]
+        require_allowlisted: Vec<PathBuf>,
+    },
+    /// Show a chart of the rewards earned by the account at the given index in
+    /// recent eras.  Rewards are computed from the validators it nominates
+    /// now, so rewards from earlier targets are not included.
+    Rewards {
+        /// If not given, pick the account interactively
+        index: Option<u32>,
+        /// How many of the most recent completed eras to show
+        #[structopt(long, default_value = "30")]
+        eras: u32,
     },
     /// Chill (announce intention to cease nomination)
-    Chill { index: u32 },
+    Chill {
+        /// The account: an index, or an address on this device.  If not given,
+        /// pick the account interactively.
+        #[structopt(conflicts_with = "all", parse(try_from_str = parse_account_ref))]
+        index: Option<AccountRef>,
+        /// Chill every account on the device that is nominating.  Each account
+        /// signs its own transaction, as a batch has a single signer and
+        /// cannot chill several controllers.
+        #[structopt(long)]
+        all: bool,
+        /// The account is a stash.  The transaction is signed by its
+        /// controller, which must also be an account on this device.
+        #[structopt(long, conflicts_with = "all")]
+        stash: bool,
+    },
     /// Set payment target
     #[structopt(name = "set-payee")]
     SetPayee {
-        index: u32,
+        /// The account: an index, or an address on this device
+        #[structopt(parse(try_from_str = parse_account_ref))]
+        index: AccountRef,
         #[structopt(parse(try_from_str = parse_reward_destination))]
-        target: RewardDestination<AccountId>,
+        target: Payee<AccountId>,
+        /// Submit the transaction even if the payee is already `target`
+        #[structopt(long)]
+        force: bool,
+        /// The account is a stash.  The transaction is signed by its
+        /// controller, which must also be an account on this device.
+        #[structopt(long)]
+        stash: bool,
     },
     /// Display the address of the given index
-    Address { index: u32 },
+    Address {
+        /// If not given, pick the account interactively
+        index: Option<u32>,
+        /// Encode the address for this network instead of `--network`.  The
+        /// account is still derived using `--network`.
+        #[structopt(long, parse(try_from_str = get_network))]
+        display_network: Option<Ss58AddressFormat>,
+        /// Also display the address as a QR code, for scanning with a mobile
+        /// wallet
+        #[structopt(long)]
+        qr: bool,
+        /// Also display the derivation path, in the standard notation used by
+        /// other wallets, such as `m/44'/354'/0'/0'/1'`
+        #[structopt(long)]
+        show_path: bool,
+        /// Also display the SS58 version byte and the account ID in hex, on
+        /// separate lines, for systems that store them separately
+        #[structopt(long)]
+        raw: bool,
+    },
+}
+
+impl Nominator {
+    /// The addresses given as arguments, with the networks they were encoded
+    /// for
+    pub(crate) fn addresses(&self) -> Vec<&(AccountId, u8)> {
+        match self {
+            Self::ShowAddress { address, .. } => vec![address],
+            Self::Nominate { index, set, .. } => index.address().into_iter().chain(set).collect(),
+            Self::Chill {
+                index: Some(index), ..
+            }
+            | Self::SetPayee { index, .. } => index.address().into_iter().collect(),
+            Self::Chill { index: None, .. }
+            | Self::Show { .. }
+            | Self::Audit { .. }
+            | Self::Rewards { .. }
+            | Self::Address { .. } => vec![],
+        }
+    }
+
+    /// Whether the command signs and submits a transaction
+    pub(crate) fn signs(&self) -> bool {
+        match self {
+            Self::Nominate { .. } | Self::Chill { .. } | Self::SetPayee { .. } => true,
+            Self::ShowAddress { .. }
+            | Self::Show { .. }
+            | Self::Audit { .. }
+            | Self::Rewards { .. }
+            | Self::Address { .. } => false,
+        }
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn display_nominators(
     controller: AccountId,
+    label: Option<&str>,
     client: &Client<KusamaRuntime>,
     network: Ss58AddressFormat,
+    output: OutputFormat,
+    token: &TokenOverrides,
+    eras: Option<u32>,
+    allowlist: Option<&BTreeSet<AccountId>>,
 ) -> Result<(), Error> {
-    use substrate_subxt::staking::{NominatorsStore, StakingLedger};
+    use substrate_subxt::staking::StakingLedger;
     let store = LedgerStore {
         controller: controller.clone(),
     };
@@ -83,30 +264,100 @@ async fn display_nominators(
         )
         .await?
         .ok_or_else(|| {
-            format!(
-                "No payee found for controller {} (this is a bug)",
-                controller
+            CategorizedError::new(
+                ErrorCategory::Internal,
+                format!(
+                    "No payee found for controller {} (this is a bug)",
+                    controller
+                ),
             )
         })?;
-    let mut props = client.properties().clone();
-    let mut good_symbol = true;
-    for i in props.token_symbol.bytes() {
-        good_symbol &= i.is_ascii_uppercase()
-    }
-    if !good_symbol {
-        props.token_symbol = "".to_owned()
+    let current_era = crate::common::current_era(client).await?;
+    // The controller pays the fees of staking transactions, so it needs funds
+    // of its own.
+    let controller_balance = client.account(&controller, None).await?.data;
+    if output == OutputFormat::Jsonl {
+        let nominations = client
+            .fetch(
+                &NominatorsStore {
+                    stash: stash.clone(),
+                },
+                None,
+            )
+            .await?;
+        println!(
+            "{}",
+            serde_json::json!({
+                "controller": ledgeracio::device_address(&controller, network),
+                "label": label,
+                "stash": ledgeracio::device_address(&stash, network),
+                "total": total.to_string(),
+                "active": active.to_string(),
+                "unlocking": unlocking
+                    .iter()
+                    .map(|chunk| serde_json::json!({
+                        "value": chunk.value.to_string(),
+                        "era": chunk.era,
+                        "withdrawable": chunk.era <= current_era,
+                    }))
+                    .collect::<Vec<_>>(),
+                "payee": format!("{:?}", payee),
+                "controller_free": controller_balance.free.to_string(),
+                "controller_reserved": controller_balance.reserved.to_string(),
+                "nominations": nominations.map(|nominations| serde_json::json!({
+                    "submitted_in": nominations.submitted_in,
+                    "effective_era": nominations.submitted_in.saturating_add(1),
+                    "active": nominations.submitted_in < current_era,
+                    "suppressed": nominations.suppressed,
+                    "targets": nominations
+                        .targets
+                        .iter()
+                        .map(|target| target.to_ss58check_with_version(network))
+                        .collect::<Vec<_>>(),
+                    "off_allowlist": allowlist.map(|allowlist| nominations
+                        .targets
+                        .iter()
+                        .filter(|target| !allowlist.contains(target))
+                        .map(|target| target.to_ss58check_with_version(network))
+                        .collect::<Vec<_>>()),
+                })),
+            })
+        );
+        return Ok(())
     }
+    let (token_decimals, token_symbol) = token.resolve(client);
 
     println!(
-        "Nominator account: {}\nStash balance: {} {sym}\nAmount at stake: {} {sym}\nAmount \
-         unlocking: {:?} {sym}\nPayee: {:?}",
-        stash.to_ss58check_with_version(network),
-        pad(props.token_decimals, total),
-        pad(props.token_decimals, active),
-        unlocking,
+        "Nominator account: {}{}\nStash balance: {} {sym}{}\nAmount at stake: {} {sym}{}\nAmount \
+         unlocking: {}\nPayee: {:?}",
+        ledgeracio::device_address(&stash, network),
+        label
+            .map(|label| format!(" [{}]", label))
+            .unwrap_or_default(),
+        pad(token_decimals, total),
+        token.fiat(token_decimals, total),
+        pad(token_decimals, active),
+        token.fiat(token_decimals, active),
+        crate::common::describe_unlocking(
+            &unlocking,
+            current_era,
+            token_decimals,
+            &token_symbol,
+            "    "
+        ),
         payee,
-        sym = props.token_symbol,
+        sym = token_symbol,
     );
+    println!(
+        "Controller balance: {} {sym}{} free, {} {sym} reserved",
+        pad(token_decimals, controller_balance.free),
+        token.fiat(token_decimals, controller_balance.free),
+        pad(token_decimals, controller_balance.reserved),
+        sym = token_symbol,
+    );
+    if controller_balance.free == 0 {
+        println!("Warning: the controller has no free balance to pay transaction fees");
+    }
     let nominations = match client.fetch(&NominatorsStore { stash }, None).await? {
         None => {
             println!("Nominations: None (yet)");
@@ -115,15 +366,46 @@ async fn display_nominators(
         Some(n) => n,
     };
     println!(
-        "Era nominations submitted: {}\nNominations suppressed: {}\nTargets:\n",
-        nominations.submitted_in, nominations.suppressed
+        "Era nominations submitted: {}\n{}\nNominations suppressed: {}\nTargets:\n",
+        nominations.submitted_in,
+        describe_submission(nominations.submitted_in, current_era),
+        nominations.suppressed
     );
     for stash in nominations.targets.iter().cloned() {
+        if let Some(allowlist) = allowlist {
+            println!(
+                "Target {} is {}",
+                stash.to_ss58check_with_version(network),
+                if allowlist.contains(&stash) {
+                    "on the allowlist"
+                } else {
+                    "NOT on the allowlist"
+                }
+            )
+        }
+        if let Some(kind) = unusual_account(&stash) {
+            println!(
+                "Warning: target {} is {}",
+                stash.to_ss58check_with_version(network),
+                kind
+            )
+        }
         let bonded = BondedStore {
             stash: stash.clone(),
         };
         if let Some(controller) = client.fetch(&bonded, None).await? {
-            crate::common::display_validators(client, &[controller], network).await?
+            if let Some(kind) = unusual_account(&controller) {
+                println!(
+                    "Warning: the controller {} of target {} is {}",
+                    controller.to_ss58check_with_version(network),
+                    stash.to_ss58check_with_version(network),
+                    kind
+                )
+            }
+            crate::common::display_validator(
+                client, controller, None, false, network, output, token, eras,
+            )
+            .await?
         } else {
             println!(
                 "controller not found for stash {}\n",
@@ -134,76 +416,743 @@ async fn display_nominators(
     Ok(())
 }
 
-pub(crate) async fn main<T: FnOnce() -> Result<super::HardStore, Error>>(
+/// Parse a list of SS58 addresses separated by commas or whitespace
+fn parse_address_list(list: &str) -> Result<Vec<(AccountId, u8)>, Error> {
+    list.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|address| !address.is_empty())
+        .map(|address| {
+            parse_address(address)
+                .map_err(|e| input_error(format!("Invalid address {:?}: {}", address, e)))
+        })
+        .collect()
+}
+
+/// Read the addresses on the system clipboard
+#[cfg(feature = "clipboard")]
+fn clipboard_addresses() -> Result<Vec<(AccountId, u8)>, Error> {
+    use copypasta::{ClipboardContext, ClipboardProvider};
+    let mut clipboard = ClipboardContext::new()?;
+    parse_address_list(&clipboard.get_contents()?)
+}
+
+#[cfg(not(feature = "clipboard"))]
+fn clipboard_addresses() -> Result<Vec<(AccountId, u8)>, Error> {
+    Err(input_error(
+        "--from-clipboard requires ledgeracio to be built with the `clipboard` feature",
+    ))
+}
+
+fn parse_percent(data: &str) -> Result<u8, Error> {
+    match data.parse() {
+        Ok(percent) if percent <= 100 => Ok(percent),
+        _ => Err(input_error("Percentages must be integers from 0 to 100")),
+    }
+}
+
+/// Interpret the era nominations were submitted in.  Nominations are used by
+/// the election at the end of the era they were submitted in, so they take
+/// effect in the next era.
+fn describe_submission(submitted_in: u32, current_era: u32) -> String {
+    let effective = submitted_in.saturating_add(1);
+    if submitted_in < current_era {
+        format!(
+            "Submitted in era {}; active since era {}",
+            submitted_in, effective
+        )
+    } else {
+        format!("Not yet active (takes effect in era {})", effective)
+    }
+}
+
+/// The row for the nominator at `index`, with controller `controller`, in the
+/// table shown by `show` when no index is given
+async fn nominator_row(
+    client: &Client<KusamaRuntime>,
+    index: u32,
+    controller: &AccountId,
+    label: Option<&str>,
+    network: Ss58AddressFormat,
+    token: (u8, &str),
+    allowlist: Option<&BTreeSet<AccountId>>,
+) -> Result<Vec<String>, Error> {
+    let label = label.unwrap_or_default().to_owned();
+    let store = LedgerStore {
+        controller: controller.clone(),
+    };
+    let ledger = match client.fetch(&store, None).await? {
+        Some(ledger) => ledger,
+        None => {
+            let none = || "-".to_owned();
+            return Ok(vec![
+                index.to_string(),
+                none(),
+                none(),
+                none(),
+                none(),
+                "not bonded".to_owned(),
+                label,
+            ])
+        }
+    };
+    let store = NominatorsStore {
+        stash: ledger.stash.clone(),
+    };
+    let (targets, status) = match client.fetch(&store, None).await? {
+        None => ("-".to_owned(), "chilled"),
+        Some(nominations) => {
+            let mut targets = nominations.targets.len().to_string();
+            if let Some(allowlist) = allowlist {
+                let off = nominations
+                    .targets
+                    .iter()
+                    .filter(|target| !allowlist.contains(target))
+                    .count();
+                if off > 0 {
+                    targets += &format!(" ({} off allowlist)", off)
+                }
+            }
+            (targets, "nominating")
+        }
+    };
+    Ok(vec![
+        index.to_string(),
+        ledgeracio::device_address(&ledger.stash, network),
+        format_balance(token, ledger.total),
+        format_balance(token, ledger.active),
+        targets,
+        status.to_owned(),
+        label,
+    ])
+}
+
+/// Report whether the targets stored on chain differ from those submitted
+fn report_nominations(submitted: &[AccountId], stored: &[AccountId], network: Ss58AddressFormat) {
+    if submitted == stored {
+        println!("On-chain targets match the submitted nominations");
+        return
+    }
+    for target in submitted.iter().filter(|target| !stored.contains(target)) {
+        println!(
+            "Submitted target {} is not stored on chain",
+            target.to_ss58check_with_version(network)
+        )
+    }
+    for target in stored.iter().filter(|target| !submitted.contains(target)) {
+        println!(
+            "Target {} is stored on chain but was not submitted",
+            target.to_ss58check_with_version(network)
+        )
+    }
+    if submitted.len() == stored.len() && submitted.iter().all(|target| stored.contains(target)) {
+        println!("The chain stored the submitted targets in a different order:");
+    } else {
+        println!("On-chain targets differ from the submitted nominations.  They are now:");
+    }
+    for target in stored {
+        println!("    {}", target.to_ss58check_with_version(network))
+    }
+}
+
+/// Load the addresses on the signed allowlist at `allowlist`, verifying its
+/// signature with the public key at `public`
+pub(crate) fn load_allowlist(
+    allowlist: &Path,
+    public: &Path,
+    network: Ss58AddressFormat,
+) -> Result<BTreeSet<AccountId>, Error> {
+    let (pk, key_network) = parse_public(&std::fs::read(public)?)?;
+    if key_network != network {
+        return Err(input_error(format!(
+            "Key is for network {}, not {}",
+            String::from(key_network),
+            String::from(network)
+        )))
+    }
+    let file = BufReader::new(File::open(allowlist)?);
+    let mut addresses = BTreeSet::new();
+    // The first line is the nonce, and lines starting with `#` are metadata.
+    for line in inspect::<_, AccountId>(file, network, &pk)?.iter().skip(1) {
+        if !line.starts_with('#') {
+            addresses.insert(parse_address::<AccountId>(line)?.0);
+        }
+    }
+    Ok(addresses)
+}
+
+pub(crate) async fn main<T: FnOnce() -> U, U: Future<Output = Result<super::HardStore, Error>>>(
     cmd: Nominator,
-    client: Pin<Box<dyn Future<Output = Result<Client<KusamaRuntime>, Error>>>>,
+    connector: &Connector,
     network: Ss58AddressFormat,
     keystore: T,
+    output: OutputFormat,
+    token: &TokenOverrides,
+    emit_call: bool,
 ) -> Result<Option<H256>, Error> {
     use std::convert::{TryFrom, TryInto};
     match cmd {
         Nominator::ShowAddress {
             address: (stash, provided_network),
+            since,
         } => {
             validate_network("", provided_network, network)?;
-            let client = client.await?;
+            let client = connector.connect_staking().await?;
+            check_chain_format(&client, network);
             let controller = match client.fetch(&BondedStore { stash }, None).await? {
                 Some(controller) => controller,
                 None => return Err("Controller not found for stash".to_owned().into()),
             };
-            display_nominators(controller, &client, network).await?;
+            let eras = since.map(|since| eras_in(&client, since)).transpose()?;
+            display_nominators(
+                controller, None, &client, network, output, token, eras, None,
+            )
+            .await?;
             Ok(None)
         }
-        Nominator::Show { index } => {
-            let client = client.await?;
+        Nominator::Show {
+            indices,
+            count_only,
+            since,
+            only_allowlisted,
+            watch,
+        } => {
+            if watch == Some(0) {
+                return Err(input_error("--watch interval must not be zero"))
+            }
+            let allowlist = match &*only_allowlisted {
+                [] => None,
+                [allowlist, public] => Some(load_allowlist(allowlist, public, network)?),
+                _ => return Err(input_error("--only-allowlisted may only be passed once")),
+            };
+            let mut client = connector.connect_staking().await?;
+            check_chain_format(&client, network);
+            let eras = since.map(|since| eras_in(&client, since)).transpose()?;
             let nominators = crate::common::fetch_validators(
                 &client,
-                crate::AddressSource::Device(index, &keystore()?),
+                crate::AddressSource::Device(&indices, &keystore().await?),
                 network,
                 AccountType::Nominator,
             )
             .await?;
-            for controller in nominators {
-                display_nominators(controller, &client, network).await?
+            let labels = Labels::load_or_warn();
+            let clear = watch.is_some() && atty::is(atty::Stream::Stdout);
+            // Only used to reload the metadata between refreshes, which only
+            // happen with `--watch`
+            let mut spec_version = match watch {
+                Some(_) => spec_version(&client).await?,
+                None => 0,
+            };
+            loop {
+                if clear {
+                    // Clear the screen and move the cursor to the top left
+                    print!("\x1b[2J\x1b[H");
+                }
+                let rendered = async {
+                    if count_only && indices.is_empty() {
+                        crate::common::display_counts(&client, &nominators, AccountType::Nominator)
+                            .await
+                    } else if indices.is_empty() && output != OutputFormat::Jsonl {
+                        let (token_decimals, token_symbol) = token.resolve(&client);
+                        let mut rows = vec![[
+                            "INDEX", "STASH", "TOTAL", "ACTIVE", "TARGETS", "STATUS", "LABEL",
+                        ]
+                        .iter()
+                        .map(|&header| header.to_owned())
+                        .collect()];
+                        for (index, controller) in &nominators {
+                            rows.push(
+                                nominator_row(
+                                    &client,
+                                    *index,
+                                    controller,
+                                    labels.get(network, AccountType::Nominator, *index),
+                                    network,
+                                    (token_decimals, &token_symbol),
+                                    allowlist.as_ref(),
+                                )
+                                .await?,
+                            )
+                        }
+                        print!("{}", format_table(&rows, &[0, 2, 3]));
+                        Ok(())
+                    } else {
+                        for (index, controller) in &nominators {
+                            let label = labels.get(network, AccountType::Nominator, *index);
+                            display_nominators(
+                                controller.clone(),
+                                label,
+                                &client,
+                                network,
+                                output,
+                                token,
+                                eras,
+                                allowlist.as_ref(),
+                            )
+                            .await?
+                        }
+                        Ok(())
+                    }
+                }
+                .await;
+                if let Err(e) = rendered {
+                    if watch.is_none() {
+                        return Err(e)
+                    }
+                    client = connector.reconnect(e).await?;
+                    continue
+                }
+                match watch {
+                    Some(seconds) => async_std::task::sleep(Duration::from_secs(seconds)).await,
+                    None => return Ok(None),
+                }
+                client = match connector.refresh(client, &mut spec_version).await {
+                    Ok(client) => client,
+                    Err(e) => connector.reconnect(e).await?,
+                }
             }
-            Ok(None)
         }
 
-        Nominator::Nominate { index, set } => {
-            let path = LedgeracioPath::new(network, AccountType::Nominator, index)?;
-            let signer = keystore()?.signer(path).await?;
+        Nominator::Nominate {
+            index,
+            set,
+            strict,
+            no_verify,
+            allow_empty_set,
+            max_commission,
+            from_clipboard,
+            require_allowlisted,
+            stash,
+        } => {
+            let mut set = set;
+            if from_clipboard {
+                // These were not arguments, so they were not checked up front
+                let clipboard = clipboard_addresses()?;
+                crate::common::check_networks(&clipboard, network)?;
+                set.extend(clipboard)
+            }
             if set.is_empty() {
-                return Err("Validator set cannot be empty".to_owned().into())
+                if allow_empty_set {
+                    eprintln!("Validator set is empty: chilling instead");
+                    let call = ChillCall::<KusamaRuntime> {
+                        _runtime: PhantomData,
+                    };
+                    let client = connector.connect_staking().await?;
+                    if emit_call {
+                        return print_call(&client, call)
+                    }
+                    let keystore = keystore().await?;
+                    let index = signer_index(
+                        &client,
+                        &keystore,
+                        network,
+                        AccountType::Nominator,
+                        &index,
+                        stash,
+                    )
+                    .await?;
+                    let path = LedgeracioPath::new(network, AccountType::Nominator, index)?;
+                    let signer = keystore.signer(path).await?;
+                    return Ok(Some(client.submit(call, &signer).await?))
+                }
+                return Err(input_error("Validator set cannot be empty"))
             }
             let mut new_set = vec![];
             for (address, provided_network) in set {
                 if network != provided_network.try_into().unwrap() {
-                    return Err(format!(
+                    return Err(input_error(format!(
                         "Network mismatch: address {} is for network {}, but you asked to use \
                          network {}",
                         address,
                         String::from(Ss58AddressFormat::try_from(provided_network).unwrap()),
                         String::from(network),
-                    )
-                    .into())
+                    )))
                 }
                 new_set.push(address)
             }
-            Ok(Some(client.await?.nominate(&signer, new_set).await?))
+            let allowlist_key = match &*require_allowlisted {
+                [] => None,
+                [allowlist, public] => {
+                    let addresses = load_allowlist(allowlist, public, network)?;
+                    let off: Vec<_> = new_set
+                        .iter()
+                        .filter(|target| !addresses.contains(target))
+                        .collect();
+                    for target in &off {
+                        eprintln!(
+                            "{} is not on the allowlist",
+                            target.to_ss58check_with_version(network)
+                        )
+                    }
+                    if !off.is_empty() {
+                        return Err(input_error(format!(
+                            "{} of the targets are not on the allowlist, so the device would \
+                             refuse to sign",
+                            off.len()
+                        )))
+                    }
+                    Some(parse_public(&std::fs::read(public)?)?.0)
+                }
+                _ => return Err(input_error("--require-allowlisted may only be passed once")),
+            };
+            let client = connector.connect_staking().await?;
+            let mut unregistered = 0_usize;
+            let mut dropped = 0_usize;
+            let mut targets = vec![];
+            for target in new_set {
+                let store = ValidatorsStore {
+                    stash: target.clone(),
+                };
+                match client.fetch(&store, None).await? {
+                    None => {
+                        eprintln!(
+                            "Warning: {} is not a registered validator",
+                            target.to_ss58check_with_version(network)
+                        );
+                        unregistered += 1
+                    }
+                    Some(prefs) => {
+                        let commission = prefs.commission.deconstruct();
+                        if max_commission.map_or(false, |max| commission > max) {
+                            eprintln!(
+                                "Dropping {}: its commission is {}%",
+                                target.to_ss58check_with_version(network),
+                                pad(7, commission.into())
+                            );
+                            dropped += 1;
+                            continue
+                        }
+                    }
+                }
+                targets.push(target)
+            }
+            let new_set = targets;
+            if let Some(max) = max_commission.filter(|_| dropped > 0) {
+                eprintln!(
+                    "Dropped {} targets with a commission above {}%",
+                    dropped,
+                    pad(7, max.into())
+                );
+                if new_set.is_empty() {
+                    return Err(input_error(format!(
+                        "Every target has a commission above {}%",
+                        pad(7, max.into())
+                    )))
+                }
+            }
+            if strict && unregistered > 0 {
+                return Err(input_error(format!(
+                    "{} of the targets are not registered validators",
+                    unregistered
+                )))
+            }
+            if emit_call {
+                return print_call(&client, NominateCall { targets: new_set })
+            }
+            let keystore = keystore().await?;
+            if let Some(key) = allowlist_key {
+                if keystore.get_pubkey().await? != key.to_bytes() {
+                    return Err(input_error(
+                        "The allowlist is not signed by the key set on the device",
+                    ))
+                }
+            }
+            let index = signer_index(
+                &client,
+                &keystore,
+                network,
+                AccountType::Nominator,
+                &index,
+                stash,
+            )
+            .await?;
+            let path = LedgeracioPath::new(network, AccountType::Nominator, index)?;
+            let signer = keystore.signer(path).await?;
+            let ledger = client
+                .fetch(
+                    &LedgerStore {
+                        controller: signer.account_id().clone(),
+                    },
+                    None,
+                )
+                .await?;
+            if let Some(ledger) = &ledger {
+                let (decimals, symbol) = token.resolve(&client);
+                let report = crate::shares::report(
+                    &client,
+                    &ledger.stash,
+                    ledger.active,
+                    &new_set,
+                    network,
+                    decimals,
+                    &symbol,
+                );
+                if let Err(e) = report.await {
+                    eprintln!("Warning: could not estimate the stake distribution: {}", e)
+                }
+            }
+            let verify = !no_verify && connector.subscribes();
+            if !no_verify && !verify {
+                eprintln!(
+                    "Warning: the nomination will not be verified, as this requires a WebSocket \
+                     connection"
+                );
+            }
+            if !verify {
+                return Ok(Some(client.nominate(&signer, new_set).await?))
+            }
+            let stash = match ledger {
+                Some(ledger) => ledger.stash,
+                None => return Err(input_error("Account is not bonded")),
+            };
+            let success = client.nominate_and_watch(&signer, new_set.clone()).await?;
+            let stored = client
+                .fetch(&NominatorsStore { stash }, Some(success.block))
+                .await?
+                .map(|nominations| nominations.targets)
+                .unwrap_or_default();
+            report_nominations(&new_set, &stored, network);
+            Ok(Some(success.extrinsic))
+        }
+        Nominator::Rewards { index, eras } => {
+            let keystore = keystore().await?;
+            let index = select_index(index, AccountType::Nominator, &keystore, network).await?;
+            let path = LedgeracioPath::new(network, AccountType::Nominator, index)?;
+            let controller = keystore.signer(path).await?.account_id().clone();
+            let client = connector.connect_staking().await?;
+            let stash = match client.fetch(&LedgerStore { controller }, None).await? {
+                Some(ledger) => ledger.stash,
+                None => return Err(input_error("This account is not bonded")),
+            };
+            let targets = client
+                .fetch(
+                    &NominatorsStore {
+                        stash: stash.clone(),
+                    },
+                    None,
+                )
+                .await?
+                .map_or_else(Vec::new, |nominations| nominations.targets);
+            let rewards = crate::rewards::fetch_rewards(&client, &stash, &targets, eras).await?;
+            let (decimals, symbol) = token.resolve(&client);
+            print!("{}", crate::rewards::chart(&rewards, decimals, &symbol));
+            if let Some(price) = &token.fiat {
+                let total = rewards
+                    .iter()
+                    .fold(0_u128, |total, &(_, reward)| total.saturating_add(reward));
+                println!("Total value: {}", price.format(decimals, total))
+            }
+            Ok(None)
+        }
+        Nominator::Audit { threshold } => {
+            let keystore = keystore().await?;
+            let client = connector.connect_staking().await?;
+            let controllers = crate::common::fetch_validators(
+                &client,
+                crate::AddressSource::Device(&[], &keystore),
+                network,
+                AccountType::Nominator,
+            )
+            .await?;
+            crate::audit::report(&client, &controllers, threshold, network, output).await?;
+            Ok(None)
+        }
+        Nominator::Chill { all: true, .. } => {
+            if emit_call {
+                return Err(input_error("--all cannot be used with --emit-call"))
+            }
+            let keystore = keystore().await?;
+            let mut client = connector.connect_staking().await?;
+            let controllers = crate::common::fetch_validators(
+                &client,
+                crate::AddressSource::Device(&[], &keystore),
+                network,
+                AccountType::Nominator,
+            )
+            .await?;
+            let mut nominating = vec![];
+            for (index, controller) in controllers {
+                let store = LedgerStore {
+                    controller: controller.clone(),
+                };
+                let stash = match client.fetch(&store, None).await? {
+                    Some(ledger) => ledger.stash,
+                    None => continue,
+                };
+                if client
+                    .fetch(&NominatorsStore { stash }, None)
+                    .await?
+                    .is_some()
+                {
+                    nominating.push((index, controller))
+                }
+            }
+            // Check every fee before asking for any confirmation on the device
+            let (token_decimals, token_symbol) = token.resolve(&client);
+            let unfunded = crate::fees::check_fees(
+                connector,
+                &mut client,
+                &nominating,
+                |_| ChillCall::<KusamaRuntime> {
+                    _runtime: PhantomData,
+                },
+                keystore.tip_amount(),
+                network,
+                (token_decimals, &token_symbol),
+            )
+            .await?;
+            // `chill` must be dispatched by each controller, and a
+            // `Utility::batch_all` is dispatched by its one signer, so the
+            // calls cannot be batched into a single transaction.
+            let (mut chilled, mut failed) = (0_usize, unfunded.len());
+            for (index, _) in nominating {
+                if unfunded.contains(&index) {
+                    eprintln!("Skipping account {}: it cannot pay the fee", index);
+                    continue
+                }
+                let path = LedgeracioPath::new(network, AccountType::Nominator, index)?;
+                let result = async {
+                    let signer = &keystore.signer(path).await?;
+                    connector
+                        .retry(&mut client, |client| async move {
+                            Ok(client.chill(signer).await?)
+                        })
+                        .await
+                };
+                match result.await {
+                    Ok(hash) => {
+                        println!("Chilled account {}: transaction hash: {:?}", index, hash);
+                        chilled += 1
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to chill account {}: {}", index, e);
+                        failed += 1
+                    }
+                }
+            }
+            if failed > 0 {
+                return Err(format!(
+                    "Chilled {} accounts, but failed to chill {}",
+                    chilled, failed
+                )
+                .into())
+            }
+            if chilled == 0 {
+                println!("No accounts are nominating");
+            }
+            Ok(None)
         }
-        Nominator::Chill { index } => {
+        Nominator::Chill { index, stash, .. } => {
+            let call = ChillCall::<KusamaRuntime> {
+                _runtime: PhantomData,
+            };
+            if emit_call {
+                return print_call(&connector.connect_staking().await?, call)
+            }
+            let keystore = keystore().await?;
+            let client = connector.connect_staking().await?;
+            let index = match index {
+                Some(index) => index,
+                None => AccountRef::Index(
+                    select_index(None, AccountType::Nominator, &keystore, network).await?,
+                ),
+            };
+            let index = signer_index(
+                &client,
+                &keystore,
+                network,
+                AccountType::Nominator,
+                &index,
+                stash,
+            )
+            .await?;
             let path = LedgeracioPath::new(network, AccountType::Nominator, index)?;
-            let signer = keystore()?.signer(path).await?;
-            Ok(Some(client.await?.chill(&signer).await?))
+            let signer = keystore.signer(path).await?;
+            Ok(Some(client.submit(call, &signer).await?))
         }
-        Nominator::SetPayee { index, target } => {
+        Nominator::SetPayee {
+            index,
+            target,
+            force,
+            stash,
+        } => {
+            let call = SetPayeeCall::<KusamaRuntime> {
+                payee: target.clone(),
+            };
+            let client = connector.connect_staking().await?;
+            check_supported(&client, &target).await?;
+            if emit_call {
+                return print_call(&client, call)
+            }
+            let keystore = keystore().await?;
+            let index = signer_index(
+                &client,
+                &keystore,
+                network,
+                AccountType::Nominator,
+                &index,
+                stash,
+            )
+            .await?;
             let path = LedgeracioPath::new(network, AccountType::Nominator, index)?;
-            let signer = keystore()?.signer(path).await?;
-            Ok(Some(client.await?.set_payee(&signer, target).await?))
+            let signer = keystore.signer(path).await?;
+            if !force && payee_is(&client, signer.account_id(), &target).await? {
+                println!("Payee already set to {:?}, nothing to do", target);
+                return Ok(None)
+            }
+            Ok(Some(client.submit(call, &signer).await?))
         }
-        Nominator::Address { index } => {
-            crate::display_path(AccountType::Nominator, &keystore()?, network, index).await?;
+        Nominator::Address {
+            index,
+            display_network,
+            qr,
+            show_path,
+            raw,
+        } => {
+            let keystore = keystore().await?;
+            let index = select_index(index, AccountType::Nominator, &keystore, network).await?;
+            crate::display_path(
+                AccountType::Nominator,
+                &keystore,
+                network,
+                index,
+                display_network,
+                qr,
+                show_path,
+                raw,
+            )
+            .await?;
             Ok(None)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_address_lists() {
+        let list = "5DArCreQ9Yk2HaGvxcRHS35qky3eXBD5BprPZQvbiJBfFY6Y,\n \
+                    5GQvjFcJBCGTFeb2hvtQ9yRfbDNQajLJbW1yzgCra5uUTLvn\t";
+        let addresses = parse_address_list(list).unwrap();
+        assert_eq!(addresses.len(), 2);
+        assert!(addresses.iter().all(|&(_, network)| network == 42));
+        assert!(parse_address_list("").unwrap().is_empty());
+        assert!(parse_address_list("5DArCreQ9Yk2HaGvxcRHS35qky3eXBD5BprPZQvbiJBfFY6Y;x").is_err());
+    }
+
+    #[test]
+    fn describes_submission() {
+        assert_eq!(
+            describe_submission(10, 12),
+            "Submitted in era 10; active since era 11"
+        );
+        assert_eq!(
+            describe_submission(11, 12),
+            "Submitted in era 11; active since era 12"
+        );
+        assert_eq!(
+            describe_submission(12, 12),
+            "Not yet active (takes effect in era 13)"
+        );
+    }
+}
@@ -0,0 +1,99 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of ledgeracio.
+//
+// ledgeracio is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ledgeracio is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ledgeracio.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Reward destinations
+//!
+//! `substrate-subxt` models the reward destination without the `None`
+//! variant, so a payee of `None` could neither be set nor read back.  The
+//! type, the storage item, and the call that use it are declared here instead.
+
+use super::{fees::estimate_fee, rewards::Staking, AccountId, Error};
+use codec::{Decode, Encode};
+use ledgeracio::{categorize, CategorizedError, ErrorCategory};
+use substrate_subxt::{Call, Client, KusamaRuntime, Store};
+
+/// Where the staking rewards of a stash are paid
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub enum Payee<AccountId> {
+    /// Pay into the stash, increasing the amount at stake
+    Staked,
+    /// Pay into the stash, without increasing the amount at stake
+    Stash,
+    /// Pay into the controller
+    Controller,
+    /// Pay into the given account
+    Account(AccountId),
+    /// Do not pay rewards at all.  Not every runtime supports this.
+    None,
+}
+
+/// The reward destination of a stash
+#[derive(Clone, Debug, Eq, PartialEq, Store, Encode)]
+pub struct PayeeStore<T: Staking> {
+    #[store(returns = Payee<T::AccountId>)]
+    pub stash: T::AccountId,
+}
+
+/// Set the reward destination of the stash controlled by the sender
+#[derive(Clone, Debug, PartialEq, Call, Encode)]
+pub struct SetPayeeCall<T: Staking> {
+    pub payee: Payee<T::AccountId>,
+}
+
+/// Check that the runtime supports setting the payee to `payee`.
+///
+/// The metadata only gives the name of the argument’s type, not its variants,
+/// so instead the node is asked to estimate the fee of the call.  This fails
+/// if the runtime cannot decode the call.  Only `None` is checked, as the other
+/// variants are supported by every runtime.  Errors reaching the node are
+/// returned unchanged.
+pub(crate) async fn check_supported(
+    client: &Client<KusamaRuntime>,
+    payee: &Payee<AccountId>,
+) -> Result<(), Error> {
+    if *payee != Payee::None {
+        return Ok(())
+    }
+    let call = SetPayeeCall::<KusamaRuntime> {
+        payee: payee.clone(),
+    };
+    match estimate_fee(client, call, &AccountId::from([0; 32]), 0).await {
+        Ok(_) => Ok(()),
+        Err(e) if categorize(&e) == ErrorCategory::Chain => Err(Box::new(CategorizedError::new(
+            ErrorCategory::Chain,
+            format!(
+                "This runtime does not appear to support a payee of `None`: {}",
+                e
+            ),
+        ))),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_like_the_runtime() {
+        assert_eq!(Payee::<AccountId>::Staked.encode(), [0]);
+        assert_eq!(Payee::<AccountId>::Controller.encode(), [2]);
+        assert_eq!(Payee::<AccountId>::None.encode(), [4]);
+        let account = Payee::Account(AccountId::from([7; 32]));
+        assert_eq!(account.encode()[0], 3);
+        assert_eq!(Payee::decode(&mut &*account.encode()).ok(), Some(account));
+    }
+}
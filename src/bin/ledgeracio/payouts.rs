@@ -16,30 +16,74 @@
 
 //! Payouts handling
 
-use crate::Error;
+use crate::{common::history_depth, rewards::Staking, Error};
+use codec::Encode;
 use futures::{future::join3,
-              stream::{FuturesUnordered, StreamExt as _}};
+              stream::{self, StreamExt as _}};
 use log::trace;
-use std::marker::PhantomData;
+use std::{convert::TryFrom, marker::PhantomData};
 use substrate_subxt::{sp_core::crypto::AccountId32 as AccountId,
                       sp_runtime::traits::Zero,
-                      staking::{CurrentEraStore, ErasRewardPointsStore, HistoryDepthStore,
-                                LedgerStore, StakingLedger},
-                      Client, KusamaRuntime};
+                      staking::{CurrentEraStore, ErasRewardPointsStore, LedgerStore,
+                                StakingLedger},
+                      Call, Client, KusamaRuntime};
 
+/// Pay out the rewards of a validator and all of its nominators for an era.
+/// Anyone can submit this.
+#[derive(Clone, Debug, PartialEq, Call, Encode)]
+pub struct PayoutStakersCall<T: Staking> {
+    /// The stash of the validator
+    pub validator_stash: T::AccountId,
+    /// The era to pay out
+    pub era: u32,
+}
+
+/// How many eras are checked for unclaimed payouts if the chain does not
+/// report its history depth.  This is the staking pallet’s default.
+const DEFAULT_HISTORY_DEPTH: u32 = 84;
+
+/// How many eras are checked for unclaimed payouts at once.  Checking every
+/// era at once can overwhelm the node when the history is long.
+pub(crate) const CONCURRENT_ERAS: usize = 16;
+
+/// Convert a duration in seconds to the number of eras it spans, rounding up.
+/// The length of an era is computed from the chain’s constants.
+pub(crate) fn eras_in(client: &Client<KusamaRuntime>, seconds: u64) -> Result<u32, Error> {
+    let metadata = client.metadata();
+    let epoch_duration: u64 = metadata
+        .module("Babe")?
+        .constant("EpochDuration")?
+        .value()?;
+    let block_time_ms: u64 = metadata
+        .module("Babe")?
+        .constant("ExpectedBlockTime")?
+        .value()?;
+    let sessions_per_era: u32 = metadata
+        .module("Staking")?
+        .constant("SessionsPerEra")?
+        .value()?;
+    let era_ms = epoch_duration
+        .saturating_mul(block_time_ms)
+        .saturating_mul(sessions_per_era.into());
+    if era_ms == 0 {
+        return Err("Chain reports an era length of zero".to_owned().into())
+    }
+    let ms = seconds.saturating_mul(1000);
+    Ok(u32::try_from((ms + era_ms - 1) / era_ms).unwrap_or(u32::max_value()))
+}
+
+/// Fetch the eras for which `controller` has unclaimed payouts.  If `eras` is
+/// `Some`, only that many of the most recent eras are checked.  If stderr is a
+/// terminal, progress is shown there.
 pub(crate) async fn display_payouts(
     controller: AccountId,
     client: &Client<KusamaRuntime>,
+    eras: Option<u32>,
 ) -> Result<Vec<u32>, Error> {
     let store = LedgerStore {
         controller: controller.clone(),
     };
-    let history_depth = client.fetch_or_default(
-        &HistoryDepthStore {
-            _runtime: PhantomData,
-        },
-        None,
-    );
+    let history_depth = history_depth(client);
     let current_era = client.fetch_or_default(
         &CurrentEraStore {
             _runtime: PhantomData,
@@ -60,18 +104,19 @@ pub(crate) async fn display_payouts(
     };
     let (history_depth, account_info, current_era) =
         join3(history_depth, fetch_account_info, current_era).await;
-    let history_depth = history_depth?;
+    let history_depth = history_depth?.unwrap_or(DEFAULT_HISTORY_DEPTH);
     let (validator_stash, claimed_rewards): (AccountId, _) = account_info?;
     let current_era = current_era?;
-    let history_start = current_era.saturating_sub(history_depth);
-    let mut futures = FuturesUnordered::new();
+    let history_start =
+        current_era.saturating_sub(eras.map_or(history_depth, |eras| eras.min(history_depth)));
     trace!("Claimed rewards: {:?}", claimed_rewards);
-    for era in history_start..=current_era {
-        if claimed_rewards.binary_search(&era).is_ok() {
-            continue
-        }
-        let validator_stash = validator_stash.clone();
-        let future = async move {
+    let unclaimed: Vec<u32> = (history_start..=current_era)
+        .filter(|era| claimed_rewards.binary_search(era).is_err())
+        .collect();
+    let total = unclaimed.len();
+    let validator_stash = &validator_stash;
+    let mut results = stream::iter(unclaimed)
+        .map(|era| async move {
             let rewards = ErasRewardPointsStore {
                 index: era,
                 _phantom: PhantomData,
@@ -80,17 +125,25 @@ pub(crate) async fn display_payouts(
             let s: Result<_, Error> = Ok((
                 era_reward_points
                     .individual
-                    .get(&validator_stash)
+                    .get(validator_stash)
                     .cloned()
                     .unwrap_or_else(Zero::zero),
                 era,
             ));
             s
-        };
-        futures.push(future);
-    }
+        })
+        .buffer_unordered(CONCURRENT_ERAS);
+    let progress = atty::is(atty::Stream::Stderr);
     let mut eras = vec![];
-    while let Some(e) = futures.next().await {
+    let mut checked = 0_usize;
+    while let Some(e) = results.next().await {
+        checked += 1;
+        if progress {
+            eprint!(
+                "\rChecking eras for unclaimed payouts: {}/{}",
+                checked, total
+            );
+        }
         let (points, era) = e?;
         if points == 0 {
             trace!("Skipping era {} as it has no points", era);
@@ -99,5 +152,10 @@ pub(crate) async fn display_payouts(
             eras.push(era);
         }
     }
+    if progress && total > 0 {
+        // Erase the progress indicator
+        eprint!("\r\x1b[K");
+    }
+    eras.sort_unstable();
     Ok(eras)
 }
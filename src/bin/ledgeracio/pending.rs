@@ -0,0 +1,108 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of ledgeracio.
+//
+// ledgeracio is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ledgeracio is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ledgeracio.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Looking up the status of a previously submitted transaction
+
+use super::Error;
+use codec::Encode;
+use jsonrpsee::common::Params;
+use substrate_subxt::{sp_core::{blake2_256, Bytes, H256},
+                      Client, KusamaRuntime};
+
+/// Where a transaction was found
+enum Status {
+    /// In the node’s transaction pool
+    Pending,
+    /// In the block with the given number and hash, at the given index
+    Included(u32, H256, usize),
+}
+
+async fn find(
+    client: &Client<KusamaRuntime>,
+    hash: H256,
+    depth: u32,
+) -> Result<Option<Status>, Error> {
+    let pending: Vec<Bytes> = client
+        .rpc_client()
+        .request("author_pendingExtrinsics", Params::None)
+        .await?;
+    if pending.iter().any(|tx| H256(blake2_256(&tx.0)) == hash) {
+        return Ok(Some(Status::Pending))
+    }
+    let mut block_hash = match client.block_hash(None).await? {
+        Some(block_hash) => block_hash,
+        None => return Err("Node did not return its best block".to_owned().into()),
+    };
+    for _ in 0..depth {
+        let block = match client.block(Some(block_hash)).await? {
+            Some(block) => block.block,
+            None => break,
+        };
+        for (index, extrinsic) in block.extrinsics.iter().enumerate() {
+            if H256(blake2_256(&extrinsic.encode())) == hash {
+                return Ok(Some(Status::Included(
+                    block.header.number,
+                    block_hash,
+                    index,
+                )))
+            }
+        }
+        if block.header.number == 0 {
+            break
+        }
+        block_hash = block.header.parent_hash;
+    }
+    Ok(None)
+}
+
+/// Report whether the transaction with hash `hash` is pending, or was included
+/// in one of the most recent `depth` blocks.
+pub(crate) async fn main(
+    client: &Client<KusamaRuntime>,
+    hash: H256,
+    depth: u32,
+) -> Result<(), Error> {
+    match find(client, hash, depth).await? {
+        Some(Status::Pending) => println!(
+            "Transaction {:?} is pending in the node’s transaction pool",
+            hash
+        ),
+        Some(Status::Included(number, block_hash, index)) => {
+            let finalized = client.finalized_head().await?;
+            let finalized = client
+                .header(Some(finalized))
+                .await?
+                .map_or(0, |header| header.number);
+            println!(
+                "Transaction {:?} was included in block #{} ({:?}) at index {}, and is {}",
+                hash,
+                number,
+                block_hash,
+                index,
+                if number <= finalized {
+                    "finalized"
+                } else {
+                    "not yet finalized"
+                }
+            )
+        }
+        None => println!(
+            "Transaction {:?} is not pending, and was not found in the last {} blocks",
+            hash, depth
+        ),
+    }
+    Ok(())
+}
@@ -0,0 +1,129 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of ledgeracio.
+//
+// ledgeracio is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ledgeracio is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ledgeracio.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Measuring the latency of RPC hosts
+
+use super::{common::{format_table, OutputFormat},
+            connection::Connector,
+            Error};
+use ledgeracio::{input_error, CategorizedError, ErrorCategory};
+use std::{convert::TryFrom, time::Instant};
+use substrate_subxt::sp_core::H256;
+
+/// The latency of a reachable host, in milliseconds
+struct Latency {
+    connect: u64,
+    min: u64,
+    mean: u64,
+}
+
+/// The milliseconds elapsed since `start`
+fn millis(start: Instant) -> u64 {
+    u64::try_from(start.elapsed().as_millis()).unwrap_or(u64::max_value())
+}
+
+/// Connect to the host of `connector`, and time `count` `chain_getHeader`
+/// requests
+async fn measure(connector: &Connector, count: u32) -> Result<Latency, Error> {
+    let start = Instant::now();
+    let client = connector.connect().await?;
+    let connect = millis(start);
+    let mut min = u64::max_value();
+    let mut total = 0_u64;
+    for _ in 0..count {
+        let start = Instant::now();
+        client.header(None::<H256>).await?;
+        let elapsed = millis(start);
+        min = min.min(elapsed);
+        total = total.saturating_add(elapsed);
+    }
+    Ok(Latency {
+        connect,
+        min,
+        mean: total / u64::from(count),
+    })
+}
+
+/// Measure the latency of each of `hosts`, or of the host of `connector` if
+/// `hosts` is empty, and report the fastest reachable host
+pub(crate) async fn main(
+    connector: &Connector,
+    hosts: Vec<String>,
+    count: u32,
+    output: OutputFormat,
+) -> Result<(), Error> {
+    if count == 0 {
+        return Err(input_error("--count must not be zero"))
+    }
+    let hosts = if hosts.is_empty() {
+        vec![connector.host().to_owned()]
+    } else {
+        hosts
+    };
+    let mut rows = vec![["HOST", "CONNECT", "MIN", "MEAN", "STATUS"]
+        .iter()
+        .map(|&header| header.to_owned())
+        .collect::<Vec<_>>()];
+    let mut fastest: Option<(&str, u64)> = None;
+    for host in &hosts {
+        let result = measure(&connector.with_host(host.clone()), count).await;
+        if output == OutputFormat::Jsonl {
+            println!("{}", match &result {
+                Ok(latency) => serde_json::json!({
+                    "host": host,
+                    "reachable": true,
+                    "connect_ms": latency.connect,
+                    "min_ms": latency.min,
+                    "mean_ms": latency.mean,
+                }),
+                Err(e) => serde_json::json!({
+                    "host": host,
+                    "reachable": false,
+                    "error": e.to_string(),
+                }),
+            })
+        }
+        match result {
+            Ok(latency) => {
+                if fastest.map_or(true, |(_, mean)| latency.mean < mean) {
+                    fastest = Some((host, latency.mean))
+                }
+                rows.push(vec![
+                    host.clone(),
+                    format!("{} ms", latency.connect),
+                    format!("{} ms", latency.min),
+                    format!("{} ms", latency.mean),
+                    "reachable".to_owned(),
+                ])
+            }
+            Err(e) => rows.push(vec![
+                host.clone(),
+                "-".to_owned(),
+                "-".to_owned(),
+                "-".to_owned(),
+                format!("unreachable: {}", e),
+            ]),
+        }
+    }
+    let (fastest, _) = fastest.ok_or_else(|| {
+        CategorizedError::new(ErrorCategory::Network, "None of the hosts are reachable")
+    })?;
+    if output != OutputFormat::Jsonl {
+        print!("{}", format_table(&rows, &[1, 2, 3]));
+        println!("Fastest: {}", fastest)
+    }
+    Ok(())
+}
@@ -0,0 +1,200 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of ledgeracio.
+//
+// ledgeracio is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ledgeracio is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ledgeracio.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Era points of validators, relative to the rest of the active set
+//!
+//! Raw point totals depend on the number of blocks in the era and the size of
+//! the active set, so each validator’s points are compared with the mean of
+//! the validators that earned points in the same era.
+
+use super::{common::OutputFormat, payouts::CONCURRENT_ERAS, AccountId, Error};
+use futures::stream::{self, StreamExt as _};
+use std::marker::PhantomData;
+use substrate_subxt::{sp_core::crypto::Ss58AddressFormat,
+                      staking::{CurrentEraStore, EraRewardPoints, ErasRewardPointsStore,
+                                LedgerStore},
+                      Client, KusamaRuntime};
+
+/// How a validator’s era points compare with the mean of the active set
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Performance {
+    /// The validator’s points in each era it earned points in, as a
+    /// percentage of that era’s mean
+    relative: Vec<(u32, u64)>,
+}
+
+impl Performance {
+    /// Compute the performance of `stash` in `eras`
+    fn new(stash: &AccountId, eras: &[(u32, EraRewardPoints<AccountId>)]) -> Self {
+        let relative = eras
+            .iter()
+            .filter_map(|(era, points)| {
+                let own = u64::from(*points.individual.get(stash)?);
+                let validators = points.individual.len() as u64;
+                let percent = (own * validators * 100)
+                    .checked_div(points.total.into())
+                    .unwrap_or(0);
+                Some((*era, percent))
+            })
+            .collect();
+        Self { relative }
+    }
+
+    /// The mean of the relative points, as a percentage
+    fn average(&self) -> Option<u64> {
+        let sum: u64 = self.relative.iter().map(|&(_, percent)| percent).sum();
+        sum.checked_div(self.relative.len() as u64)
+    }
+
+    /// How many eras the validator was below the mean in
+    fn below_mean(&self) -> usize { self.relative.iter().filter(|&&(_, p)| p < 100).count() }
+
+    /// Whether the validator was below the mean in at least three quarters of
+    /// the eras it earned points in
+    fn underperforming(&self) -> bool {
+        !self.relative.is_empty() && self.below_mean() * 4 >= self.relative.len() * 3
+    }
+}
+
+/// Fetch the reward points of the last `eras` completed eras, oldest first
+async fn fetch_points(
+    client: &Client<KusamaRuntime>,
+    eras: u32,
+) -> Result<Vec<(u32, EraRewardPoints<AccountId>)>, Error> {
+    let current_era = client
+        .fetch_or_default(
+            &CurrentEraStore {
+                _runtime: PhantomData,
+            },
+            None,
+        )
+        .await?;
+    let mut points = stream::iter(current_era.saturating_sub(eras)..current_era)
+        .map(|index| async move {
+            let store = ErasRewardPointsStore {
+                index,
+                _phantom: PhantomData,
+            };
+            Ok::<_, Error>((index, client.fetch_or_default(&store, None).await?))
+        })
+        .buffer_unordered(CONCURRENT_ERAS)
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    points.sort_unstable_by_key(|&(era, _)| era);
+    Ok(points)
+}
+
+/// Report the era points of the validators with controllers `controllers`
+/// over the last `eras` completed eras, relative to the mean of each era
+pub(crate) async fn report(
+    client: &Client<KusamaRuntime>,
+    controllers: &[(u32, AccountId)],
+    eras: u32,
+    network: Ss58AddressFormat,
+    output: OutputFormat,
+) -> Result<(), Error> {
+    let points = fetch_points(client, eras).await?;
+    if output != OutputFormat::Jsonl {
+        println!(
+            "Era points relative to the mean, over the last {} eras:",
+            points.len()
+        );
+    }
+    for (index, controller) in controllers {
+        let store = LedgerStore {
+            controller: controller.clone(),
+        };
+        let stash = match client.fetch(&store, None).await? {
+            Some(ledger) => ledger.stash,
+            None => continue,
+        };
+        let performance = Performance::new(&stash, &points);
+        let address = ledgeracio::device_address(&stash, network);
+        if output == OutputFormat::Jsonl {
+            println!(
+                "{}",
+                serde_json::json!({
+                    "index": index,
+                    "stash": address,
+                    "eras": performance
+                        .relative
+                        .iter()
+                        .map(|&(era, percent)| serde_json::json!({
+                            "era": era,
+                            "percent_of_mean": percent,
+                        }))
+                        .collect::<Vec<_>>(),
+                    "average_percent_of_mean": performance.average(),
+                    "underperforming": performance.underperforming(),
+                })
+            );
+            continue
+        }
+        match performance.average() {
+            None => println!("    {}: not active in these eras", address),
+            Some(average) => println!(
+                "    {}: {}% of the mean on average, below the mean in {} of {} active eras{}",
+                address,
+                average,
+                performance.below_mean(),
+                performance.relative.len(),
+                if performance.underperforming() {
+                    " (UNDERPERFORMING)"
+                } else {
+                    ""
+                }
+            ),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn era(total: u32, individual: &[(u8, u32)]) -> EraRewardPoints<AccountId> {
+        EraRewardPoints {
+            total,
+            individual: individual
+                .iter()
+                .map(|&(id, points)| (AccountId::from([id; 32]), points))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn compares_with_the_mean() {
+        let eras = vec![
+            (1, era(300, &[(1, 50), (2, 250)])),
+            (2, era(200, &[(2, 200)])),
+            (3, era(400, &[(1, 100), (2, 300)])),
+            (4, era(200, &[(1, 100), (2, 100)])),
+        ];
+        let performance = Performance::new(&[1; 32].into(), &eras);
+        assert_eq!(performance.relative, vec![(1, 33), (3, 50), (4, 100)]);
+        assert_eq!(performance.average(), Some(61));
+        assert_eq!(performance.below_mean(), 2);
+        assert!(!performance.underperforming());
+        let performance = Performance::new(&[1; 32].into(), &eras[..3]);
+        assert!(performance.underperforming());
+        let performance = Performance::new(&[3; 32].into(), &eras);
+        assert_eq!(performance.average(), None);
+        assert!(!performance.underperforming());
+    }
+}
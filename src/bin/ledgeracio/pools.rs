@@ -0,0 +1,150 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of ledgeracio.
+//
+// ledgeracio is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ledgeracio is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ledgeracio.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Nomination pool commands.  Requires a network connection.
+//!
+//! `substrate-subxt` does not ship bindings for the nomination pools pallet,
+//! and not every chain has it, so the storage we need is declared here and
+//! the pallet is looked up in the metadata first.
+
+use super::{common::{pad, select_index, TokenOverrides},
+            connection::Connector,
+            AccountId, AccountType, Error, LedgeracioPath, StructOpt};
+use codec::{Decode, Encode};
+use core::future::Future;
+use ledgeracio::{parse_address, validate_network};
+use std::collections::BTreeMap;
+use substrate_subxt::{balances::Balances, module, sp_core::crypto::Ss58AddressFormat,
+                      KusamaRuntime, Signer, Store};
+
+/// The nomination pools pallet
+#[module]
+pub trait NominationPools: Balances {}
+
+impl NominationPools for KusamaRuntime {}
+
+/// A member of a nomination pool
+#[derive(Clone, Debug, Decode, PartialEq, Eq)]
+pub struct PoolMember<Balance> {
+    /// The pool the account is a member of
+    pub pool_id: u32,
+    /// The member’s points in the pool’s bonded balance
+    pub points: Balance,
+    /// The reward counter when the member last claimed rewards
+    pub last_recorded_reward_counter: u128,
+    /// Points being unbonded, by the era in which they can be withdrawn
+    pub unbonding_eras: BTreeMap<u32, Balance>,
+}
+
+/// The pool membership of an account
+#[derive(Clone, Debug, Eq, PartialEq, Store, Encode)]
+pub struct PoolMembersStore<T: NominationPools> {
+    #[store(returns = PoolMember<T::Balance>)]
+    pub who: T::AccountId,
+}
+
+#[derive(StructOpt, Debug)]
+pub(crate) enum PoolCommand {
+    /// Show the nomination pool membership of an account.  This does not
+    /// require a Ledger device if `--member` is given.
+    Show {
+        /// The index of the account on the device.  If neither this nor
+        /// `--member` is given, pick the account interactively.
+        #[structopt(conflicts_with = "member")]
+        index: Option<u32>,
+        /// The address of the member
+        #[structopt(long, parse(try_from_str = parse_address))]
+        member: Option<(AccountId, u8)>,
+    },
+}
+
+impl PoolCommand {
+    /// The addresses given as arguments, with the networks they were encoded
+    /// for
+    pub(crate) fn addresses(&self) -> Vec<&(AccountId, u8)> {
+        match self {
+            Self::Show { member, .. } => member.iter().collect(),
+        }
+    }
+}
+
+pub(crate) async fn main<T: FnOnce() -> U, U: Future<Output = Result<super::HardStore, Error>>>(
+    cmd: PoolCommand,
+    connector: &Connector,
+    network: Ss58AddressFormat,
+    keystore: T,
+    token: &TokenOverrides,
+) -> Result<(), Error> {
+    match cmd {
+        PoolCommand::Show { index, member } => {
+            let member = match member {
+                Some((member, provided_network)) => {
+                    validate_network("", provided_network, network)?;
+                    member
+                }
+                None => {
+                    let keystore = keystore().await?;
+                    let index =
+                        select_index(index, AccountType::Nominator, &keystore, network).await?;
+                    let path = LedgeracioPath::new(network, AccountType::Nominator, index)?;
+                    keystore.signer(path).await?.account_id().clone()
+                }
+            };
+            let client = connector.connect().await?;
+            if client.metadata().module("NominationPools").is_err() {
+                println!("The nominationPools pallet is not present on this chain");
+                return Ok(())
+            }
+            let address = ledgeracio::device_address(&member, network);
+            let PoolMember {
+                pool_id,
+                points,
+                unbonding_eras,
+                ..
+            } = match client
+                .fetch(&PoolMembersStore { who: member }, None)
+                .await?
+            {
+                Some(pool_member) => pool_member,
+                None => {
+                    println!("{} is not a member of any nomination pool", address);
+                    return Ok(())
+                }
+            };
+            // Points are denominated like balances, but are not balances
+            let (decimals, _) = token.resolve(&client);
+            println!(
+                "Member: {}\nPool: {}\nPoints: {}",
+                address,
+                pool_id,
+                pad(decimals, points)
+            );
+            if unbonding_eras.is_empty() {
+                println!("Unbonding: none");
+            } else {
+                println!("Unbonding:");
+                for (era, points) in unbonding_eras {
+                    println!(
+                        "    {} points (withdrawable in era {})",
+                        pad(decimals, points),
+                        era
+                    );
+                }
+            }
+            Ok(())
+        }
+    }
+}
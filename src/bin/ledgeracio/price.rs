@@ -0,0 +1,189 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of ledgeracio.
+//
+// ledgeracio is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ledgeracio is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ledgeracio.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Approximate fiat values of balances, for `--fiat` and `--price-source`
+//!
+//! The price is fetched once per command, with an HTTP `GET`, from an endpoint
+//! chosen by the user.  Redirects are followed, proxies configured in the
+//! environment are used, and the response must arrive within [`TIMEOUT`] and
+//! fit in [`MAX_RESPONSE`].  The response must be JSON: either the price
+//! itself, or an object in which the price is the value of a key named after
+//! the currency, such as `{"polkadot":{"usd":4.56}}`.  Values are only
+//! estimates, and are always marked as such.
+
+use super::{common::pad, Error};
+use futures::io::AsyncReadExt;
+use isahc::{config::{RedirectPolicy, SslOption},
+            HttpClient};
+use ledgeracio::{input_error, CategorizedError, ErrorCategory};
+use serde_json::Value;
+use std::time::Duration;
+
+/// The price of the token in a fiat currency
+#[derive(Debug, Clone)]
+pub(crate) struct FiatPrice {
+    /// The currency code, such as `USD`
+    pub(crate) currency: String,
+    /// The price of one token
+    pub(crate) price: f64,
+}
+
+impl FiatPrice {
+    /// The approximate fiat value of `value` planck, for a token with
+    /// `decimals` decimals
+    pub(crate) fn value(&self, decimals: u8, value: u128) -> f64 {
+        pad(decimals, value).parse::<f64>().unwrap_or(0.0) * self.price
+    }
+
+    /// The fiat value of `value`, marked as an estimate
+    pub(crate) fn format(&self, decimals: u8, value: u128) -> String {
+        format!(
+            "≈ {:.2} {}, estimated",
+            self.value(decimals, value),
+            self.currency
+        )
+    }
+
+    /// A note giving the fiat value of `value`, to follow a balance
+    pub(crate) fn describe(&self, decimals: u8, value: u128) -> String {
+        format!(" ({})", self.format(decimals, value))
+    }
+}
+
+/// Find the price in `json`: a number or numeric string, or the value of the
+/// first key equal to `currency`, ignoring case, at any depth
+fn find_price(json: &Value, currency: &str) -> Option<f64> {
+    match json {
+        Value::Number(price) => price.as_f64(),
+        Value::String(price) => price.parse().ok(),
+        Value::Object(object) => object
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(currency))
+            .and_then(|(_, value)| find_price(value, currency))
+            .or_else(|| {
+                object
+                    .values()
+                    .filter(|value| value.is_object())
+                    .find_map(|value| find_price(value, currency))
+            }),
+        _ => None,
+    }
+}
+
+/// The largest response body accepted from the price source
+const MAX_RESPONSE: u64 = 1 << 20;
+
+/// How long fetching the price, redirects included, may take
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How many redirects are followed before giving up
+const MAX_REDIRECTS: u32 = 5;
+
+/// Send a `GET` request for `url`, following redirects, and return the body of
+/// the response.  Certificates are checked unless
+/// `insecure_accept_invalid_certs` is set, as for the node connection.
+async fn get(url: &url::Url, insecure_accept_invalid_certs: bool) -> Result<Vec<u8>, Error> {
+    let mut builder = HttpClient::builder()
+        .timeout(TIMEOUT)
+        .redirect_policy(RedirectPolicy::Limit(MAX_REDIRECTS))
+        .default_header("Accept", "application/json");
+    if insecure_accept_invalid_certs {
+        builder = builder.ssl_options(SslOption::DANGER_ACCEPT_INVALID_CERTS);
+    }
+    let client = builder.build()?;
+    let response = client.get_async(url.as_str()).await?;
+    if !response.status().is_success() {
+        return Err(format!("Price source returned {}", response.status()).into())
+    }
+    let mut body = vec![];
+    response
+        .into_body()
+        .take(MAX_RESPONSE + 1)
+        .read_to_end(&mut body)
+        .await?;
+    if body.len() as u64 > MAX_RESPONSE {
+        return Err(format!("response is larger than {} bytes", MAX_RESPONSE).into())
+    }
+    Ok(body)
+}
+
+/// Fetch the price of the token in `currency` from `source`
+pub(crate) async fn fetch(
+    source: &str,
+    currency: &str,
+    insecure_accept_invalid_certs: bool,
+) -> Result<FiatPrice, Error> {
+    let url =
+        url::Url::parse(source).map_err(|e| input_error(format!("Invalid price source: {}", e)))?;
+    match url.scheme() {
+        "http" | "https" => {}
+        scheme => {
+            return Err(input_error(format!(
+                "Price source must be an http:// or https:// URL, not {}://",
+                scheme
+            )))
+        }
+    }
+    let unavailable = |e: Error| -> Error {
+        Box::new(CategorizedError::new(
+            ErrorCategory::Network,
+            format!("Cannot fetch the {} price from {}: {}", currency, source, e),
+        ))
+    };
+    let body = get(&url, insecure_accept_invalid_certs)
+        .await
+        .map_err(unavailable)?;
+    let json: Value = serde_json::from_slice(&body).map_err(|e| unavailable(e.into()))?;
+    let price = find_price(&json, currency)
+        .filter(|price| price.is_finite() && *price >= 0.0)
+        .ok_or_else(|| unavailable(format!("no {} price in the response", currency).into()))?;
+    Ok(FiatPrice {
+        currency: currency.to_ascii_uppercase(),
+        price,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    #[allow(clippy::float_cmp)] // the prices are exact
+    fn finds_price() {
+        assert_eq!(find_price(&json!(4.5), "USD"), Some(4.5));
+        assert_eq!(find_price(&json!("4.5"), "USD"), Some(4.5));
+        assert_eq!(
+            find_price(&json!({"polkadot": {"eur": 4.0, "usd": 4.5}}), "USD"),
+            Some(4.5)
+        );
+        assert_eq!(find_price(&json!({"USD": "2"}), "usd"), Some(2.0));
+        assert_eq!(find_price(&json!({"polkadot": {"eur": 4.0}}), "USD"), None);
+        assert_eq!(find_price(&json!([4.5]), "USD"), None);
+    }
+
+    #[test]
+    fn describes_value() {
+        let price = FiatPrice {
+            currency: "USD".to_owned(),
+            price: 4.5,
+        };
+        assert_eq!(
+            price.describe(10, 20_000_000_000),
+            " (≈ 9.00 USD, estimated)"
+        );
+    }
+}
@@ -0,0 +1,234 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of ledgeracio.
+//
+// ledgeracio is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ledgeracio is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ledgeracio.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Validator metrics in the Prometheus text format, for the textfile collector
+//! of `node_exporter`
+
+use super::{common::{active_era, pad},
+            rewards::Staking,
+            shares::ErasStakersStore,
+            AccountId, Error};
+use codec::{Compact, Decode, Encode, Input};
+use std::marker::PhantomData;
+use substrate_subxt::{sp_core::crypto::Ss58AddressFormat,
+                      staking::{ErasRewardPointsStore, LedgerStore},
+                      Client, KusamaRuntime, Store};
+
+/// The preferences of a validator.  Runtimes before validators could block
+/// nominations do not store `blocked`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Prefs {
+    /// The commission, in parts per billion
+    commission: u32,
+    blocked: Option<bool>,
+}
+
+impl Decode for Prefs {
+    fn decode<I: Input>(input: &mut I) -> Result<Self, codec::Error> {
+        let commission = Compact::<u32>::decode(input)?.0;
+        Ok(Self {
+            commission,
+            blocked: bool::decode(input).ok(),
+        })
+    }
+}
+
+/// The preferences of a validator that is validating
+#[derive(Clone, Debug, Eq, PartialEq, Store, Encode)]
+pub struct ValidatorsStore<T: Staking> {
+    #[store(returns = Prefs)]
+    pub stash: T::AccountId,
+}
+
+/// The metrics of one validator
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Sample {
+    index: u32,
+    stash: String,
+    /// The bonded stake, in tokens
+    stake: String,
+    /// The preferences, if the validator is validating
+    prefs: Option<Prefs>,
+    /// The era points earned in the active era
+    era_points: u32,
+    /// Whether the validator is in the active set of the active era
+    active: bool,
+}
+
+/// The metrics of each validator, as `(name, help, value)`.  A value of `None`
+/// omits the sample.
+type Metric = (&'static str, &'static str, fn(&Sample) -> Option<String>);
+
+const METRICS: &[Metric] = &[
+    (
+        "ledgeracio_validator_stake",
+        "Bonded stake of the validator, in tokens",
+        |sample| Some(sample.stake.clone()),
+    ),
+    (
+        "ledgeracio_validator_commission",
+        "Commission of the validator, as a fraction",
+        |sample| Some(pad(9, sample.prefs.as_ref()?.commission.into())),
+    ),
+    (
+        "ledgeracio_validator_era_points",
+        "Era points earned by the validator in the active era",
+        |sample| Some(sample.era_points.to_string()),
+    ),
+    (
+        "ledgeracio_validator_validating",
+        "Whether the validator intends to validate",
+        |sample| Some(u8::from(sample.prefs.is_some()).to_string()),
+    ),
+    (
+        "ledgeracio_validator_active",
+        "Whether the validator is in the active set of the active era",
+        |sample| Some(u8::from(sample.active).to_string()),
+    ),
+    (
+        "ledgeracio_validator_blocked",
+        "Whether the validator blocks new nominations",
+        |sample| Some(u8::from(sample.prefs.as_ref()?.blocked?).to_string()),
+    ),
+];
+
+/// Render `samples` in the Prometheus text format.  Samples of the same metric
+/// must be grouped together.
+fn render(samples: &[Sample]) -> String {
+    let mut rendered = String::new();
+    for (name, help, value) in METRICS {
+        rendered += &format!("# HELP {} {}\n# TYPE {} gauge\n", name, help, name);
+        for sample in samples {
+            if let Some(value) = value(sample) {
+                rendered += &format!(
+                    "{}{{index=\"{}\",stash=\"{}\"}} {}\n",
+                    name, sample.index, sample.stash, value
+                )
+            }
+        }
+    }
+    rendered
+}
+
+/// Print the metrics of the validators with controllers `controllers`.
+/// Controllers that are not bonded are skipped.
+pub(crate) async fn export(
+    client: &Client<KusamaRuntime>,
+    controllers: &[(u32, AccountId)],
+    network: Ss58AddressFormat,
+    decimals: u8,
+) -> Result<(), Error> {
+    // The current era can be one that has been planned but not started, with
+    // no points yet
+    let era = active_era(client).await?;
+    let points = client
+        .fetch_or_default(
+            &ErasRewardPointsStore {
+                index: era,
+                _phantom: PhantomData,
+            },
+            None,
+        )
+        .await?;
+    let mut samples = vec![];
+    for (index, controller) in controllers {
+        let store = LedgerStore {
+            controller: controller.clone(),
+        };
+        let ledger = match client.fetch(&store, None).await? {
+            Some(ledger) => ledger,
+            None => continue,
+        };
+        let store = ValidatorsStore {
+            stash: ledger.stash.clone(),
+        };
+        let prefs = client.fetch(&store, None).await?;
+        let store = ErasStakersStore {
+            era,
+            validator: ledger.stash.clone(),
+        };
+        let active = client
+            .fetch(&store, None)
+            .await?
+            .map_or(false, |exposure| exposure.total.0 > 0);
+        samples.push(Sample {
+            index: *index,
+            stash: ledgeracio::device_address(&ledger.stash, network),
+            stake: pad(decimals, ledger.total),
+            prefs,
+            era_points: points.individual.get(&ledger.stash).copied().unwrap_or(0),
+            active,
+        })
+    }
+    print!("{}", render(&samples));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_prefs() {
+        let mut old = &Compact(50_000_000_u32).encode()[..];
+        assert_eq!(Prefs::decode(&mut old).unwrap(), Prefs {
+            commission: 50_000_000,
+            blocked: None,
+        });
+        let mut new = Compact(0_u32).encode();
+        new.push(1);
+        assert_eq!(Prefs::decode(&mut &new[..]).unwrap(), Prefs {
+            commission: 0,
+            blocked: Some(true),
+        });
+    }
+
+    #[test]
+    fn renders_metrics() {
+        let samples = vec![
+            Sample {
+                index: 1,
+                stash: "A".to_owned(),
+                stake: "10.5".to_owned(),
+                prefs: Some(Prefs {
+                    commission: 25_000_000,
+                    blocked: Some(false),
+                }),
+                era_points: 80,
+                active: true,
+            },
+            Sample {
+                index: 2,
+                stash: "B".to_owned(),
+                stake: "1".to_owned(),
+                prefs: None,
+                era_points: 0,
+                active: false,
+            },
+        ];
+        let rendered = render(&samples);
+        assert!(rendered.starts_with(
+            "# HELP ledgeracio_validator_stake Bonded stake of the validator, in tokens\n# TYPE \
+             ledgeracio_validator_stake \
+             gauge\nledgeracio_validator_stake{index=\"1\",stash=\"A\"} \
+             10.5\nledgeracio_validator_stake{index=\"2\",stash=\"B\"} 1\n"
+        ));
+        assert!(rendered.contains("ledgeracio_validator_active{index=\"2\",stash=\"B\"} 0\n"));
+        assert!(rendered.contains("ledgeracio_validator_blocked{index=\"1\",stash=\"A\"} 0\n"));
+        assert!(!rendered.contains("ledgeracio_validator_blocked{index=\"2\""));
+        assert!(!rendered.contains("ledgeracio_validator_commission{index=\"2\""));
+    }
+}
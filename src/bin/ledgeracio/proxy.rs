@@ -0,0 +1,187 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of ledgeracio.
+//
+// ledgeracio is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ledgeracio is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ledgeracio.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Proxy commands: adding and removing proxies of accounts on the device.
+//! Requires a network connection.
+//!
+//! `substrate-subxt` does not ship bindings for the proxy pallet, so the calls
+//! we need are declared here.
+
+use super::{common::print_call, connection::Connector, AccountType, Error, LedgeracioPath,
+            StructOpt};
+use codec::Encode;
+use core::future::Future;
+use ledgeracio::{parse_address, validate_network};
+use substrate_subxt::{module,
+                      sp_core::{crypto::{AccountId32 as AccountId, Ss58AddressFormat},
+                                H256},
+                      system::System,
+                      Call, KusamaRuntime};
+
+/// The proxy pallet
+#[module]
+pub trait Proxy: System {}
+
+impl Proxy for KusamaRuntime {}
+
+/// The kinds of proxy.  Only the kinds that are encoded identically on
+/// Polkadot and Kusama are supported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode)]
+pub enum ProxyType {
+    /// Any call
+    Any,
+    /// Any call that does not transfer funds
+    NonTransfer,
+    /// Governance calls
+    Governance,
+    /// Staking calls
+    Staking,
+}
+
+impl std::str::FromStr for ProxyType {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &*s.to_ascii_lowercase() {
+            "any" => Ok(Self::Any),
+            "nontransfer" | "non-transfer" => Ok(Self::NonTransfer),
+            "governance" => Ok(Self::Governance),
+            "staking" => Ok(Self::Staking),
+            _ => Err("Proxy type must be `Any`, `NonTransfer`, `Governance`, or `Staking`"),
+        }
+    }
+}
+
+/// Register an account as a proxy of the sender
+#[derive(Clone, Debug, PartialEq, Call, Encode)]
+pub struct AddProxyCall<T: Proxy> {
+    pub delegate: T::AccountId,
+    pub proxy_type: ProxyType,
+    pub delay: T::BlockNumber,
+}
+
+/// Unregister a proxy of the sender
+#[derive(Clone, Debug, PartialEq, Call, Encode)]
+pub struct RemoveProxyCall<T: Proxy> {
+    pub delegate: T::AccountId,
+    pub proxy_type: ProxyType,
+    pub delay: T::BlockNumber,
+}
+
+#[derive(StructOpt, Debug)]
+pub(crate) enum ProxyCommand {
+    /// Allow another account to act as a proxy for the account at the given
+    /// index
+    Add {
+        /// The type of account: `nominator` or `validator`
+        account_type: AccountType,
+        index: u32,
+        /// The address of the proxy
+        #[structopt(parse(try_from_str = parse_address))]
+        delegate: (AccountId, u8),
+        /// The kind of proxy: `Any`, `NonTransfer`, `Governance`, or `Staking`
+        #[structopt(long, default_value = "Staking")]
+        proxy_type: ProxyType,
+        /// The number of blocks a proxy must announce a call in advance
+        #[structopt(long, default_value = "0")]
+        delay: u32,
+    },
+    /// Remove a proxy of the account at the given index
+    Remove {
+        /// The type of account: `nominator` or `validator`
+        account_type: AccountType,
+        index: u32,
+        /// The address of the proxy
+        #[structopt(parse(try_from_str = parse_address))]
+        delegate: (AccountId, u8),
+        /// The kind of proxy.  This must match the kind it was added with.
+        #[structopt(long, default_value = "Staking")]
+        proxy_type: ProxyType,
+        /// The delay the proxy was added with
+        #[structopt(long, default_value = "0")]
+        delay: u32,
+    },
+}
+
+impl ProxyCommand {
+    /// The addresses given as arguments, with the networks they were encoded
+    /// for
+    pub(crate) fn addresses(&self) -> Vec<&(AccountId, u8)> {
+        match self {
+            Self::Add { delegate, .. } | Self::Remove { delegate, .. } => vec![delegate],
+        }
+    }
+
+    /// Whether the command signs and submits a transaction
+    pub(crate) fn signs(&self) -> bool {
+        match self {
+            Self::Add { .. } | Self::Remove { .. } => true,
+        }
+    }
+}
+
+pub(crate) async fn main<T: FnOnce() -> U, U: Future<Output = Result<super::HardStore, Error>>>(
+    cmd: ProxyCommand,
+    connector: &Connector,
+    network: Ss58AddressFormat,
+    keystore: T,
+    emit_call: bool,
+) -> Result<Option<H256>, Error> {
+    match cmd {
+        ProxyCommand::Add {
+            account_type,
+            index,
+            delegate: (delegate, provided_network),
+            proxy_type,
+            delay,
+        } => {
+            validate_network("", provided_network, network)?;
+            let path = LedgeracioPath::new(network, account_type, index)?;
+            let call = AddProxyCall::<KusamaRuntime> {
+                delegate,
+                proxy_type,
+                delay,
+            };
+            let client = connector.connect().await?;
+            if emit_call {
+                return print_call(&client, call)
+            }
+            let signer = keystore().await?.signer(path).await?;
+            Ok(Some(client.submit(call, &signer).await?))
+        }
+        ProxyCommand::Remove {
+            account_type,
+            index,
+            delegate: (delegate, provided_network),
+            proxy_type,
+            delay,
+        } => {
+            validate_network("", provided_network, network)?;
+            let path = LedgeracioPath::new(network, account_type, index)?;
+            let call = RemoveProxyCall::<KusamaRuntime> {
+                delegate,
+                proxy_type,
+                delay,
+            };
+            let client = connector.connect().await?;
+            if emit_call {
+                return print_call(&client, call)
+            }
+            let signer = keystore().await?.signer(path).await?;
+            Ok(Some(client.submit(call, &signer).await?))
+        }
+    }
+}
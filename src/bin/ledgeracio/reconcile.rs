@@ -0,0 +1,244 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of ledgeracio.
+//
+// ledgeracio is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ledgeracio is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ledgeracio.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Reconciling the accounts on the device with their staking state on chain
+//!
+//! Every account is expected to be bonded, and to be nominating or validating
+//! according to its type.  The targets of nominators can also be checked
+//! against an allowlist, and the reward destination of every account against
+//! the one given.  Each difference is reported as drift.
+
+use super::{common::{fetch_validators, AddressSource, OutputFormat},
+            labels::account_type_name,
+            payee::{Payee, PayeeStore},
+            AccountId, AccountType, Error, HardStore};
+use std::collections::BTreeSet;
+use substrate_subxt::{sp_core::crypto::{Ss58AddressFormat, Ss58Codec},
+                      staking::{LedgerStore, NominatorsStore, ValidatorsStore},
+                      Client, KusamaRuntime};
+
+/// What the staking state of every account should be
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Expectations {
+    /// The reward destination of every account
+    pub(crate) payee: Option<Payee<AccountId>>,
+    /// The only validators nominators may nominate
+    pub(crate) allowlist: Option<BTreeSet<AccountId>>,
+}
+
+/// The staking state of one account
+#[derive(Clone, Debug, Default)]
+struct State {
+    /// The stash controlled by the account, if it is bonded
+    stash: Option<AccountId>,
+    /// The reward destination of the stash
+    payee: Option<Payee<AccountId>>,
+    /// The targets of the stash, if it is nominating
+    targets: Option<Vec<AccountId>>,
+    /// Whether the stash is validating
+    validating: bool,
+}
+
+/// Fetch the staking state of the account controlled by `controller`
+async fn fetch_state(
+    client: &Client<KusamaRuntime>,
+    controller: AccountId,
+) -> Result<State, Error> {
+    let stash = match client.fetch(&LedgerStore { controller }, None).await? {
+        Some(ledger) => ledger.stash,
+        None => return Ok(State::default()),
+    };
+    let payee = client
+        .fetch(
+            &PayeeStore {
+                stash: stash.clone(),
+            },
+            None,
+        )
+        .await?;
+    let targets = client
+        .fetch(
+            &NominatorsStore {
+                stash: stash.clone(),
+            },
+            None,
+        )
+        .await?
+        .map(|nominations| nominations.targets);
+    let validating = client
+        .fetch(
+            &ValidatorsStore {
+                stash: stash.clone(),
+            },
+            None,
+        )
+        .await?
+        .is_some();
+    Ok(State {
+        stash: Some(stash),
+        payee,
+        targets,
+        validating,
+    })
+}
+
+/// A reward destination, as given to `set-payee`
+fn describe_payee(payee: &Payee<AccountId>, network: Ss58AddressFormat) -> String {
+    match payee {
+        Payee::Staked => "Staked".to_owned(),
+        Payee::Stash => "Stash".to_owned(),
+        Payee::Controller => "Controller".to_owned(),
+        Payee::None => "None".to_owned(),
+        Payee::Account(account) => account.to_ss58check_with_version(network),
+    }
+}
+
+/// Each way in which `state`, of an account of type `account_type`, differs
+/// from `expected`
+fn drift(
+    account_type: AccountType,
+    state: &State,
+    expected: &Expectations,
+    network: Ss58AddressFormat,
+) -> Vec<String> {
+    if state.stash.is_none() {
+        return vec!["not bonded".to_owned()]
+    }
+    let mut drift = vec![];
+    match (account_type, &state.targets) {
+        (AccountType::Nominator, None) => drift.push("not nominating".to_owned()),
+        (AccountType::Nominator, Some(targets)) => {
+            let off_allowlist: Vec<String> = expected
+                .allowlist
+                .iter()
+                .flat_map(|allowlist| targets.iter().filter(move |t| !allowlist.contains(t)))
+                .map(|target| target.to_ss58check_with_version(network))
+                .collect();
+            if !off_allowlist.is_empty() {
+                drift.push(format!(
+                    "nominates targets not on the allowlist: {}",
+                    off_allowlist.join(", ")
+                ))
+            }
+        }
+        (AccountType::Validator, _) if !state.validating => drift.push("not validating".to_owned()),
+        (AccountType::Validator, _) => {}
+    }
+    if let Some(payee) = &expected.payee {
+        if state.payee.as_ref() != Some(payee) {
+            drift.push(format!(
+                "payee is {}, not {}",
+                state
+                    .payee
+                    .as_ref()
+                    .map_or_else(|| "unset".to_owned(), |p| describe_payee(p, network)),
+                describe_payee(payee, network)
+            ))
+        }
+    }
+    drift
+}
+
+/// Report the drift of every nominator and validator account on the device at
+/// `indices`, or up to the first with no free balance if `indices` is empty.
+/// Fails if any account has drifted.
+pub(crate) async fn report(
+    client: &Client<KusamaRuntime>,
+    keystore: &HardStore,
+    indices: &[u32],
+    expected: &Expectations,
+    network: Ss58AddressFormat,
+    output: OutputFormat,
+) -> Result<(), Error> {
+    let (mut checked, mut drifted) = (0_usize, 0_usize);
+    for &account_type in &[AccountType::Nominator, AccountType::Validator] {
+        let source = AddressSource::Device(indices, keystore);
+        for (index, controller) in fetch_validators(client, source, network, account_type).await? {
+            let address = ledgeracio::device_address(&controller, network);
+            let state = fetch_state(client, controller).await?;
+            let drift = drift(account_type, &state, expected, network);
+            let account_type_name = account_type_name(account_type);
+            checked += 1;
+            if !drift.is_empty() {
+                drifted += 1
+            }
+            if output == OutputFormat::Jsonl {
+                println!(
+                    "{}",
+                    serde_json::json!({
+                        "account_type": account_type_name,
+                        "index": index,
+                        "address": address,
+                        "drift": drift,
+                    })
+                );
+                continue
+            }
+            if drift.is_empty() {
+                println!("{} {} ({}): OK", account_type_name, index, address)
+            } else {
+                println!("{} {} ({}): DRIFT", account_type_name, index, address);
+                for difference in &drift {
+                    println!("    {}", difference)
+                }
+            }
+        }
+    }
+    if drifted > 0 {
+        return Err(format!("{} of the {} accounts have drifted", drifted, checked).into())
+    }
+    if output != OutputFormat::Jsonl {
+        println!("All {} accounts match", checked)
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(id: u8) -> AccountId { [id; 32].into() }
+
+    #[test]
+    fn reports_drift() {
+        let network = Ss58AddressFormat::PolkadotAccount;
+        let expected = Expectations {
+            payee: Some(Payee::Staked),
+            allowlist: Some([account(1), account(2)].iter().cloned().collect()),
+        };
+        let unbonded = State::default();
+        assert_eq!(
+            drift(AccountType::Nominator, &unbonded, &expected, network),
+            ["not bonded"]
+        );
+        let mut state = State {
+            stash: Some(account(9)),
+            payee: Some(Payee::Staked),
+            targets: Some(vec![account(1), account(2)]),
+            validating: false,
+        };
+        assert!(drift(AccountType::Nominator, &state, &expected, network).is_empty());
+        assert_eq!(drift(AccountType::Validator, &state, &expected, network), [
+            "not validating"
+        ]);
+        state.targets = Some(vec![account(1), account(3)]);
+        state.payee = Some(Payee::Stash);
+        let drift = drift(AccountType::Nominator, &state, &expected, network);
+        assert_eq!(drift.len(), 2);
+        assert!(drift[0].starts_with("nominates targets not on the allowlist: "));
+        assert_eq!(drift[1], "payee is Stash, not Staked");
+    }
+}
@@ -0,0 +1,265 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of ledgeracio.
+//
+// ledgeracio is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ledgeracio is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ledgeracio.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Reward history of a nominator
+//!
+//! Rewards are computed from storage rather than from payout events, which
+//! would require scanning every block.  For each era, a validator’s share of
+//! the era’s reward is proportional to its reward points.  After commission,
+//! the rest is split among its exposure in proportion to stake.
+//!
+//! `substrate-subxt` does not ship bindings for the active era or for the
+//! per-era exposure, reward, and preference storage, so they are declared here.
+
+use super::{common::pad, payouts::CONCURRENT_ERAS, AccountId, Error};
+use codec::{Compact, Decode, Encode};
+use futures::stream::{self, StreamExt as _};
+use std::{convert::TryFrom, marker::PhantomData};
+use substrate_subxt::{balances::Balances,
+                      module,
+                      staking::{CurrentEraStore, ErasRewardPointsStore, ValidatorPrefs},
+                      Client, KusamaRuntime, Store};
+
+/// The width of the longest bar in the chart, in characters
+const CHART_WIDTH: usize = 40;
+
+/// The staking pallet
+#[module]
+pub trait Staking: Balances {}
+
+impl Staking for KusamaRuntime {}
+
+/// The stake of one nominator behind a validator
+#[derive(Clone, Debug, Decode, PartialEq, Eq)]
+pub struct IndividualExposure<AccountId, Balance> {
+    pub who: AccountId,
+    pub value: Compact<Balance>,
+}
+
+/// The stake behind a validator in an era
+#[derive(Clone, Debug, Decode, PartialEq, Eq)]
+pub struct Exposure<AccountId, Balance> {
+    pub total: Compact<Balance>,
+    pub own: Compact<Balance>,
+    pub others: Vec<IndividualExposure<AccountId, Balance>>,
+}
+
+/// The exposure of a validator in an era, limited to the nominators that are
+/// paid
+#[derive(Clone, Debug, Eq, PartialEq, Store, Encode)]
+pub struct ErasStakersClippedStore<T: Staking> {
+    #[store(returns = Exposure<T::AccountId, T::Balance>)]
+    pub era: u32,
+    pub validator: T::AccountId,
+}
+
+/// The preferences of a validator in an era
+#[derive(Clone, Debug, Eq, PartialEq, Store, Encode)]
+pub struct ErasValidatorPrefsStore<T: Staking> {
+    #[store(returns = ValidatorPrefs)]
+    pub era: u32,
+    pub validator: T::AccountId,
+}
+
+/// The total reward paid to validators and nominators in an era
+#[derive(Clone, Debug, Eq, PartialEq, Store, Encode)]
+pub struct ErasValidatorRewardStore<T: Staking> {
+    #[store(returns = T::Balance)]
+    pub era: u32,
+    pub _runtime: PhantomData<T>,
+}
+
+/// The era in progress
+#[derive(Clone, Debug, Decode, PartialEq, Eq)]
+pub struct ActiveEraInfo {
+    pub index: u32,
+    /// When the era started, in milliseconds since the Unix epoch
+    pub start: Option<u64>,
+}
+
+/// The era in progress.  During the last session of an era, the current era
+/// is already the next one, which is planned but has not started.
+#[derive(Clone, Debug, Eq, PartialEq, Store, Encode)]
+pub struct ActiveEraStore<T: Staking> {
+    #[store(returns = ActiveEraInfo)]
+    pub _runtime: PhantomData<T>,
+}
+
+/// The reward that `stash` earned in `era` by nominating `targets`
+async fn era_reward(
+    client: &Client<KusamaRuntime>,
+    stash: &AccountId,
+    targets: &[AccountId],
+    era: u32,
+) -> Result<u128, Error> {
+    let era_reward = match client
+        .fetch(
+            &ErasValidatorRewardStore {
+                era,
+                _runtime: PhantomData,
+            },
+            None,
+        )
+        .await?
+    {
+        Some(reward) => reward,
+        None => return Ok(0),
+    };
+    let points = client
+        .fetch_or_default(
+            &ErasRewardPointsStore {
+                index: era,
+                _phantom: PhantomData,
+            },
+            None,
+        )
+        .await?;
+    if points.total == 0 {
+        return Ok(0)
+    }
+    let mut reward = 0_u128;
+    for validator in targets {
+        let validator_points = match points.individual.get(validator) {
+            Some(&validator_points) => validator_points,
+            None => continue,
+        };
+        let exposure = match client
+            .fetch(
+                &ErasStakersClippedStore {
+                    era,
+                    validator: validator.clone(),
+                },
+                None,
+            )
+            .await?
+        {
+            Some(exposure) => exposure,
+            None => continue,
+        };
+        let stake = match exposure.others.iter().find(|i| &i.who == stash) {
+            Some(individual) => individual.value.0,
+            None => continue,
+        };
+        let payout = era_reward.saturating_mul(validator_points.into()) / u128::from(points.total);
+        let commission = client
+            .fetch(
+                &ErasValidatorPrefsStore {
+                    era,
+                    validator: validator.clone(),
+                },
+                None,
+            )
+            .await?
+            .map_or(0, |prefs| prefs.commission * payout);
+        let leftover = payout - commission;
+        reward = reward.saturating_add(
+            leftover
+                .saturating_mul(stake)
+                .checked_div(exposure.total.0)
+                .unwrap_or(0),
+        );
+    }
+    Ok(reward)
+}
+
+/// Fetch the rewards that `stash` earned by nominating `targets` in each of
+/// the last `eras` completed eras, oldest first
+pub(crate) async fn fetch_rewards(
+    client: &Client<KusamaRuntime>,
+    stash: &AccountId,
+    targets: &[AccountId],
+    eras: u32,
+) -> Result<Vec<(u32, u128)>, Error> {
+    let current_era = client
+        .fetch_or_default(
+            &CurrentEraStore {
+                _runtime: PhantomData,
+            },
+            None,
+        )
+        .await?;
+    let mut rewards =
+        stream::iter(current_era.saturating_sub(eras)..current_era)
+            .map(|era| async move {
+                Ok::<_, Error>((era, era_reward(client, stash, targets, era).await?))
+            })
+            .buffer_unordered(CONCURRENT_ERAS)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+    rewards.sort_unstable();
+    Ok(rewards)
+}
+
+/// Render `rewards` as a bar chart, one line per era, followed by the total
+/// and the average per era
+pub(crate) fn chart(rewards: &[(u32, u128)], decimals: u8, symbol: &str) -> String {
+    let max = rewards.iter().map(|&(_, reward)| reward).max().unwrap_or(0);
+    let total = rewards
+        .iter()
+        .fold(0_u128, |total, &(_, reward)| total.saturating_add(reward));
+    let mut output = String::new();
+    for &(era, reward) in rewards {
+        let width = reward
+            .saturating_mul(CHART_WIDTH as u128)
+            .checked_div(max)
+            .map_or(0, |width| usize::try_from(width).unwrap_or(CHART_WIDTH));
+        output += &format!(
+            "Era {:>6} |{:<width$}| {} {}\n",
+            era,
+            "#".repeat(width),
+            pad(decimals, reward),
+            symbol,
+            width = CHART_WIDTH,
+        );
+    }
+    let average = total.checked_div(rewards.len() as u128).unwrap_or(0);
+    output += &format!(
+        "Total: {} {sym}\nAverage per era: {} {sym}\n",
+        pad(decimals, total),
+        pad(decimals, average),
+        sym = symbol,
+    );
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chart_scales_to_largest_reward() {
+        let rendered = chart(&[(10, 1000), (11, 500), (12, 0)], 3, "KSM");
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines[0], format!("Era     10 |{}| 1 KSM", "#".repeat(40)));
+        assert_eq!(
+            lines[1],
+            format!("Era     11 |{}{}| 0.5 KSM", "#".repeat(20), " ".repeat(20))
+        );
+        assert_eq!(lines[2], format!("Era     12 |{}| 0 KSM", " ".repeat(40)));
+        assert_eq!(lines[3], "Total: 1.5 KSM");
+        assert_eq!(lines[4], "Average per era: 0.5 KSM");
+    }
+
+    #[test]
+    fn empty_chart() {
+        assert_eq!(
+            chart(&[], 0, "DOT"),
+            "Total: 0 DOT\nAverage per era: 0 DOT\n"
+        );
+    }
+}
@@ -0,0 +1,263 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of ledgeracio.
+//
+// ledgeracio is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ledgeracio is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ledgeracio.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Estimating how a nominator’s stake would be split among its targets
+//!
+//! The election balances the stake behind the elected validators, so the
+//! estimate assumes that the current era’s exposures stay the same and pours
+//! the nominator’s stake into the least-backed targets first, until their
+//! totals are level.  Targets that are not in the active set, or that are
+//! oversubscribed by larger nominators, are not given any stake.  This is only
+//! an estimate: the real election also moves other nominators’ stake.
+
+use super::{common::pad,
+            rewards::{Exposure, Staking},
+            AccountId, Error};
+use codec::Encode;
+use std::{convert::TryFrom, marker::PhantomData};
+use substrate_subxt::{sp_core::crypto::{Ss58AddressFormat, Ss58Codec},
+                      staking::CurrentEraStore,
+                      Client, KusamaRuntime, Store};
+
+/// The full exposure of a validator in an era, including nominators that are
+/// not paid
+#[derive(Clone, Debug, Eq, PartialEq, Store, Encode)]
+pub struct ErasStakersStore<T: Staking> {
+    #[store(returns = Exposure<T::AccountId, T::Balance>)]
+    pub era: u32,
+    pub validator: T::AccountId,
+}
+
+/// The backing of a target in the current era, excluding the nominator’s own
+/// stake
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Backing {
+    /// The total stake behind the target
+    total: u128,
+    /// How many nominators back the target
+    nominators: usize,
+    /// The smallest stake among those nominators
+    smallest: u128,
+}
+
+/// The estimated outcome for one target
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Share {
+    /// The target is not in the active set
+    Inactive,
+    /// The target is oversubscribed, and the nominator’s stake is not larger
+    /// than the smallest nominator already backing it
+    Oversubscribed { nominators: usize, smallest: u128 },
+    /// The target would receive this much of the nominator’s stake
+    Stake(u128),
+}
+
+/// Split `stake` among `backings`, returning one [`Share`] per backing.
+/// `max_rewarded` is the number of nominators paid per validator.
+fn estimate(stake: u128, backings: &[Option<Backing>], max_rewarded: usize) -> Vec<Share> {
+    let mut shares: Vec<Share> = backings
+        .iter()
+        .map(|backing| match backing {
+            None => Share::Inactive,
+            Some(backing) if backing.nominators >= max_rewarded && stake <= backing.smallest => {
+                Share::Oversubscribed {
+                    nominators: backing.nominators,
+                    smallest: backing.smallest,
+                }
+            }
+            Some(_) => Share::Stake(0),
+        })
+        .collect();
+    let mut eligible: Vec<(usize, u128)> = backings
+        .iter()
+        .enumerate()
+        .filter(|&(index, _)| shares[index] == Share::Stake(0))
+        .filter_map(|(index, backing)| Some((index, backing.as_ref()?.total)))
+        .collect();
+    eligible.sort_by_key(|&(_, total)| total);
+    // Find how many of the least-backed targets get stake, and the level their
+    // totals are raised to.
+    let mut sum = 0_u128;
+    let mut level = 0_u128;
+    let mut filled = 0;
+    for (count, &(_, total)) in eligible.iter().enumerate() {
+        let new_sum = sum.saturating_add(total);
+        let new_level = stake.saturating_add(new_sum) / (count as u128 + 1);
+        if new_level <= total {
+            break
+        }
+        sum = new_sum;
+        level = new_level;
+        filled = count + 1;
+    }
+    for &(index, total) in &eligible[..filled] {
+        shares[index] = Share::Stake(level - total)
+    }
+    shares
+}
+
+/// Fetch the backing of `target` in `era`, excluding the stake of `stash`
+async fn fetch_backing(
+    client: &Client<KusamaRuntime>,
+    stash: &AccountId,
+    target: &AccountId,
+    era: u32,
+) -> Result<Option<Backing>, Error> {
+    let exposure = client
+        .fetch(
+            &ErasStakersStore {
+                era,
+                validator: target.clone(),
+            },
+            None,
+        )
+        .await?;
+    let exposure = match exposure {
+        Some(exposure) if exposure.total.0 > 0 => exposure,
+        _ => return Ok(None),
+    };
+    let others: Vec<u128> = exposure
+        .others
+        .iter()
+        .filter(|individual| &individual.who != stash)
+        .map(|individual| individual.value.0)
+        .collect();
+    let validator_stake = exposure
+        .total
+        .0
+        .saturating_sub(exposure.others.iter().map(|i| i.value.0).sum());
+    Ok(Some(Backing {
+        total: validator_stake.saturating_add(others.iter().sum()),
+        nominators: others.len(),
+        smallest: others.iter().copied().min().unwrap_or(0),
+    }))
+}
+
+/// Print an estimate of how `stake`, bonded by `stash`, would be split among
+/// `targets`, and flag the targets that would likely get none of it
+pub(crate) async fn report(
+    client: &Client<KusamaRuntime>,
+    stash: &AccountId,
+    stake: u128,
+    targets: &[AccountId],
+    network: Ss58AddressFormat,
+    decimals: u8,
+    symbol: &str,
+) -> Result<(), Error> {
+    let max_rewarded: u32 = client
+        .metadata()
+        .module("Staking")?
+        .constant("MaxNominatorRewardedPerValidator")?
+        .value()?;
+    let era = client
+        .fetch_or_default(
+            &CurrentEraStore {
+                _runtime: PhantomData,
+            },
+            None,
+        )
+        .await?;
+    let mut backings = vec![];
+    for target in targets {
+        backings.push(fetch_backing(client, stash, target, era).await?)
+    }
+    let shares = estimate(
+        stake,
+        &backings,
+        usize::try_from(max_rewarded).unwrap_or(usize::max_value()),
+    );
+    println!(
+        "Estimated distribution of {} {} bonded, based on era {}:",
+        pad(decimals, stake),
+        symbol,
+        era
+    );
+    let mut backed = 0_usize;
+    for (target, share) in targets.iter().zip(&shares) {
+        let address = target.to_ss58check_with_version(network);
+        match share {
+            Share::Stake(0) => println!("    {}: none (already well backed)", address),
+            Share::Stake(amount) => {
+                backed += 1;
+                println!("    {}: {} {}", address, pad(decimals, *amount), symbol)
+            }
+            Share::Inactive => println!("    {}: none (not in the active set)", address),
+            Share::Oversubscribed {
+                nominators,
+                smallest,
+            } => println!(
+                "    {}: none (oversubscribed: {} nominators, smallest stake {} {})",
+                address,
+                nominators,
+                pad(decimals, *smallest),
+                symbol
+            ),
+        }
+    }
+    if backed < targets.len() {
+        println!(
+            "Only {} of the {} targets would likely receive your stake",
+            backed,
+            targets.len()
+        )
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backing(total: u128, nominators: usize, smallest: u128) -> Option<Backing> {
+        Some(Backing {
+            total,
+            nominators,
+            smallest,
+        })
+    }
+
+    #[test]
+    fn levels_least_backed_targets() {
+        let shares = estimate(
+            100,
+            &[backing(50, 1, 10), backing(10, 1, 10), backing(500, 1, 10)],
+            256,
+        );
+        assert_eq!(shares, vec![
+            Share::Stake(30),
+            Share::Stake(70),
+            Share::Stake(0)
+        ]);
+    }
+
+    #[test]
+    fn flags_inactive_and_oversubscribed_targets() {
+        let shares = estimate(100, &[None, backing(1000, 2, 100), backing(1000, 2, 99)], 2);
+        assert_eq!(shares, vec![
+            Share::Inactive,
+            Share::Oversubscribed {
+                nominators: 2,
+                smallest: 100
+            },
+            Share::Stake(100),
+        ]);
+    }
+
+    #[test]
+    fn zero_stake() {
+        assert_eq!(estimate(0, &[backing(5, 0, 0)], 256), vec![Share::Stake(0)]);
+    }
+}
@@ -18,17 +18,25 @@
 //! e.g. rotating session keys, set payment target, announcing
 //! intention to validate etc. Requires a network connection.
 
-use super::{common::parse_ppb, parse_reward_destination, AccountType, AddressSource, Error,
-            LedgeracioPath, StructOpt};
+use super::{common::{active_era, format_balance, format_table, history_depth, pad,
+                     parse_account_ref, parse_duration, parse_ppb, payee_is, print_call,
+                     select_index, signer_index, AccountRef, OutputFormat, TokenOverrides},
+            connection::Connector,
+            labels::Labels,
+            parse_reward_destination,
+            payee::{check_supported, Payee, SetPayeeCall},
+            payouts::{display_payouts, eras_in, PayoutStakersCall},
+            AccountType, AddressSource, Error, LedgeracioPath, StructOpt};
 use codec::Decode;
-use core::{future::Future, pin::Pin};
-use ledgeracio::parse_address;
-use substrate_subxt::{session::SetKeysCallExt,
+use core::future::Future;
+use ledgeracio::{get_network, input_error, parse_address};
+use std::marker::PhantomData;
+use substrate_subxt::{session::{SetKeysCall, ValidatorsStore as SessionValidatorsStore},
                       sp_core::{crypto::{AccountId32 as AccountId, Ss58AddressFormat},
                                 H256},
                       sp_runtime::Perbill,
-                      staking::{BondedStore, ChillCallExt, RewardDestination, SetPayeeCallExt,
-                                ValidateCallExt, ValidatorPrefs},
+                      staking::{BondedStore, ChillCall, LedgerStore, StakingLedger, ValidateCall,
+                                ValidatorPrefs, ValidatorsStore},
                       Client, KusamaRuntime, SessionKeys};
 
 #[derive(StructOpt, Debug)]
@@ -38,18 +46,81 @@ pub(crate) enum Validator {
     ShowAddress {
         #[structopt(parse(try_from_str = parse_address))]
         address: (AccountId, u8),
+        /// Only report unclaimed payouts from eras in this period, such as
+        /// `7d` or `12h`.
+        #[structopt(long, parse(try_from_str = parse_duration))]
+        since: Option<u64>,
     },
-    /// Show status of the given Validator Controller key, or all if none is
+    /// Show status of the given Validator Controller keys, or all if none are
     /// specified.
-    Show { index: Option<u32> },
+    Show {
+        /// The indices to show, separated by commas or given as separate
+        /// arguments
+        #[structopt(use_delimiter = true)]
+        indices: Vec<u32>,
+        /// Only print how many accounts are bonded, validating, chilled, or
+        /// unbonded.  Only used when no index is given.
+        #[structopt(long)]
+        count_only: bool,
+        /// Only report unclaimed payouts from eras in this period, such as
+        /// `7d` or `12h`.
+        #[structopt(long, parse(try_from_str = parse_duration))]
+        since: Option<u64>,
+        /// Only show validators that are not in the active set
+        #[structopt(long)]
+        waiting: bool,
+        /// Also compare each validator’s era points in this many of the most
+        /// recent completed eras with the mean of the active set, and flag
+        /// validators that are consistently below it
+        #[structopt(long, value_name = "ERAS")]
+        era_points: Option<u32>,
+        /// Print metrics in the Prometheus text format instead, for the
+        /// textfile collector of `node_exporter`
+        #[structopt(long, conflicts_with_all = &["count-only", "era-points"])]
+        export_prometheus: bool,
+    },
+    /// List every validator on the network, by decreasing total stake in the
+    /// current era.  This does not require a Ledger device.
+    List {
+        /// Only list validators with at least this total stake, in tokens,
+        /// such as `1000` or `1000.5`
+        #[structopt(long)]
+        min_stake: Option<String>,
+        /// Only list validators with at most this commission, in any format
+        /// accepted by `announce`
+        #[structopt(long, parse(try_from_str = parse_ppb))]
+        max_commission: Option<u32>,
+        /// Only list validators in the active set
+        #[structopt(long)]
+        active: bool,
+    },
     /// Announce intention to validate
     Announce {
         index: u32,
+        /// The commission: a decimal between 0 and 1, a percentage such as
+        /// `2.5%`, or basis points such as `250bps`.  Defaults to 100%.
         #[structopt(parse(try_from_str = parse_ppb))]
         commission: Option<u32>,
     },
     /// Chill (announce intention to cease validation)
-    Chill { index: u32 },
+    Chill {
+        /// The account: an index, or an address on this device.  If not given,
+        /// pick the account interactively.
+        #[structopt(parse(try_from_str = parse_account_ref))]
+        index: Option<AccountRef>,
+        /// The account is a stash.  The transaction is signed by its
+        /// controller, which must also be an account on this device.
+        #[structopt(long)]
+        stash: bool,
+    },
+    /// Pay out the rewards of the validator at the given index, and of all of
+    /// its nominators, for an era.  If no era is given, every era with
+    /// unclaimed rewards is paid out, with one transaction each.
+    Payout {
+        index: u32,
+        /// The era to pay out
+        era: Option<u32>,
+    },
     /// Replace a session key
     ReplaceKey {
         index: u32,
@@ -59,85 +130,517 @@ pub(crate) enum Validator {
     /// Set payment target
     #[structopt(name = "set-payee")]
     SetPayee {
-        index: u32,
+        /// The account: an index, or an address on this device
+        #[structopt(parse(try_from_str = parse_account_ref))]
+        index: AccountRef,
         #[structopt(parse(try_from_str = parse_reward_destination))]
-        target: RewardDestination<AccountId>,
+        target: Payee<AccountId>,
+        /// Submit the transaction even if the payee is already `target`
+        #[structopt(long)]
+        force: bool,
+        /// The account is a stash.  The transaction is signed by its
+        /// controller, which must also be an account on this device.
+        #[structopt(long)]
+        stash: bool,
     },
     /// Display the address of the given index
-    Address { index: u32 },
+    Address {
+        /// If not given, pick the account interactively
+        index: Option<u32>,
+        /// Encode the address for this network instead of `--network`.  The
+        /// account is still derived using `--network`.
+        #[structopt(long, parse(try_from_str = get_network))]
+        display_network: Option<Ss58AddressFormat>,
+        /// Also display the address as a QR code, for scanning with a mobile
+        /// wallet
+        #[structopt(long)]
+        qr: bool,
+        /// Also display the derivation path, in the standard notation used by
+        /// other wallets, such as `m/44'/354'/0'/0'/1'`
+        #[structopt(long)]
+        show_path: bool,
+        /// Also display the SS58 version byte and the account ID in hex, on
+        /// separate lines, for systems that store them separately
+        #[structopt(long)]
+        raw: bool,
+    },
+}
+
+impl Validator {
+    /// The addresses given as arguments, with the networks they were encoded
+    /// for
+    pub(crate) fn addresses(&self) -> Vec<&(AccountId, u8)> {
+        match self {
+            Self::ShowAddress { address, .. } => vec![address],
+            Self::Chill {
+                index: Some(index), ..
+            }
+            | Self::SetPayee { index, .. } => index.address().into_iter().collect(),
+            Self::Chill { index: None, .. }
+            | Self::Show { .. }
+            | Self::List { .. }
+            | Self::Announce { .. }
+            | Self::Payout { .. }
+            | Self::ReplaceKey { .. }
+            | Self::Address { .. } => vec![],
+        }
+    }
+
+    /// Whether the command signs and submits a transaction
+    pub(crate) fn signs(&self) -> bool {
+        match self {
+            Self::Announce { .. }
+            | Self::Chill { .. }
+            | Self::Payout { .. }
+            | Self::ReplaceKey { .. }
+            | Self::SetPayee { .. } => true,
+            Self::ShowAddress { .. }
+            | Self::Show { .. }
+            | Self::List { .. }
+            | Self::Address { .. } => false,
+        }
+    }
 }
 
 fn parse_keys(buffer: &str) -> Result<SessionKeys, Error> {
     let buffer: &[u8] = buffer.as_ref();
     if !buffer.starts_with(b"0x") {
-        return Err("Hex data must start with ‘0x’".to_owned().into())
+        return Err(input_error("Hex data must start with ‘0x’"))
     }
     let bytes = ::hex::decode(&buffer[2..])?;
     Decode::decode(&mut &*bytes).map_err(|e| Box::new(e) as _)
 }
 
-pub(crate) async fn main<T: FnOnce() -> Result<super::HardStore, Error>>(
+/// Filter `controllers` down to those whose stash is not in the current
+/// session’s validator set
+async fn waiting_validators(
+    client: &Client<KusamaRuntime>,
+    controllers: Vec<(u32, AccountId)>,
+) -> Result<Vec<(u32, AccountId)>, Error> {
+    let active = client
+        .fetch_or_default(
+            &SessionValidatorsStore {
+                _runtime: PhantomData,
+            },
+            None,
+        )
+        .await?;
+    let mut waiting = vec![];
+    for (index, controller) in controllers {
+        let store = LedgerStore {
+            controller: controller.clone(),
+        };
+        match client.fetch(&store, None).await? {
+            Some(StakingLedger { stash, .. }) if active.contains(&stash) => {}
+            _ => waiting.push((index, controller)),
+        }
+    }
+    Ok(waiting)
+}
+
+/// The row for the validator at `index`, with controller `controller`, in the
+/// table shown by `show` when no index is given
+async fn validator_row(
+    client: &Client<KusamaRuntime>,
+    index: u32,
+    controller: &AccountId,
+    label: Option<&str>,
+    network: Ss58AddressFormat,
+    token: (u8, &str),
+    eras: Option<u32>,
+) -> Result<Vec<String>, Error> {
+    let label = label.unwrap_or_default().to_owned();
+    let store = LedgerStore {
+        controller: controller.clone(),
+    };
+    let ledger = match client.fetch(&store, None).await? {
+        Some(ledger) => ledger,
+        None => {
+            let none = || "-".to_owned();
+            return Ok(vec![
+                index.to_string(),
+                none(),
+                none(),
+                none(),
+                none(),
+                none(),
+                "not bonded".to_owned(),
+                label,
+            ])
+        }
+    };
+    let unclaimed = crate::payouts::display_payouts(controller.clone(), client, eras).await?;
+    let store = ValidatorsStore {
+        stash: ledger.stash.clone(),
+    };
+    let (commission, status) = match client.fetch(&store, None).await? {
+        None => ("-".to_owned(), "chilled"),
+        Some(prefs) => (
+            format!("{}%", pad(7, prefs.commission.deconstruct().into())),
+            "validating",
+        ),
+    };
+    Ok(vec![
+        index.to_string(),
+        ledgeracio::device_address(&ledger.stash, network),
+        format_balance(token, ledger.total),
+        format_balance(token, ledger.active),
+        commission,
+        unclaimed.len().to_string(),
+        status.to_owned(),
+        label,
+    ])
+}
+
+pub(crate) async fn main<T: FnOnce() -> U, U: Future<Output = Result<super::HardStore, Error>>>(
     cmd: Validator,
-    client: Pin<Box<dyn Future<Output = Result<Client<KusamaRuntime>, Error>>>>,
+    connector: &Connector,
     network: Ss58AddressFormat,
     keystore: T,
+    output: OutputFormat,
+    token: &TokenOverrides,
+    emit_call: bool,
 ) -> Result<Option<H256>, Error> {
     match cmd {
         Validator::ShowAddress {
             address: (stash, provided_network),
+            since,
         } => {
             ledgeracio::validate_network("", provided_network, network)?;
-            let client = client.await?;
+            let client = connector.connect_staking().await?;
             let controller = match client.fetch(&BondedStore { stash }, None).await? {
                 Some(controller) => controller,
                 None => return Err("Controller not found for stash".to_owned().into()),
             };
-            crate::common::display_validators(&client, &[controller], network).await?;
+            let eras = since.map(|since| eras_in(&client, since)).transpose()?;
+            crate::common::display_validator(
+                &client, controller, None, false, network, output, token, eras,
+            )
+            .await?;
+            Ok(None)
+        }
+        Validator::List {
+            min_stake,
+            max_commission,
+            active,
+        } => {
+            let client = connector.connect_staking().await?;
+            crate::discover::main(
+                &client,
+                min_stake.as_deref(),
+                max_commission,
+                active,
+                network,
+                output,
+                token,
+            )
+            .await?;
             Ok(None)
         }
         Validator::Announce { index, commission } => {
             let path = LedgeracioPath::new(network, AccountType::Validator, index)?;
             let commission = commission.unwrap_or(1_000_000_000);
             if commission > 1_000_000_000 {
-                return Err(format!("Commission {} too large (limit is 10⁹)", commission).into())
+                return Err(input_error(format!(
+                    "Commission {} too large (limit is 10⁹)",
+                    commission
+                )))
+            }
+            let call = ValidateCall::<KusamaRuntime> {
+                _runtime: PhantomData,
+                prefs: ValidatorPrefs {
+                    commission: Perbill::from_parts(commission),
+                },
+            };
+            let client = connector.connect_staking().await?;
+            if emit_call {
+                return print_call(&client, call)
+            }
+            let signer = keystore().await?.signer(path).await?;
+            Ok(Some(client.submit(call, &signer).await?))
+        }
+        Validator::Chill { index, stash } => {
+            let call = ChillCall::<KusamaRuntime> {
+                _runtime: PhantomData,
+            };
+            if emit_call {
+                return print_call(&connector.connect_staking().await?, call)
             }
-            let prefs = ValidatorPrefs {
-                commission: Perbill::from_parts(commission),
+            let keystore = keystore().await?;
+            let client = connector.connect_staking().await?;
+            let index = match index {
+                Some(index) => index,
+                None => AccountRef::Index(
+                    select_index(None, AccountType::Validator, &keystore, network).await?,
+                ),
             };
-            let signer = keystore()?.signer(path).await?;
-            Ok(Some(client.await?.validate(&signer, prefs).await?))
+            let index = signer_index(
+                &client,
+                &keystore,
+                network,
+                AccountType::Validator,
+                &index,
+                stash,
+            )
+            .await?;
+            let path = LedgeracioPath::new(network, AccountType::Validator, index)?;
+            let signer = keystore.signer(path).await?;
+            Ok(Some(client.submit(call, &signer).await?))
         }
-        Validator::Chill { index } => {
+        Validator::Payout { index, era } => {
+            if emit_call {
+                return Err(input_error(
+                    "validator payout cannot be used with --emit-call, as the stash is found \
+                     using the device",
+                ))
+            }
+            let keystore = keystore().await?;
+            let mut client = connector.connect_staking().await?;
             let path = LedgeracioPath::new(network, AccountType::Validator, index)?;
-            let signer = keystore()?.signer(path).await?;
-            Ok(Some(client.await?.chill(&signer).await?))
+            let signer = keystore.signer(path).await?;
+            let store = LedgerStore {
+                controller: signer.account_id().clone(),
+            };
+            let StakingLedger {
+                stash,
+                claimed_rewards,
+                ..
+            } = client
+                .fetch(&store, None)
+                .await?
+                .ok_or_else(|| input_error(format!("Validator account {} is not bonded", index)))?;
+            // The era in progress already has reward points, but paying it
+            // out fails once the transaction is dispatched
+            let active = active_era(&client).await?;
+            let oldest = history_depth(&client)
+                .await?
+                .map_or(0, |depth| active.saturating_sub(depth));
+            let eras = match era {
+                Some(era) if era >= active => {
+                    return Err(input_error(format!(
+                        "Era {} has not ended yet: the active era is {}",
+                        era, active
+                    )))
+                }
+                Some(era) if era < oldest => {
+                    return Err(input_error(format!(
+                        "Era {} can no longer be paid out: the oldest era the chain keeps is {}",
+                        era, oldest
+                    )))
+                }
+                Some(era) if claimed_rewards.contains(&era) => {
+                    return Err(input_error(format!(
+                        "Era {} has already been paid out",
+                        era
+                    )))
+                }
+                Some(era) => vec![era],
+                None => display_payouts(signer.account_id().clone(), &client, None)
+                    .await?
+                    .into_iter()
+                    .filter(|&era| era < active)
+                    .collect(),
+            };
+            if eras.is_empty() {
+                println!("No eras have unclaimed rewards");
+                return Ok(None)
+            }
+            let (mut paid, mut failed) = (0_usize, 0_usize);
+            let store = &store;
+            for era in eras {
+                // Only reads are retried: a submission that failed may still
+                // have reached the node, so it is never sent again.  The
+                // claimed eras are fetched again before each payout, so that
+                // eras paid out in the meantime are skipped.
+                let claimed_rewards = connector
+                    .retry(&mut client, |client| async move {
+                        Ok(client
+                            .fetch(store, None)
+                            .await?
+                            .map(|ledger| ledger.claimed_rewards)
+                            .unwrap_or_default())
+                    })
+                    .await;
+                let payout = match claimed_rewards {
+                    Ok(claimed_rewards) if claimed_rewards.contains(&era) => {
+                        println!("Era {} has already been paid out", era);
+                        continue
+                    }
+                    Ok(_) => {
+                        let call = PayoutStakersCall::<KusamaRuntime> {
+                            validator_stash: stash.clone(),
+                            era,
+                        };
+                        client.submit(call, &signer).await.map_err(Error::from)
+                    }
+                    Err(e) => Err(e),
+                };
+                match payout {
+                    Ok(hash) => {
+                        println!("Paid out era {}: transaction hash: {:?}", era, hash);
+                        paid += 1
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to pay out era {}: {}", era, e);
+                        failed += 1
+                    }
+                }
+            }
+            if failed > 0 {
+                return Err(
+                    format!("Paid out {} eras, but failed to pay out {}", paid, failed).into(),
+                )
+            }
+            Ok(None)
         }
         Validator::ReplaceKey { index, keys } => {
             let path = LedgeracioPath::new(network, AccountType::Validator, index)?;
-            let signer = keystore()?.signer(path).await?;
-            Ok(Some(client.await?.set_keys(&signer, keys, vec![]).await?))
+            let call = SetKeysCall::<KusamaRuntime> {
+                keys,
+                proof: vec![],
+            };
+            let client = connector.connect_staking().await?;
+            if emit_call {
+                return print_call(&client, call)
+            }
+            let signer = keystore().await?.signer(path).await?;
+            Ok(Some(client.submit(call, &signer).await?))
         }
-        Validator::Show { index } => {
-            let client = client.await?;
+        Validator::Show {
+            indices,
+            count_only,
+            waiting,
+            since,
+            era_points,
+            export_prometheus,
+        } => {
+            let client = connector.connect_staking().await?;
+            let eras = since.map(|since| eras_in(&client, since)).transpose()?;
             // These are *controller*, not *stash*, accounts.
-            let validators = crate::common::fetch_validators(
+            let mut validators = crate::common::fetch_validators(
                 &client,
-                AddressSource::Device(index, &keystore()?),
+                AddressSource::Device(&indices, &keystore().await?),
                 network,
                 AccountType::Validator,
             )
             .await?;
-            crate::common::display_validators(&client, &*validators, network).await?;
+            if waiting {
+                validators = waiting_validators(&client, validators).await?;
+            }
+            if count_only && indices.is_empty() {
+                crate::common::display_counts(&client, &validators, AccountType::Validator).await?;
+                return Ok(None)
+            }
+            if export_prometheus {
+                let (token_decimals, _) = token.resolve(&client);
+                crate::prometheus::export(&client, &validators, network, token_decimals).await?;
+                return Ok(None)
+            }
+            let labels = Labels::load_or_warn();
+            if indices.is_empty() && output != OutputFormat::Jsonl {
+                let (token_decimals, token_symbol) = token.resolve(&client);
+                let mut rows = vec![[
+                    "INDEX",
+                    "STASH",
+                    "TOTAL",
+                    "ACTIVE",
+                    "COMMISSION",
+                    "UNCLAIMED",
+                    "STATUS",
+                    "LABEL",
+                ]
+                .iter()
+                .map(|&header| header.to_owned())
+                .collect()];
+                for (index, controller) in &validators {
+                    rows.push(
+                        validator_row(
+                            &client,
+                            *index,
+                            controller,
+                            labels.get(network, AccountType::Validator, *index),
+                            network,
+                            (token_decimals, &token_symbol),
+                            eras,
+                        )
+                        .await?,
+                    )
+                }
+                print!("{}", format_table(&rows, &[0, 2, 3, 4, 5]));
+            } else {
+                for (index, controller) in &validators {
+                    let label = labels.get(network, AccountType::Validator, *index);
+                    crate::common::display_validator(
+                        &client,
+                        controller.clone(),
+                        label,
+                        true,
+                        network,
+                        output,
+                        token,
+                        eras,
+                    )
+                    .await?
+                }
+            }
+            if let Some(era_points) = era_points {
+                crate::points::report(&client, &validators, era_points, network, output).await?
+            }
             Ok(None)
         }
-        Validator::SetPayee { index, target } => {
+        Validator::SetPayee {
+            index,
+            target,
+            force,
+            stash,
+        } => {
+            let call = SetPayeeCall::<KusamaRuntime> {
+                payee: target.clone(),
+            };
+            let client = connector.connect_staking().await?;
+            check_supported(&client, &target).await?;
+            if emit_call {
+                return print_call(&client, call)
+            }
+            let keystore = keystore().await?;
+            let index = signer_index(
+                &client,
+                &keystore,
+                network,
+                AccountType::Validator,
+                &index,
+                stash,
+            )
+            .await?;
             let path = LedgeracioPath::new(network, AccountType::Validator, index)?;
-            let signer = keystore()?.signer(path).await?;
-            client.await?.set_payee(&signer, target).await?;
+            let signer = keystore.signer(path).await?;
+            if !force && payee_is(&client, signer.account_id(), &target).await? {
+                println!("Payee already set to {:?}, nothing to do", target);
+                return Ok(None)
+            }
+            client.submit(call, &signer).await?;
             Ok(None)
         }
-        Validator::Address { index } => {
-            crate::display_path(AccountType::Validator, &keystore()?, network, index).await?;
+        Validator::Address {
+            index,
+            display_network,
+            qr,
+            show_path,
+            raw,
+        } => {
+            let keystore = keystore().await?;
+            let index = select_index(index, AccountType::Validator, &keystore, network).await?;
+            crate::display_path(
+                AccountType::Validator,
+                &keystore,
+                network,
+                index,
+                display_network,
+                qr,
+                show_path,
+                raw,
+            )
+            .await?;
             Ok(None)
         }
     }
@@ -51,9 +51,12 @@ pub enum Error {
     /// Unsupported network (not Polkadot or Kusama)
     #[error("Unsupported network {0:?}")]
     UnsupportedNetwork(Ss58AddressFormat),
-    /// Index too large (greater than `2**31`)
-    #[error("Index too large: hardened child keys are not supported (greater than 2**31): {0}")]
+    /// Index too large (`2**31` or greater)
+    #[error("Index too large: hardened child keys are not supported (2**31 or greater): {0}")]
     UnsupportedKeyIndex(u32),
+    /// Index zero, which Ledgeracio does not use
+    #[error("Index must not be zero: account indices start at 1")]
+    ZeroKeyIndex,
 }
 
 /// The MSB of indexes for hardened derivation paths
@@ -71,14 +74,14 @@ const KUSAMA: u32 = 0x8000_01b2;
 
 impl LedgeracioPath {
     /// Create a new Ledgeracio derivation path, or return an error if the path
-    /// is not valid or if using a [hardened](https://en.bitcoin.it/wiki/BIP_0032#Extended_keys) key index (> 2**31).
+    /// is not valid or if using index 0 or a [hardened](https://en.bitcoin.it/wiki/BIP_0032#Extended_keys) key index (>= 2**31).
     ///
     /// # Errors
     ///
     /// Returns `Err` if:
     ///
     /// - The network is not supported.
-    /// - The account index is greater than 2**31.
+    /// - The account index is zero, or 2**31 or greater.
     pub fn new(
         network: Ss58AddressFormat,
         account_type: AccountType,
@@ -89,7 +92,10 @@ impl LedgeracioPath {
             Ss58AddressFormat::KusamaAccount => KUSAMA,
             bad_network => return Err(Error::UnsupportedNetwork(bad_network)),
         };
-        if account_index > HARDENED {
+        if account_index == 0 {
+            return Err(Error::ZeroKeyIndex)
+        }
+        if account_index >= HARDENED {
             return Err(Error::UnsupportedKeyIndex(account_index))
         }
         Ok(Self(BIP44Path([
@@ -102,6 +108,25 @@ impl LedgeracioPath {
     }
 }
 
+impl LedgeracioPath {
+    /// The path in the standard BIP-0044 notation, with the [SLIP-0044] coin
+    /// type and `'` marking hardened indices, such as `m/44'/354'/0'/0'/1'`
+    ///
+    /// [SLIP-0044]: https://github.com/satoshilabs/slips/blob/master/slip-0044.md
+    #[must_use]
+    pub fn slip44_notation(&self) -> String {
+        let mut notation = "m".to_owned();
+        for &index in &(self.0).0 {
+            if index & HARDENED == 0 {
+                notation += &format!("/{}", index)
+            } else {
+                notation += &format!("/{}'", index & !HARDENED)
+            }
+        }
+        notation
+    }
+}
+
 impl Clone for LedgeracioPath {
     fn clone(&self) -> Self { Self(BIP44Path((self.0).0)) }
 }
@@ -117,3 +142,37 @@ impl AsRef<[u32]> for LedgeracioPath {
 impl AsRef<BIP44Path> for LedgeracioPath {
     fn as_ref(&self) -> &BIP44Path { &self.0 }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slip44_notation() {
+        let path = LedgeracioPath::new(
+            Ss58AddressFormat::PolkadotAccount,
+            AccountType::Nominator,
+            5,
+        );
+        assert_eq!(path.unwrap().slip44_notation(), "m/44'/354'/0'/0'/5'");
+        let path = LedgeracioPath::new(Ss58AddressFormat::KusamaAccount, AccountType::Validator, 1);
+        assert_eq!(path.unwrap().slip44_notation(), "m/44'/434'/1'/0'/1'");
+    }
+
+    #[test]
+    fn rejects_bad_indices() {
+        let path = |index| {
+            LedgeracioPath::new(
+                Ss58AddressFormat::KusamaAccount,
+                AccountType::Nominator,
+                index,
+            )
+        };
+        assert!(matches!(path(0), Err(Error::ZeroKeyIndex)));
+        assert!(matches!(
+            path(HARDENED),
+            Err(Error::UnsupportedKeyIndex(HARDENED))
+        ));
+        assert!(path(HARDENED - 1).is_ok());
+    }
+}
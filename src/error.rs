@@ -0,0 +1,121 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of ledgeracio.
+//
+// ledgeracio is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ledgeracio is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ledgeracio.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Error categories, used to choose the exit status of the process
+
+use super::Error;
+
+/// The category of an error.  Each category has its own exit status, so that
+/// scripts can tell them apart.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ErrorCategory {
+    /// An error that does not fit any other category
+    Other,
+    /// The user provided invalid input
+    Input,
+    /// The Ledger device is missing, refused the operation, or failed
+    Device,
+    /// The RPC node could not be reached, or returned an error
+    Network,
+    /// The chain rejected the transaction
+    Chain,
+    /// An internal error.  This is a bug.
+    Internal,
+    /// The user interrupted the program with Ctrl-C
+    Interrupted,
+    /// The command did not finish within the time limit
+    TimedOut,
+}
+
+impl ErrorCategory {
+    /// The exit status for this category
+    #[must_use]
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Self::Other => 1,
+            Self::Input => 2,
+            Self::Device => 3,
+            Self::Network => 4,
+            Self::Chain => 5,
+            Self::Internal => 70,
+            Self::Interrupted => 130,
+            Self::TimedOut => 124,
+        }
+    }
+}
+
+/// An error message with an explicit category
+#[derive(::thiserror::Error, Debug)]
+#[error("{message}")]
+pub struct CategorizedError {
+    category: ErrorCategory,
+    message: String,
+}
+
+impl CategorizedError {
+    /// Create an error with the given category and message
+    pub fn new(category: ErrorCategory, message: impl Into<String>) -> Self {
+        Self {
+            category,
+            message: message.into(),
+        }
+    }
+}
+
+/// Create an error caused by invalid user input
+pub fn input_error(message: impl Into<String>) -> Error {
+    Box::new(CategorizedError::new(ErrorCategory::Input, message))
+}
+
+/// Determine the category of `error`
+#[must_use]
+pub fn categorize(error: &Error) -> ErrorCategory {
+    use substrate_subxt::Error as SubxtError;
+    if let Some(e) = error.downcast_ref::<CategorizedError>() {
+        e.category
+    } else if error.is::<crate::derivation::Error>()
+        || error.is::<std::num::ParseIntError>()
+        || error.is::<hex::FromHexError>()
+        || error.is::<codec::Error>()
+    {
+        ErrorCategory::Input
+    } else if error.is::<ledger_zondax_generic::LedgerAppError>()
+        || error.is::<ledger_transport_hid::LedgerHIDError>()
+    {
+        ErrorCategory::Device
+    } else if let Some(e) = error.downcast_ref::<SubxtError>() {
+        match e {
+            SubxtError::Io(_) | SubxtError::Rpc(_) => ErrorCategory::Network,
+            SubxtError::Invalid(_) | SubxtError::Runtime(_) => ErrorCategory::Chain,
+            SubxtError::Codec(_) | SubxtError::Metadata(_) => ErrorCategory::Internal,
+            _ => ErrorCategory::Other,
+        }
+    } else {
+        ErrorCategory::Other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_device_is_a_device_error() {
+        let error: Error = Box::new(ledger_transport_hid::LedgerHIDError::DeviceNotFound);
+        assert_eq!(categorize(&error), ErrorCategory::Device);
+        assert_eq!(categorize(&error).exit_code(), 3);
+    }
+}
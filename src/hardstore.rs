@@ -20,11 +20,19 @@
 //! To use this keystore, a Ledger device with the Kusama and/or Polkadot apps
 //! installed must be connected, and the process must have permission to use it.
 
-use super::{Encode, Error, LedgeracioPath};
+use super::{CategorizedError, Encode, Error, ErrorCategory, LedgeracioPath};
 use codec::Decode;
 use ledger_substrate::SubstrateApp;
-use std::{future::Future, pin::Pin, sync::Arc};
-use substrate_subxt::{sp_core::crypto::{AccountId32 as AccountId, Ss58AddressFormat},
+use ledger_zondax_generic::LedgerAppError;
+use std::{collections::HashMap,
+          fs::OpenOptions,
+          future::Future,
+          io::Write,
+          path::{Path, PathBuf},
+          pin::Pin,
+          sync::{atomic::{AtomicBool, Ordering},
+                 Arc, Mutex, MutexGuard, PoisonError}};
+use substrate_subxt::{sp_core::crypto::{AccountId32 as AccountId, Ss58AddressFormat, Ss58Codec},
                       sp_runtime::{generic::{SignedPayload, UncheckedExtrinsic},
                                    MultiSignature},
                       system::System,
@@ -33,8 +41,16 @@ use substrate_subxt::{sp_core::crypto::{AccountId32 as AccountId, Ss58AddressFor
 /// Hardware keystore
 pub struct HardStore {
     inner: Arc<SubstrateApp>,
+    dump_extrinsics: Option<PathBuf>,
+    /// The tip, in planck, of every extrinsic signed by this store
+    tip: u128,
+    /// The addresses already derived by the device, by path.  Commands often
+    /// need the same account several times, and each derivation is a round
+    /// trip to the device.
+    addresses: Mutex<HashMap<Vec<u32>, AccountId>>,
 }
 
+/// The future returned by [`substrate_subxt::Signer::sign`]
 pub type Signed<T> = Pin<
     Box<
         dyn Future<
@@ -52,17 +68,253 @@ pub type Signed<T> = Pin<
     >,
 >;
 
+/// Information about the app open on a Ledger device
+#[derive(Debug, Clone)]
+pub struct AppInfo {
+    /// The name of the app.  This is `BOLOS` if no app is open.
+    pub name: String,
+    /// The version of the app
+    pub version: String,
+    /// Whether the user has entered their PIN
+    pub pin_validated: bool,
+}
+
+impl AppInfo {
+    /// Check that this is the app named `app_name`, or the stock app for
+    /// `network` if `app_name` is `None`.  The name reported by the app may
+    /// have a suffix, such as the app variant.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the device is locked, the network is unsupported, or this is
+    /// the wrong app.
+    pub fn check(&self, network: Ss58AddressFormat, app_name: Option<&str>) -> Result<(), Error> {
+        let expected = expected_app_name(network, app_name)?;
+        if !self.pin_validated {
+            Err(locked_error())
+        } else if self.name.eq_ignore_ascii_case("BOLOS") {
+            Err(format!(
+                "No app is open on your Ledger device.  Please open the {} app.",
+                expected
+            )
+            .into())
+        } else if self
+            .name
+            .to_ascii_lowercase()
+            .starts_with(&*expected.to_ascii_lowercase())
+        {
+            Ok(())
+        } else {
+            Err(format!(
+                "The {} app is open on your Ledger device, but network {} requires the {} app.",
+                self.name,
+                String::from(network),
+                expected
+            )
+            .into())
+        }
+    }
+}
+
+/// The name of the app to expect: `app_name`, or the stock app for `network`
+fn expected_app_name(network: Ss58AddressFormat, app_name: Option<&str>) -> Result<&str, Error> {
+    match (app_name, network) {
+        (Some(app_name), _) => Ok(app_name),
+        (None, Ss58AddressFormat::PolkadotAccount) => Ok("Polkadot"),
+        (None, Ss58AddressFormat::KusamaAccount) => Ok("Kusama"),
+        (None, _) => Err(format!("Unsupported network {}", network).into()),
+    }
+}
+
+/// Status words with which a locked device refuses requests
+const LOCKED_STATUS: [u16; 2] = [0x5515, 0x6982];
+
+/// Whether `error` means that the device is locked
+fn is_locked(error: &LedgerAppError) -> bool {
+    matches!(error, LedgerAppError::AppSpecific(code, _) if LOCKED_STATUS.contains(code))
+}
+
+fn locked_error() -> Error {
+    Box::new(CategorizedError::new(
+        ErrorCategory::Device,
+        "Your Ledger is locked — enter your PIN and try again.",
+    ))
+}
+
+/// How many times to check whether an app has finished opening
+const OPEN_APP_ATTEMPTS: u32 = 60;
+
+/// How long to wait between checks, in milliseconds
+const OPEN_APP_INTERVAL_MS: u64 = 500;
+
+/// Whether exchanges with the device are traced to stderr
+static TRACE_APDU: AtomicBool = AtomicBool::new(false);
+
+/// Log every APDU exchanged with the device to stderr, in hex.  This is for
+/// diagnosing incompatibilities between Ledgeracio and an app.
+///
+/// Every device transport is wrapped in a [`TracingTransport`], so the APDUs
+/// that the app bindings split requests into are logged individually, along
+/// with a line describing the request they belong to.  Nothing secret is ever
+/// sent to or received from the device, but the payloads of signing and
+/// allowlist upload commands are replaced by their length and hash, and the
+/// keys returned by the device are hidden if [`confirm_on_device_only`] is
+/// enabled.
+pub fn trace_apdu(enable: bool) { TRACE_APDU.store(enable, Ordering::Relaxed) }
+
+/// Whether addresses may only be displayed by the device
+static CONFIRM_ON_DEVICE_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// What [`device_address`] displays instead of a hidden address
+pub const HIDDEN_ADDRESS: &str = "<hidden>";
+
+/// Treat the host’s screen and logs as untrusted.  The addresses of accounts
+/// on the device, and of the stashes they control, are then hidden by
+/// [`device_address`], and should be shown with [`HardStore::show_address`]
+/// instead.
+pub fn confirm_on_device_only(enable: bool) {
+    CONFIRM_ON_DEVICE_ONLY.store(enable, Ordering::Relaxed)
+}
+
+/// Whether [`confirm_on_device_only`] is enabled
+pub fn confirms_on_device_only() -> bool { CONFIRM_ON_DEVICE_ONLY.load(Ordering::Relaxed) }
+
+/// The address of `account`, which is on the device or tied to an account on
+/// it, for display by the host.  If [`confirm_on_device_only`] is enabled, it
+/// is [`HIDDEN_ADDRESS`].
+pub fn device_address(account: &AccountId, network: Ss58AddressFormat) -> String {
+    if confirms_on_device_only() {
+        HIDDEN_ADDRESS.to_owned()
+    } else {
+        account.to_ss58check_with_version(network)
+    }
+}
+
+fn trace(message: std::fmt::Arguments<'_>) {
+    if TRACE_APDU.load(Ordering::Relaxed) {
+        eprintln!("[apdu] {}", message)
+    }
+}
+
+/// The classes of commands handled by the device itself, rather than by an app
+const DEVICE_CLASSES: [u8; 2] = [0xb0, 0xe0];
+
+/// The instruction that returns the key and address at a path
+const INS_GET_ADDRESS: u8 = 0x01;
+
+/// The instruction that sends a chunk of a transaction to sign
+const INS_SIGN: u8 = 0x02;
+
+/// The instruction that sends a chunk of an allowlist
+const INS_ALLOWLIST_UPLOAD: u8 = 0x93;
+
+/// The length and a short hash of `data`, to trace in place of it.  Identical
+/// chunks still have identical hashes.
+fn digest(data: &[u8]) -> String {
+    let hash = blake2b_simd::Params::new().hash_length(8).hash(data);
+    format!("<{} bytes, blake2b {}>", data.len(), hash.to_hex())
+}
+
+/// A device transport that traces every APDU sent and received, if
+/// [`trace_apdu`] is enabled.  Transactions and allowlists are only traced as
+/// a [`digest`], and keys are hidden if [`confirm_on_device_only`] is enabled.
+struct TracingTransport {
+    inner: Box<dyn ledger_transport::Exchange>,
+}
+
+impl ledger_transport::Exchange for TracingTransport {
+    fn exchange<'a, 'b, 'c>(
+        &'a self,
+        command: &'b ledger_transport::APDUCommand,
+    ) -> Pin<
+        Box<
+            dyn Future<
+                    Output = Result<
+                        ledger_transport::APDUAnswer,
+                        ledger_transport::errors::TransportError,
+                    >,
+                > + Send
+                + 'c,
+        >,
+    >
+    where
+        'a: 'c,
+        'b: 'c,
+        Self: 'c,
+    {
+        Box::pin(async move {
+            let app_ins = if DEVICE_CLASSES.contains(&command.cla) {
+                None
+            } else {
+                Some(command.ins)
+            };
+            let header = hex::encode([command.cla, command.ins, command.p1, command.p2]);
+            match app_ins {
+                Some(INS_SIGN) | Some(INS_ALLOWLIST_UPLOAD) => {
+                    trace(format_args!("=> {} {}", header, digest(&command.data)))
+                }
+                _ => trace(format_args!("=> {}{}", header, hex::encode(&command.data))),
+            }
+            let answer = self.inner.exchange(command).await;
+            match &answer {
+                Ok(answer) if app_ins == Some(INS_GET_ADDRESS) && confirms_on_device_only() => {
+                    trace(format_args!("<= {} {:04x}", HIDDEN_ADDRESS, answer.retcode))
+                }
+                Ok(answer) => trace(format_args!(
+                    "<= {} {:04x}",
+                    hex::encode(&answer.data),
+                    answer.retcode
+                )),
+                Err(e) => trace(format_args!("<= error: {}", e)),
+            }
+            answer
+        })
+    }
+}
+
+fn device_error(message: String) -> Error {
+    Box::new(CategorizedError::new(ErrorCategory::Device, message))
+}
+
+fn open_transport() -> Result<ledger_substrate::APDUTransport, Error> {
+    Ok(ledger_substrate::APDUTransport {
+        transport_wrapper: Box::new(TracingTransport {
+            inner: ledger::TransportNativeHID::new()?,
+        }),
+    })
+}
+
+async fn app_info(transport: &ledger_substrate::APDUTransport) -> Result<AppInfo, Error> {
+    trace(format_args!("-- get app info"));
+    let info = ledger_zondax_generic::get_app_info(transport)
+        .await
+        .map_err(|e| {
+            if is_locked(&e) {
+                locked_error()
+            } else {
+                Box::new(e) as _
+            }
+        })?;
+    Ok(AppInfo {
+        name: info.app_name,
+        version: info.app_version,
+        pin_validated: info.flag_pin_validated,
+    })
+}
+
 impl HardStore {
     /// Creates an instance of [`HardStore`].
     ///
+    /// The app open on the device is checked with [`AppInfo::check`].  This
+    /// ensures that keys are never derived by the app for the wrong network.
+    ///
     /// # Errors
     ///
-    /// This will return `Err` if the network is unsupported or an I/O error
-    /// occurs.
-    pub fn new(network: Ss58AddressFormat) -> Result<Self, crate::Error> {
-        let transport = ledger_substrate::APDUTransport {
-            transport_wrapper: ledger::TransportNativeHID::new()?,
-        };
+    /// This will return `Err` if the network is unsupported, the wrong app is
+    /// open, or an I/O error occurs.
+    pub async fn new(network: Ss58AddressFormat, app_name: Option<&str>) -> Result<Self, Error> {
+        let transport = open_transport()?;
+        app_info(&transport).await?.check(network, app_name)?;
         let app = match network {
             Ss58AddressFormat::PolkadotAccount => ledger_substrate::new_polkadot_app,
             Ss58AddressFormat::KusamaAccount => ledger_substrate::new_kusama_app,
@@ -70,8 +322,182 @@ impl HardStore {
         }(transport);
         Ok(Self {
             inner: Arc::new(app),
+            dump_extrinsics: None,
+            tip: 0,
+            addresses: Mutex::default(),
         })
     }
+
+    /// Append every extrinsic signed by the signers of this store to the file
+    /// at `path`, as hex, one per line.  This is for keeping an audit trail of
+    /// the exact bytes that were broadcast.
+    #[must_use]
+    pub fn dump_extrinsics(self, path: PathBuf) -> Self {
+        Self {
+            dump_extrinsics: Some(path),
+            ..self
+        }
+    }
+
+    /// Pay a tip of `tip` planck with every extrinsic signed by the signers of
+    /// this store.  A tip raises the priority of a transaction in the pool of
+    /// pending transactions.
+    #[must_use]
+    pub fn tip(self, tip: u128) -> Self { Self { tip, ..self } }
+
+    /// The tip, in planck, paid with every extrinsic signed by the signers of
+    /// this store
+    #[must_use]
+    pub fn tip_amount(&self) -> u128 { self.tip }
+
+    /// Display the address at `path` on the device, and wait for the user to
+    /// confirm it there.
+    ///
+    /// # Errors
+    ///
+    /// This function will fail if the user rejects the address, the device is
+    /// locked, or an I/O error occurs.
+    pub async fn show_address(&self, path: &LedgeracioPath) -> Result<(), Error> {
+        trace(format_args!("-- show address {}", path));
+        let ledger_address = self.inner.get_address(path.as_ref(), true).await;
+        let address: AccountId = match ledger_address {
+            Ok(address) => address.public_key.into(),
+            Err(e) if is_locked(&e) => return Err(locked_error()),
+            Err(e) => {
+                return Err(device_error(format!(
+                    "The address at {} was not confirmed on the device: {}",
+                    path, e
+                )))
+            }
+        };
+        self.addresses()
+            .insert(AsRef::<[u32]>::as_ref(path).to_vec(), address);
+        Ok(())
+    }
+
+    /// Open the app for `network` (or the app named `app_name`, if it is
+    /// `Some`) if the device is on its dashboard, and wait for the app to
+    /// start.  Newer firmware asks the user to confirm this on the device.
+    /// Does nothing if the correct app is already open.
+    ///
+    /// # Errors
+    ///
+    /// This will return `Err` if another app is open, the app is not
+    /// installed, the user refuses, the app does not start in time, or an I/O
+    /// error occurs.
+    pub async fn open_app(network: Ss58AddressFormat, app_name: Option<&str>) -> Result<(), Error> {
+        let expected = expected_app_name(network, app_name)?;
+        let transport = open_transport()?;
+        let info = app_info(&transport).await?;
+        if !info.pin_validated {
+            return Err(locked_error())
+        }
+        if !info.name.eq_ignore_ascii_case("BOLOS") {
+            // Either the right app is already open, or apps cannot be opened
+            // from the one that is.
+            return info.check(network, app_name)
+        }
+        let command = ledger_transport::APDUCommand {
+            cla: 0xE0,
+            ins: 0xD8,
+            p1: 0,
+            p2: 0,
+            data: expected.as_bytes().to_vec(),
+        };
+        let answer = transport.exchange(&command).await?;
+        match answer.retcode {
+            0x9000 => {}
+            0x6807 => {
+                return Err(device_error(format!(
+                    "The {} app is not installed",
+                    expected
+                )))
+            }
+            0x5501 => {
+                return Err(device_error(format!(
+                    "Opening the {} app was refused",
+                    expected
+                )))
+            }
+            code => {
+                return Err(device_error(format!(
+                    "Opening the {} app failed with status {:#06x}",
+                    expected, code
+                )))
+            }
+        }
+        // The device reconnects once the app has started.
+        drop(transport);
+        for _ in 0..OPEN_APP_ATTEMPTS {
+            async_std::task::sleep(std::time::Duration::from_millis(OPEN_APP_INTERVAL_MS)).await;
+            if let Ok(transport) = open_transport() {
+                if let Ok(info) = app_info(&transport).await {
+                    if info.check(network, app_name).is_ok() {
+                        return Ok(())
+                    }
+                }
+            }
+        }
+        Err(device_error(format!(
+            "Timed out waiting for the {} app to start",
+            expected
+        )))
+    }
+
+    /// Close the app open on the attached Ledger device, returning it to the
+    /// dashboard.  Does nothing if no app is open.  Returns the name of the
+    /// app that was closed, if any.
+    ///
+    /// # Errors
+    ///
+    /// This will return `Err` if the device is locked, the app refuses to
+    /// close, or an I/O error occurs.
+    pub async fn quit_app() -> Result<Option<String>, Error> {
+        let transport = open_transport()?;
+        let info = app_info(&transport).await?;
+        if !info.pin_validated {
+            return Err(locked_error())
+        }
+        if info.name.eq_ignore_ascii_case("BOLOS") {
+            return Ok(None)
+        }
+        let command = ledger_transport::APDUCommand {
+            cla: 0xB0,
+            ins: 0xA7,
+            p1: 0,
+            p2: 0,
+            data: vec![],
+        };
+        match transport.exchange(&command).await?.retcode {
+            0x9000 => Ok(Some(info.name)),
+            code => Err(device_error(format!(
+                "Closing the {} app failed with status {:#06x}",
+                info.name, code
+            ))),
+        }
+    }
+
+    /// Query the app open on the attached Ledger device, without checking
+    /// which app it is.
+    ///
+    /// # Errors
+    ///
+    /// This will return `Err` if no device is attached or an I/O error occurs.
+    pub async fn app_info() -> Result<AppInfo, Error> { app_info(&open_transport()?).await }
+
+    /// Get the version of the app, as reported by the app itself.
+    ///
+    /// # Errors
+    ///
+    /// This function will fail if an I/O error occurs.
+    pub async fn version(&self) -> Result<String, Error> {
+        trace(format_args!("-- get version"));
+        let version = self.inner.get_version().await?;
+        Ok(format!(
+            "{}.{}.{}",
+            version.major, version.minor, version.patch
+        ))
+    }
 }
 
 #[derive(Clone)]
@@ -79,6 +505,14 @@ pub struct HardSigner {
     app: Arc<SubstrateApp>,
     path: LedgeracioPath,
     address: AccountId,
+    dump_extrinsics: Option<PathBuf>,
+    tip: u128,
+}
+
+/// Append the hex encoding of `extrinsic` to the file at `path`
+fn dump_extrinsic(path: &Path, extrinsic: &impl Encode) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "0x{}", hex::encode(extrinsic.encode()))
 }
 
 impl HardStore {
@@ -91,12 +525,27 @@ impl HardStore {
     /// This function will fail if no Ledger is inserted, the Ledger is not open
     /// to the correct app, or if there is an error communicating with the
     /// Ledger device.
+    ///
+    /// The address of each path is only requested from the device once per
+    /// [`HardStore`]; later calls with the same path reuse it.
     pub async fn signer(&self, path: LedgeracioPath) -> Result<HardSigner, Error> {
         let app = self.inner.clone();
+        let cached = self.addresses().get(AsRef::<[u32]>::as_ref(&path)).cloned();
+        if let Some(address) = cached {
+            return Ok(HardSigner {
+                app,
+                path,
+                address,
+                dump_extrinsics: self.dump_extrinsics.clone(),
+                tip: self.tip,
+            })
+        }
+        trace(format_args!("-- get address {}", path));
         let ledger_address = app.get_address(path.as_ref(), false).await;
 
         let ledger_address = match ledger_address {
             Ok(e) => e,
+            Err(e) if is_locked(&e) => return Err(locked_error()),
             Err(e) => {
                 eprintln!(
                     "Failed to obtain a signer for path {}: {}.\n\nCheck that your Ledger device \
@@ -107,8 +556,24 @@ impl HardStore {
                 return Err(Box::new(e) as _)
             }
         };
-        let address = ledger_address.public_key.into();
-        Ok(HardSigner { app, path, address })
+        let address: AccountId = ledger_address.public_key.into();
+        self.addresses()
+            .insert(AsRef::<[u32]>::as_ref(&path).to_vec(), address.clone());
+        Ok(HardSigner {
+            app,
+            path,
+            address,
+            dump_extrinsics: self.dump_extrinsics.clone(),
+            tip: self.tip,
+        })
+    }
+
+    /// The cache of derived addresses
+    fn addresses(&self) -> MutexGuard<'_, HashMap<Vec<u32>, AccountId>> {
+        // Entries are inserted whole, so a poisoned cache is still consistent
+        self.addresses
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
     }
 
     /// Set a public key
@@ -118,6 +583,7 @@ impl HardStore {
     /// This function will fail if the device refuses the operation, the app is
     /// not the special Ledgeracio app, or an I/O error occurs.
     pub async fn set_pubkey(&self, key: &'_ [u8; 32]) -> Result<(), Error> {
+        trace(format_args!("-- set allowlist key"));
         self.inner
             .allowlist_set_pubkey(key)
             .await
@@ -131,6 +597,7 @@ impl HardStore {
     /// This function will fail if the device refuses the operation, the app is
     /// not the special Ledgeracio app, or an I/O error occurs.
     pub async fn allowlist_upload(&self, allowlist: &[u8]) -> Result<(), Error> {
+        trace(format_args!("-- upload allowlist"));
         self.inner
             .allowlist_upload(allowlist)
             .await
@@ -143,6 +610,7 @@ impl HardStore {
     ///
     /// This function will fail if an I/O error occurs.
     pub async fn get_pubkey(&self) -> Result<[u8; 32], Error> {
+        trace(format_args!("-- get allowlist key"));
         self.inner.allowlist_get_pubkey().await.map_err(From::from)
     }
 }
@@ -174,6 +642,7 @@ impl HardSigner {
         let call = extrinsic.deconstruct();
         let call_bytes = call.encode();
         let (call, extra, _) = call;
+        trace(format_args!("-- sign with {}", path));
         let signature = match app.sign(path.as_ref(), &*call_bytes).await {
             Ok(e) => e,
             Err(e) => return Err(e.to_string()),
@@ -182,12 +651,18 @@ impl HardSigner {
             Ok(e) => e,
             Err(e) => return Err(e.to_string()),
         };
-        Ok(UncheckedExtrinsic::new_signed(
-            call,
-            self.address.clone(),
-            signature,
-            extra,
-        ))
+        let extrinsic =
+            UncheckedExtrinsic::new_signed(call, self.address.clone(), signature, extra);
+        if let Some(dump) = &self.dump_extrinsics {
+            dump_extrinsic(dump, &extrinsic).map_err(|e| {
+                format!(
+                    "Cannot write the signed extrinsic to {}: {}",
+                    dump.display(),
+                    e
+                )
+            })?
+        }
+        Ok(extrinsic)
     }
 }
 
@@ -203,6 +678,11 @@ impl substrate_subxt::Signer<substrate_subxt::KusamaRuntime> for HardSigner {
         extrinsic: SignedPayload<Encoded, <<T as Runtime>::Extra as SignedExtra<T>>::Extra>,
     ) -> Signed<T> {
         let tmp = self.clone();
+        let (call, mut extra, additional) = extrinsic.deconstruct();
+        // `ChargeTransactionPayment`, which carries the tip, is the last signed
+        // extension.  subxt always sets the tip to zero.
+        (extra.6).0 = self.tip;
+        let extrinsic = SignedPayload::from_raw(call, extra, additional);
         Box::pin(async move { tmp.sign::<T>(extrinsic).await })
     }
 }
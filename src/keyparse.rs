@@ -16,16 +16,25 @@
 
 //! Routines for parsing public and secret keys
 
-use super::{KEY_MAGIC, KEY_VERSION};
-use ed25519_dalek::{ExpandedSecretKey, Keypair, PublicKey};
-use ledgeracio::Error;
+use crate::Error;
+use ed25519_dalek::{ExpandedSecretKey, Keypair, PublicKey, SecretKey};
 use regex::bytes::Regex;
 use std::{convert::{TryFrom, TryInto},
           str};
 use substrate_subxt::sp_core::crypto::Ss58AddressFormat;
 
+/// The version of keys supported
+pub const KEY_VERSION: u8 = 1;
+
+/// The magic number at the beginning of a secret key
+pub const KEY_MAGIC: &[u8] = &*b"Ledgeracio Secret Key";
+
 /// Parse a Ledgeracio secret key file
-pub(crate) fn parse_secret(secret: &[u8], network: Ss58AddressFormat) -> Result<Keypair, Error> {
+///
+/// # Errors
+///
+/// Fails if the key is malformed, or is not for `network`.
+pub fn parse_secret(secret: &[u8], network: Ss58AddressFormat) -> Result<Keypair, Error> {
     if secret.len() != 88 {
         return Err(format!("Ledgeracio secret keys are 88 bytes, not {}", secret.len()).into())
     }
@@ -63,10 +72,56 @@ pub(crate) fn parse_secret(secret: &[u8], network: Ss58AddressFormat) -> Result<
     Ok(keypair)
 }
 
+/// Decode a Ledgeracio secret key that has been encoded as text, such as for
+/// storage in an environment variable.  Hex (with or without a leading `0x`)
+/// and standard base64 are accepted.  The result must still be checked with
+/// [`parse_secret`].
+///
+/// # Errors
+///
+/// Fails if `encoded` is neither valid hex nor valid base64.
+pub fn decode_secret(encoded: &str) -> Result<Vec<u8>, Error> {
+    let encoded = encoded.trim();
+    let hex = encoded.strip_prefix("0x").unwrap_or(encoded);
+    if let Ok(secret) = hex::decode(hex) {
+        return Ok(secret)
+    }
+    base64::decode_config(encoded, base64::STANDARD).map_err(|_| {
+        "Secret key must be encoded as hex or base64"
+            .to_owned()
+            .into()
+    })
+}
+
+/// Parse the seed of a secret key, as 32 bytes of hex with or without a leading
+/// `0x`
+///
+/// # Errors
+///
+/// Fails if `seed` is not valid hex, or is not 32 bytes long.
+pub fn parse_seed(seed: &str) -> Result<SecretKey, Error> {
+    let seed = hex::decode(seed.strip_prefix("0x").unwrap_or(seed))?;
+    SecretKey::from_bytes(&seed).map_err(|_| "Seeds must be 32 bytes of hex".to_owned().into())
+}
+
+/// A short fingerprint of a public key, for telling keys apart at a glance:
+/// the first 8 bytes of its BLAKE2b-256 hash, as hex
+#[must_use]
+pub fn fingerprint(pk: &PublicKey) -> String {
+    let digest = blake2b_simd::Params::new()
+        .hash_length(32)
+        .hash(pk.as_bytes());
+    hex::encode(&digest.as_bytes()[..8])
+}
+
 /// Parse a Ledgeracio public key
 ///
 /// See FORMATS.md for the format of this key.
-pub(crate) fn parse_public(unparsed: &[u8]) -> Result<(PublicKey, Ss58AddressFormat), Error> {
+///
+/// # Errors
+///
+/// Fails if the key is malformed.
+pub fn parse_public(unparsed: &[u8]) -> Result<(PublicKey, Ss58AddressFormat), Error> {
     let re = Regex::new(
         r"^untrusted comment: Ledgeracio v2 network ([[:alpha:]]+) public key\n([[:alnum:]/+]+)\n$",
     )
@@ -105,6 +160,32 @@ mod tests {
     #[test]
     #[should_panic(expected = "Ledgeracio secret keys are 88 bytes, not 87")]
     fn too_short_rejected() { parse_secret(&[0; 87][..], Default::default()).unwrap(); }
+
+    #[test]
+    fn decodes_text_secrets() {
+        let secret: Vec<u8> = (0..88).collect();
+        let hex = hex::encode(&secret);
+        assert_eq!(decode_secret(&hex).unwrap(), secret);
+        assert_eq!(decode_secret(&format!("0x{}\n", hex)).unwrap(), secret);
+        assert_eq!(decode_secret(&base64::encode(&secret)).unwrap(), secret);
+        assert!(decode_secret("not a key!").is_err());
+    }
+    #[test]
+    fn parses_seeds() {
+        let seed = [7_u8; 32];
+        let hex = hex::encode(&seed);
+        assert_eq!(parse_seed(&format!("0x{}", hex)).unwrap().as_bytes(), &seed);
+        assert_eq!(parse_seed(&hex).unwrap().as_bytes(), &seed);
+        assert!(parse_seed(&hex[2..]).is_err());
+        assert!(parse_seed(&format!("{}07", hex)).is_err());
+        assert!(parse_seed(&format!("0x{}", &hex[1..])).is_err());
+        assert!(parse_seed("0xnot hex").is_err());
+    }
+    #[test]
+    fn fingerprints_are_short_and_stable() {
+        let keypair = parse_secret(GOOD_KEY, Ss58AddressFormat::PolkadotAccount).unwrap();
+        assert_eq!(fingerprint(&keypair.public), "4aa21e0bd6dec5fb");
+    }
     #[test]
     #[should_panic(expected = "Ledgeracio secret keys are 88 bytes, not 89")]
     fn too_long_rejected() { parse_secret(&[0; 89][..], Default::default()).unwrap(); }
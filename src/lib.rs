@@ -21,11 +21,16 @@
 #![forbid(unsafe_code)]
 
 mod derivation;
+mod error;
 mod hardstore;
+pub mod keyparse;
+pub mod parser;
 
 use codec::Encode;
 pub use derivation::{AccountType, LedgeracioPath};
-pub use hardstore::{HardSigner, HardStore};
+pub use error::{categorize, input_error, CategorizedError, ErrorCategory};
+pub use hardstore::{confirm_on_device_only, confirms_on_device_only, device_address, trace_apdu,
+                    AppInfo, HardSigner, HardStore, Signed, HIDDEN_ADDRESS};
 
 #[cfg(not(unix))]
 compile_error!("Only *nix-like platforms are supported");
@@ -70,13 +75,12 @@ pub fn validate_network(
     if network == provided_network.try_into().unwrap() {
         Ok(())
     } else {
-        Err(format!(
+        Err(input_error(format!(
             "Network mismatch: address {} is for network {}, but you asked to use network {}",
             address,
             String::from(Ss58AddressFormat::try_from(provided_network).unwrap()),
             String::from(network),
-        )
-        .into())
+        )))
     }
 }
 
@@ -86,5 +90,6 @@ pub fn validate_network(
 ///
 /// Fails if `Ss58AddressFormat::try_from` fails.
 pub fn get_network(address: &str) -> Result<Ss58AddressFormat, Error> {
-    Ss58AddressFormat::try_from(address).map_err(|_| format!("Unknown network {}", address).into())
+    Ss58AddressFormat::try_from(address)
+        .map_err(|_| input_error(format!("Unknown network {}", address)))
 }
@@ -0,0 +1,808 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of ledgeracio.
+//
+// ledgeracio is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ledgeracio is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ledgeracio.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Ledgeracio allowlist parser/signer
+
+use ed25519_dalek::{ExpandedSecretKey, PublicKey};
+use std::{convert::TryFrom,
+          io::{prelude::*, Error, ErrorKind}};
+use substrate_subxt::sp_core::crypto::{AccountId32 as AccountId, Ss58AddressFormat, Ss58Codec};
+
+/// The magic number that starts the optional metadata section
+const METADATA_MAGIC: &[u8; 4] = b"META";
+
+/// The magic number that starts the version trailer at the end of the file
+const VERSION_MAGIC: &[u8; 4] = b"VERS";
+
+/// The length of the version trailer: the magic number, the version, and the
+/// signature of everything before the signature
+const VERSION_TRAILER_LEN: usize = 72;
+
+/// The version of the binary allowlist format written by [`parse`].  Files
+/// without a version trailer predate it, and are version 1.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// The prefix of comment lines that are embedded as metadata
+const METADATA_PREFIX: &str = "#meta:";
+
+/// The default maximum number of addresses in an allowlist
+pub const DEFAULT_MAX_ENTRIES: u32 = 10_000;
+
+/// Whether the trimmed line `line` of a textual allowlist is a comment
+fn is_comment(line: &str) -> bool {
+    line.starts_with(';') || line.starts_with('#') || line.is_empty()
+}
+
+/// Parse the address on line `l` of a textual allowlist, and check that it is
+/// for `network`
+fn parse_line(l: usize, trimmed: &str, network: Ss58AddressFormat) -> std::io::Result<AccountId> {
+    let (address, address_type) = crate::parse_address(trimmed).map_err(|i| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("parse error on line {}: {}", l, i),
+        )
+    })?;
+    crate::validate_network(trimmed, address_type, network).map_err(|i| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid network on line {}: {}", l, i),
+        )
+    })?;
+    Ok(address)
+}
+
+/// Merge textual allowlists into one, ready to be signed with [`parse`].
+/// Every address is checked as by [`parse`], and duplicates are removed.
+/// Metadata lines are kept, without duplicates, before the addresses; other
+/// comments are dropped.  Returns the merged list and the number of
+/// duplicate addresses removed.
+///
+/// # Errors
+///
+/// Fails if an address is malformed or for the wrong network, or an I/O error
+/// occurs.  The error names the list, using the name paired with its reader.
+pub fn merge<T: BufRead>(
+    lists: impl IntoIterator<Item = (String, T)>,
+    network: Ss58AddressFormat,
+) -> std::io::Result<(String, usize)> {
+    let mut metadata = vec![];
+    let mut seen = std::collections::BTreeSet::new();
+    let mut addresses = vec![];
+    let mut duplicates = 0;
+    for (name, reader) in lists {
+        for (l, line) in reader.lines().enumerate() {
+            let line = line?;
+            let trimmed = line.trim();
+            if trimmed.starts_with(METADATA_PREFIX) {
+                if !metadata.iter().any(|i| i == trimmed) {
+                    metadata.push(trimmed.to_owned())
+                }
+                continue
+            }
+            if is_comment(trimmed) {
+                continue
+            }
+            let address = parse_line(l, trimmed, network)
+                .map_err(|e| Error::new(e.kind(), format!("{}: {}", name, e)))?;
+            if seen.insert(address) {
+                addresses.push(trimmed.to_owned())
+            } else {
+                duplicates += 1
+            }
+        }
+    }
+    let mut merged = String::new();
+    for line in metadata.iter().chain(&addresses) {
+        merged.push_str(line);
+        merged.push('\n');
+    }
+    Ok((merged, duplicates))
+}
+
+/// Compile a textual allowlist to binary format and sign it.
+///
+/// Comment lines starting with `#meta:` are metadata.  If there are any, they
+/// are appended after the addresses, in a section that is signed separately.
+/// The signature of this section covers everything before it, too.  The
+/// device does not understand this section, so it must be removed with
+/// [`device_part`] before uploading.  The allowlist ends with a signed trailer
+/// giving its format version, [`FORMAT_VERSION`].
+///
+/// # Errors
+///
+/// Fails if an address is malformed or for the wrong network, the allowlist has
+/// more than `max_entries` addresses, or an I/O error occurs.
+pub fn parse<T: BufRead, U: Ss58Codec>(
+    reader: T,
+    network: Ss58AddressFormat,
+    pk: &PublicKey,
+    sk: &ExpandedSecretKey,
+    nonce: u32,
+    max_entries: u32,
+) -> std::io::Result<Vec<u8>> {
+    let mut v = vec![0; 72];
+    let mut entries = 0_u32;
+    let mut metadata = vec![];
+    v[..4].copy_from_slice(&nonce.to_le_bytes());
+    for (l, i) in reader.lines().enumerate() {
+        let i = i?;
+        let trimmed = i.trim_start().trim_end();
+        if let Some(data) = trimmed.strip_prefix(METADATA_PREFIX) {
+            metadata.push(data.trim_start().to_owned());
+            continue
+        }
+        if is_comment(trimmed) {
+            continue
+        }
+        parse_line(l, trimmed, network)?;
+        entries += 1;
+        if entries > max_entries {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "too many addresses: line {} is address {}, but the maximum is {}",
+                    l, entries, max_entries
+                ),
+            ))
+        }
+        let bytes = trimmed.as_bytes();
+        let current_len = v.len();
+        v.extend_from_slice(&[0_u8; 64]);
+        v[current_len..current_len + bytes.len()].copy_from_slice(bytes);
+    }
+    let total_len_bytes = u32::try_from((v.len() - 68) >> 6)
+        .map_err(|_| Error::new(ErrorKind::Other, "Data too long".to_owned()))?
+        .to_le_bytes();
+    v[4..8].copy_from_slice(&total_len_bytes);
+    let digest = blake2b_simd::Params::new()
+        .hash_length(32)
+        .to_state()
+        .update(&v[..8])
+        .update(&v[72..])
+        .finalize();
+    let signature = sk.sign(&digest.as_bytes(), &pk);
+    v[8..72].copy_from_slice(&signature.to_bytes()[..]);
+    if !metadata.is_empty() {
+        let metadata = metadata.join("\n");
+        let metadata_len = u32::try_from(metadata.len())
+            .map_err(|_| Error::new(ErrorKind::Other, "Metadata too long".to_owned()))?;
+        v.extend_from_slice(METADATA_MAGIC);
+        v.extend_from_slice(&metadata_len.to_le_bytes());
+        v.extend_from_slice(metadata.as_bytes());
+        let digest = blake2b_simd::Params::new()
+            .hash_length(32)
+            .to_state()
+            .update(&v)
+            .finalize();
+        let signature = sk.sign(&digest.as_bytes(), &pk);
+        v.extend_from_slice(&signature.to_bytes()[..]);
+    }
+    v.extend_from_slice(VERSION_MAGIC);
+    v.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    let digest = blake2b_simd::Params::new()
+        .hash_length(32)
+        .to_state()
+        .update(&v)
+        .finalize();
+    let signature = sk.sign(&digest.as_bytes(), &pk);
+    v.extend_from_slice(&signature.to_bytes()[..]);
+    Ok(v)
+}
+
+/// The version trailer at the end of `allowlist`, if it has one, after
+/// checking its format version.  The signature of the trailer is not checked.
+fn version_trailer(allowlist: &[u8]) -> std::io::Result<Option<&[u8]>> {
+    let len = allowlist.len();
+    if len < VERSION_TRAILER_LEN || &allowlist[len - VERSION_TRAILER_LEN..][..4] != VERSION_MAGIC {
+        return Ok(None)
+    }
+    let trailer = &allowlist[len - VERSION_TRAILER_LEN..];
+    let mut version = [0_u8; 4];
+    version.copy_from_slice(&trailer[4..8]);
+    match u32::from_le_bytes(version) {
+        FORMAT_VERSION => Ok(Some(trailer)),
+        version => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("unsupported allowlist format version {}", version),
+        )),
+    }
+}
+
+/// Check the format version of a binary allowlist and the signature of its
+/// version trailer, and return the allowlist without the trailer.  The version
+/// is checked first, as an unknown version may use a different signature
+/// algorithm.
+fn strip_version<'a>(allowlist: &'a [u8], pk: &PublicKey) -> std::io::Result<&'a [u8]> {
+    let trailer = match version_trailer(allowlist)? {
+        Some(trailer) => trailer,
+        None => return Ok(allowlist),
+    };
+    let rest = &allowlist[..allowlist.len() - VERSION_TRAILER_LEN];
+    let mut signature = [0_u8; 64];
+    signature.copy_from_slice(&trailer[8..]);
+    let digest = blake2b_simd::Params::new()
+        .hash_length(32)
+        .to_state()
+        .update(rest)
+        .update(&trailer[..8])
+        .finalize();
+    ed25519_dalek::PublicKey::verify_strict(
+        &pk,
+        digest.as_bytes(),
+        &ed25519_dalek::Signature::new(signature),
+    )
+    .map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidData,
+            "Allowlist version forged!".to_owned(),
+        )
+    })?;
+    Ok(rest)
+}
+
+/// The part of a binary allowlist that is uploaded to the device: everything
+/// except the metadata section and version trailer, if any.  The format
+/// version is checked, so that a file in a future format is never uploaded.
+///
+/// # Errors
+///
+/// Fails if the allowlist is truncated or in an unsupported format version.
+pub fn device_part(allowlist: &[u8]) -> std::io::Result<&[u8]> {
+    version_trailer(allowlist)?;
+    let too_short = || Error::new(ErrorKind::InvalidData, "allowlist too short".to_owned());
+    if allowlist.len() < 72 {
+        return Err(too_short())
+    }
+    let mut length = [0_u8; 4];
+    length.copy_from_slice(&allowlist[4..8]);
+    let len = usize::try_from(u32::from_le_bytes(length))
+        .ok()
+        .and_then(|length| length.checked_mul(64))
+        .and_then(|length| length.checked_add(72))
+        .ok_or_else(too_short)?;
+    allowlist.get(..len).ok_or_else(too_short)
+}
+
+/// The signature of a binary allowlist, as checked by the device.  This is the
+/// ed25519 signature of the 32-byte BLAKE2b hash of the nonce, the number of
+/// addresses, and the addresses.  It does not cover the metadata section.
+///
+/// # Errors
+///
+/// Fails if the allowlist is truncated.
+pub fn signature(allowlist: &[u8]) -> std::io::Result<[u8; 64]> {
+    let mut signature = [0_u8; 64];
+    signature.copy_from_slice(&device_part(allowlist)?[8..72]);
+    Ok(signature)
+}
+
+/// Decode a hex-encoded binary allowlist, as written by `sign --hex`.  Leading
+/// and trailing whitespace and a `0x` prefix are ignored.
+///
+/// # Errors
+///
+/// Fails if `text` is not valid hex.
+pub fn from_hex(text: &[u8]) -> std::io::Result<Vec<u8>> {
+    let text = std::str::from_utf8(text)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("invalid hex: {}", e)))?
+        .trim();
+    hex::decode(text.strip_prefix("0x").unwrap_or(text))
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("invalid hex: {}", e)))
+}
+
+/// A summary of a verified binary allowlist
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Summary {
+    /// The nonce
+    pub nonce: u32,
+    /// The number of addresses
+    pub count: u32,
+    /// The metadata, one item per line, if there is a metadata section
+    pub metadata: Option<String>,
+}
+
+/// Read a binary allowlist and verify its signatures.  `entry` is called with
+/// the index and raw bytes of each address.  The format version is checked
+/// before anything else, so that files in a future format are rejected
+/// instead of being verified as if they were in this one.
+fn read_verified<T: Read>(
+    mut file: T,
+    pk: &PublicKey,
+    mut entry: impl FnMut(u32, &[u8; 64]) -> std::io::Result<()>,
+) -> std::io::Result<Summary> {
+    let mut allowlist = vec![];
+    file.read_to_end(&mut allowlist)?;
+    let mut reader = strip_version(&allowlist, pk)?;
+    let mut nonce = [0_u8; 4];
+    let mut length = [0_u8; 4];
+    let mut sig = [0_u8; 64];
+    reader.read_exact(&mut nonce[..])?;
+    reader.read_exact(&mut length[..])?;
+    let mut digest = blake2b_simd::Params::new().hash_length(32).to_state();
+    let mut full_digest = blake2b_simd::Params::new().hash_length(32).to_state();
+    digest.update(&nonce);
+    digest.update(&length);
+    full_digest.update(&nonce);
+    full_digest.update(&length);
+    let length = u32::from_le_bytes(length);
+    reader.read_exact(&mut sig[..])?;
+    full_digest.update(&sig);
+    for i in 0..length {
+        let mut address = [0_u8; 64];
+        reader.read_exact(&mut address[..])?;
+        digest.update(&address[..]);
+        full_digest.update(&address[..]);
+        entry(i, &address)?;
+    }
+    let mut trailer = vec![];
+    reader.read_to_end(&mut trailer)?;
+    ed25519_dalek::PublicKey::verify_strict(
+        &pk,
+        digest.finalize().as_bytes(),
+        &ed25519_dalek::Signature::new(sig),
+    )
+    .map_err(|_| Error::new(ErrorKind::InvalidData, "Allowlist forged!".to_owned()))?;
+    let metadata = if trailer.is_empty() {
+        None
+    } else {
+        Some(inspect_metadata(&trailer, full_digest, pk)?)
+    };
+    Ok(Summary {
+        nonce: u32::from_le_bytes(nonce),
+        count: length,
+        metadata,
+    })
+}
+
+/// Verify the signatures of a binary allowlist, without checking its
+/// addresses.  This is much faster than [`inspect`] for long allowlists.
+///
+/// # Errors
+///
+/// Fails if the allowlist is truncated or forged, or an I/O error occurs.
+pub fn verify<T: Read>(reader: T, pk: &PublicKey) -> std::io::Result<Summary> {
+    read_verified(reader, pk, |_, _| Ok(()))
+}
+
+/// Verify a binary allowlist, and return its contents in textual form: the
+/// nonce, then the metadata (if any), then the addresses.
+///
+/// # Errors
+///
+/// Fails if the allowlist is malformed or forged, or an I/O error occurs.
+pub fn inspect<T: BufRead, U: Ss58Codec>(
+    reader: T,
+    network: Ss58AddressFormat,
+    pk: &PublicKey,
+) -> std::io::Result<Vec<String>> {
+    let mut addresses = vec![];
+    let summary = read_verified(reader, pk, |i, address| {
+        let len = address.iter().position(|&s| s == b'\0').unwrap_or(64);
+        let trimmed = core::str::from_utf8(&address[..len]).map_err(|j| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid UTF8 in address {}: {}", i, j),
+            )
+        })?;
+        let address_type = crate::parse_address::<AccountId>(trimmed)
+            .map_err(|j| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("parse error on line {}: {}", i, j),
+                )
+            })?
+            .1;
+        crate::validate_network(trimmed, address_type, network).map_err(|j| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid network on line {}: {}", i, j),
+            )
+        })?;
+        addresses.push(trimmed.to_owned());
+        Ok(())
+    })?;
+    let mut output = vec![format!("Nonce: {}\n", summary.nonce)];
+    if let Some(metadata) = summary.metadata {
+        output.extend(
+            metadata
+                .lines()
+                .map(|i| format!("{} {}", METADATA_PREFIX, i)),
+        );
+    }
+    output.extend(addresses);
+    Ok(output)
+}
+
+/// Check that `signed`, a binary allowlist produced by [`parse`] from the
+/// textual allowlist `text`, verifies with `pk` and contains exactly the nonce,
+/// metadata, and addresses in `text`.  This catches serialization bugs before
+/// the allowlist is uploaded.
+///
+/// # Errors
+///
+/// Fails if `signed` does not verify or does not match `text`, or an I/O error
+/// occurs.
+pub fn check_signed<T: BufRead>(
+    text: T,
+    signed: &[u8],
+    network: Ss58AddressFormat,
+    pk: &PublicKey,
+    nonce: u32,
+) -> std::io::Result<()> {
+    let mut metadata = vec![];
+    let mut addresses = vec![];
+    for line in text.lines() {
+        let line = line?;
+        let trimmed = line.trim();
+        if let Some(data) = trimmed.strip_prefix(METADATA_PREFIX) {
+            metadata.push(data.trim_start().to_owned())
+        } else if !is_comment(trimmed) {
+            addresses.push(trimmed.to_owned())
+        }
+    }
+    let mut expected = vec![format!("Nonce: {}\n", nonce)];
+    // Metadata items are joined with newlines when signed, so split them the
+    // same way `inspect` does.
+    expected.extend(
+        metadata
+            .join("\n")
+            .lines()
+            .map(|i| format!("{} {}", METADATA_PREFIX, i)),
+    );
+    expected.extend(addresses);
+    if inspect::<_, AccountId>(signed, network, pk)? == expected {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            "signed allowlist does not match its input".to_owned(),
+        ))
+    }
+}
+
+/// Verify the metadata section `trailer` and return the metadata in it.
+/// `digest` must have been updated with everything before the section.
+fn inspect_metadata(
+    trailer: &[u8],
+    mut digest: blake2b_simd::State,
+    pk: &PublicKey,
+) -> std::io::Result<String> {
+    let junk = || Error::new(ErrorKind::InvalidData, "junk at end of file".to_owned());
+    if trailer.len() < 72 || &trailer[..4] != METADATA_MAGIC {
+        return Err(junk())
+    }
+    let mut length = [0_u8; 4];
+    length.copy_from_slice(&trailer[4..8]);
+    let length = usize::try_from(u32::from_le_bytes(length)).map_err(|_| junk())?;
+    if trailer.len() - 72 != length {
+        return Err(junk())
+    }
+    let (data, sig) = trailer.split_at(8 + length);
+    let mut signature = [0_u8; 64];
+    signature.copy_from_slice(sig);
+    digest.update(data);
+    ed25519_dalek::PublicKey::verify_strict(
+        &pk,
+        digest.finalize().as_bytes(),
+        &ed25519_dalek::Signature::new(signature),
+    )
+    .map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidData,
+            "Allowlist metadata forged!".to_owned(),
+        )
+    })?;
+    String::from_utf8(data[8..].to_owned()).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid UTF8 in metadata: {}", e),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::Keypair;
+    const BUF: &[u8] = br#"
+5DArCreQ9Yk2HaGvxcRHS35qky3eXBD5BprPZQvbiJBfFY6Y
+; a comment
+
+# another comment
+   ; leading spaces
+   5GQvjFcJBCGTFeb2hvtQ9yRfbDNQajLJbW1yzgCra5uUTLvn
+5Cw8KtiVsBx4AK9SCzAMmXvprJiYuhRYwDUA4WHJ55ghYgYL
+5EhBPkiqA1rkoFZL6o87bSpgfTptHzp6nE3VkH4dRUed1Qdh
+5G3uDdTW8MeGW1QZR9FeZuN1exiVJZnUJ9ovyJexubiytNUj     
+5FbtadyFPdDZMiLYjdEwAyFqavVwzYueEYX8Z6fsL4UrxTXx
+5ENTEF2sAtM89XxdwRxwSKDF7hxX9udy7zdr2G4i8bRdbBH9
+5EWgCx3UMqzYt9vSf7GCHd2jhRUYF7GqVNeyPjpXxGkLV7b4
+5DFxRkcYqWa1CFkqKzM7meytTKyPMR72TPJjBb6S5zvnpuCz
+		"#;
+    const NONCE: u32 = 0;
+    #[test]
+    fn accepts_own_output() {
+        let keypair = Keypair::generate(&mut rand::rngs::OsRng {});
+        let parsed: Vec<u8> = parse::<&[u8], AccountId>(
+            &mut BUF,
+            Ss58AddressFormat::SubstrateAccount,
+            &keypair.public,
+            &(&keypair.secret).into(),
+            NONCE,
+            DEFAULT_MAX_ENTRIES,
+        )
+        .expect("no error");
+        let inspected = inspect::<&[u8], AccountId>(
+            &mut &*parsed,
+            Ss58AddressFormat::SubstrateAccount,
+            &keypair.public,
+        )
+        .expect("no error");
+        assert_eq!(
+            inspected,
+            &[
+                "Nonce: 0\n",
+                "5DArCreQ9Yk2HaGvxcRHS35qky3eXBD5BprPZQvbiJBfFY6Y",
+                "5GQvjFcJBCGTFeb2hvtQ9yRfbDNQajLJbW1yzgCra5uUTLvn",
+                "5Cw8KtiVsBx4AK9SCzAMmXvprJiYuhRYwDUA4WHJ55ghYgYL",
+                "5EhBPkiqA1rkoFZL6o87bSpgfTptHzp6nE3VkH4dRUed1Qdh",
+                "5G3uDdTW8MeGW1QZR9FeZuN1exiVJZnUJ9ovyJexubiytNUj",
+                "5FbtadyFPdDZMiLYjdEwAyFqavVwzYueEYX8Z6fsL4UrxTXx",
+                "5ENTEF2sAtM89XxdwRxwSKDF7hxX9udy7zdr2G4i8bRdbBH9",
+                "5EWgCx3UMqzYt9vSf7GCHd2jhRUYF7GqVNeyPjpXxGkLV7b4",
+                "5DFxRkcYqWa1CFkqKzM7meytTKyPMR72TPJjBb6S5zvnpuCz"
+            ][..]
+        );
+    }
+
+    #[test]
+    fn detached_signature_verifies() {
+        let keypair = Keypair::generate(&mut rand::rngs::OsRng {});
+        let parsed: Vec<u8> = parse::<&[u8], AccountId>(
+            &mut BUF,
+            Ss58AddressFormat::SubstrateAccount,
+            &keypair.public,
+            &(&keypair.secret).into(),
+            NONCE,
+            DEFAULT_MAX_ENTRIES,
+        )
+        .expect("no error");
+        let digest = blake2b_simd::Params::new()
+            .hash_length(32)
+            .to_state()
+            .update(&parsed[..8])
+            .update(&device_part(&parsed).unwrap()[72..])
+            .finalize();
+        let detached = ed25519_dalek::Signature::new(signature(&parsed).unwrap());
+        keypair
+            .public
+            .verify_strict(digest.as_bytes(), &detached)
+            .expect("detached signature is valid");
+        assert!(signature(&parsed[..71]).is_err());
+    }
+
+    #[test]
+    fn metadata() {
+        let keypair = Keypair::generate(&mut rand::rngs::OsRng {});
+        let mut buf = b"#meta: owner alice\n  #meta:ticket 42\n# not metadata\n"[..].to_owned();
+        buf.extend_from_slice(BUF);
+        let parsed: Vec<u8> = parse::<&[u8], AccountId>(
+            &mut &*buf,
+            Ss58AddressFormat::SubstrateAccount,
+            &keypair.public,
+            &(&keypair.secret).into(),
+            NONCE,
+            DEFAULT_MAX_ENTRIES,
+        )
+        .expect("no error");
+        let inspected = inspect::<&[u8], AccountId>(
+            &mut &*parsed,
+            Ss58AddressFormat::SubstrateAccount,
+            &keypair.public,
+        )
+        .expect("no error");
+        assert_eq!(inspected[..3], [
+            "Nonce: 0\n",
+            "#meta: owner alice",
+            "#meta: ticket 42"
+        ]);
+        assert_eq!(inspected.len(), 12);
+        check_signed(
+            &*buf,
+            &parsed,
+            Ss58AddressFormat::SubstrateAccount,
+            &keypair.public,
+            NONCE,
+        )
+        .unwrap();
+        assert!(check_signed(
+            BUF,
+            &parsed,
+            Ss58AddressFormat::SubstrateAccount,
+            &keypair.public,
+            NONCE,
+        )
+        .is_err());
+        assert!(check_signed(
+            &*buf,
+            &parsed,
+            Ss58AddressFormat::SubstrateAccount,
+            &keypair.public,
+            NONCE + 1,
+        )
+        .is_err());
+        let without_metadata = parse::<&[u8], AccountId>(
+            &mut BUF,
+            Ss58AddressFormat::SubstrateAccount,
+            &keypair.public,
+            &(&keypair.secret).into(),
+            NONCE,
+            DEFAULT_MAX_ENTRIES,
+        )
+        .expect("no error");
+        let device = &without_metadata[..without_metadata.len() - VERSION_TRAILER_LEN];
+        assert_eq!(device_part(&parsed).unwrap(), device);
+        assert_eq!(device_part(&without_metadata).unwrap(), device);
+        let mut tampered = parsed.clone();
+        // In the metadata signature, before the version trailer
+        let position = tampered.len() - VERSION_TRAILER_LEN - 6;
+        tampered[position] ^= 1;
+        assert!(inspect::<&[u8], AccountId>(
+            &mut &*tampered,
+            Ss58AddressFormat::SubstrateAccount,
+            &keypair.public,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn merges_lists() {
+        let first =
+            &b"#meta: region eu\n5DArCreQ9Yk2HaGvxcRHS35qky3eXBD5BprPZQvbiJBfFY6Y\n; comment\n"[..];
+        let second = &b"#meta: region eu\n  5DArCreQ9Yk2HaGvxcRHS35qky3eXBD5BprPZQvbiJBfFY6Y\n5GQvjFcJBCGTFeb2hvtQ9yRfbDNQajLJbW1yzgCra5uUTLvn\n"[..];
+        let (merged, duplicates) = merge(
+            vec![("first".to_owned(), first), ("second".to_owned(), second)],
+            Ss58AddressFormat::SubstrateAccount,
+        )
+        .unwrap();
+        // `format_strings` would break the line inside an escape
+        #[rustfmt::skip]
+        let expected = "#meta: region eu\n5DArCreQ9Yk2HaGvxcRHS35qky3eXBD5BprPZQvbiJBfFY6Y\n\
+                        5GQvjFcJBCGTFeb2hvtQ9yRfbDNQajLJbW1yzgCra5uUTLvn\n";
+        assert_eq!(merged, expected);
+        assert_eq!(duplicates, 1);
+        let error = merge(
+            vec![("second".to_owned(), second)],
+            Ss58AddressFormat::KusamaAccount,
+        )
+        .unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("second: invalid network on line 1"));
+    }
+
+    #[test]
+    fn max_entries() {
+        let keypair = Keypair::generate(&mut rand::rngs::OsRng {});
+        let sign = |max_entries| {
+            parse::<&[u8], AccountId>(
+                &mut BUF,
+                Ss58AddressFormat::SubstrateAccount,
+                &keypair.public,
+                &(&keypair.secret).into(),
+                NONCE,
+                max_entries,
+            )
+        };
+        assert!(sign(9).is_ok());
+        assert_eq!(
+            sign(8).unwrap_err().to_string(),
+            "too many addresses: line 13 is address 9, but the maximum is 8"
+        );
+    }
+
+    #[test]
+    fn verify_only() {
+        let keypair = Keypair::generate(&mut rand::rngs::OsRng {});
+        let mut buf = b"#meta: owner alice\n"[..].to_owned();
+        buf.extend_from_slice(BUF);
+        let parsed: Vec<u8> = parse::<&[u8], AccountId>(
+            &mut &*buf,
+            Ss58AddressFormat::SubstrateAccount,
+            &keypair.public,
+            &(&keypair.secret).into(),
+            7,
+            DEFAULT_MAX_ENTRIES,
+        )
+        .expect("no error");
+        assert_eq!(
+            verify(&*parsed, &keypair.public).expect("no error"),
+            Summary {
+                nonce: 7,
+                count: 9,
+                metadata: Some("owner alice".to_owned()),
+            }
+        );
+        let mut tampered = parsed.clone();
+        tampered[100] ^= 1;
+        assert!(verify(&*tampered, &keypair.public).is_err());
+    }
+
+    #[test]
+    fn checks_version() {
+        let keypair = Keypair::generate(&mut rand::rngs::OsRng {});
+        let parsed: Vec<u8> = parse::<&[u8], AccountId>(
+            &mut BUF,
+            Ss58AddressFormat::SubstrateAccount,
+            &keypair.public,
+            &(&keypair.secret).into(),
+            NONCE,
+            DEFAULT_MAX_ENTRIES,
+        )
+        .expect("no error");
+        let len = parsed.len();
+        let trailer = len - VERSION_TRAILER_LEN;
+        assert_eq!(&parsed[trailer..trailer + 8], b"VERS\x01\0\0\0");
+        // Files written before the version trailer are version 1
+        let unversioned = &parsed[..trailer];
+        assert_eq!(verify(unversioned, &keypair.public).unwrap().count, 9);
+        let mut future = parsed.clone();
+        future[trailer + 4] = 2;
+        assert_eq!(
+            verify(&*future, &keypair.public).unwrap_err().to_string(),
+            "unsupported allowlist format version 2"
+        );
+        assert_eq!(
+            device_part(&future).unwrap_err().to_string(),
+            "unsupported allowlist format version 2"
+        );
+        // The version is signed
+        let mut forged = parsed.clone();
+        forged[len - 1] ^= 1;
+        assert_eq!(
+            verify(&*forged, &keypair.public).unwrap_err().to_string(),
+            "Allowlist version forged!"
+        );
+    }
+
+    #[test]
+    fn decodes_hex() {
+        assert_eq!(from_hex(b"0x00ff10\n").unwrap(), vec![0, 0xff, 0x10]);
+        assert_eq!(from_hex(b"  00FF10").unwrap(), vec![0, 0xff, 0x10]);
+        assert!(from_hex(b"00f").is_err());
+        assert!(from_hex(b"\x00\x01").is_err());
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "called `Result::unwrap()` on an `Err` value: Custom { kind: InvalidData, \
+                    error: \"invalid network on line 1: Network mismatch: address \
+                    5DArCreQ9Yk2HaGvxcRHS35qky3eXBD5BprPZQvbiJBfFY6Y is for network substrate, \
+                    but you asked to use network polkadot\" }"
+    )]
+    fn rejects_bad_file() {
+        let keypair = Keypair::generate(&mut rand::rngs::OsRng {});
+        parse::<&[u8], AccountId>(
+            &mut BUF,
+            Ss58AddressFormat::PolkadotAccount,
+            &keypair.public,
+            &(&keypair.secret).into(),
+            NONCE,
+            DEFAULT_MAX_ENTRIES,
+        )
+        .unwrap();
+    }
+}